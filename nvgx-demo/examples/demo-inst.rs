@@ -339,9 +339,10 @@ impl ShapeInstance {
         };
     }
 
-    fn update(&mut self, dt: f32) -> Transform {
+    fn update(&mut self, dt: f32) -> Instance {
         self.rotation = self.rotation + dt * self.speed;
-        return Transform::rotate(self.rotation) * Transform::translate(self.pos.0, self.pos.1);
+        let xform = Transform::rotate(self.rotation) * Transform::translate(self.pos.0, self.pos.1);
+        return Instance::new(xform);
     }
 }
 
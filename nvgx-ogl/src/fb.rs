@@ -31,7 +31,7 @@ impl RenderFrameBufferDevice for Renderer {
         return nvgx::TextureType::RGBA;
     }
 
-    fn create_fb(&mut self, width: u32, height: u32, image: ImageId) -> anyhow::Result<Self::FB> {
+    fn create_fb(&mut self, width: u32, height: u32, image: ImageId) -> nvgx::Result<Self::FB> {
         let mut fbo = 0;
         let mut rbo = 0;
         let texture = self.textures.get(image).unwrap().tex;
@@ -75,7 +75,7 @@ impl RenderFrameBufferDevice for Renderer {
 
                 gl::DeleteFramebuffers(1, &mut fbo);
                 gl::DeleteRenderbuffers(1, &mut rbo);
-                return Err(anyhow!("Failed to create framebuffer"));
+                return Err(nvgx::Error::backend("Failed to create framebuffer"));
             }
 
             gl::BindFramebuffer(gl::FRAMEBUFFER, default_fbo.fbo as gl::types::GLuint);
@@ -90,7 +90,7 @@ impl RenderFrameBufferDevice for Renderer {
         }
     }
 
-    fn delete_fb(&mut self, mut fb: Self::FB) -> anyhow::Result<()> {
+    fn delete_fb(&mut self, mut fb: Self::FB) -> nvgx::Result<()> {
         unsafe {
             gl::DeleteFramebuffers(1, &mut fb.fbo);
             gl::DeleteRenderbuffers(1, &mut fb.rbo);
@@ -98,17 +98,19 @@ impl RenderFrameBufferDevice for Renderer {
         return Ok(());
     }
 
-    fn bind(&mut self, fb: &Self::FB) -> anyhow::Result<()> {
+    fn bind(&mut self, fb: &Self::FB) -> nvgx::Result<()> {
         unsafe {
             gl::BindFramebuffer(gl::FRAMEBUFFER, fb.fbo);
         }
+        self.fb_bound = true;
         Ok(())
     }
 
-    fn unbind(&mut self) -> anyhow::Result<()> {
+    fn unbind(&mut self) -> nvgx::Result<()> {
         unsafe {
             gl::BindFramebuffer(gl::FRAMEBUFFER, self.default_fbo.fbo as gl::types::GLuint);
         }
+        self.fb_bound = false;
         Ok(())
     }
 }
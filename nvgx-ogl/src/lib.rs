@@ -1,7 +1,3 @@
-#[macro_use]
-#[allow(unused)]
-extern crate anyhow;
-
 pub mod fb;
 
 use std::{ops::Range, sync::Arc};
@@ -12,11 +8,16 @@ use nvgx::{
 };
 use renderer::GLArrayBuffer;
 use slab::Slab;
+use timer::GpuTimer;
 
 mod renderer;
+mod timer;
 
 pub struct RenderConfig {
     antialias: bool,
+    partial_redraw: bool,
+    gpu_timing: bool,
+    batching: bool,
 }
 
 impl RenderConfig {
@@ -24,11 +25,38 @@ impl RenderConfig {
         self.antialias = antialias;
         self
     }
+
+    /// Allows frames to redraw only their damaged area. The default
+    /// framebuffer must keep its content between frames, e.g. with
+    /// `EGL_BUFFER_PRESERVED` or single buffering.
+    pub fn partial_redraw(mut self, partial_redraw: bool) -> Self {
+        self.partial_redraw = partial_redraw;
+        self
+    }
+
+    /// Measures the GPU time of frames with timer queries, where OpenGL 3.3
+    /// or `ARB_timer_query` is available
+    pub fn gpu_timing(mut self, gpu_timing: bool) -> Self {
+        self.gpu_timing = gpu_timing;
+        self
+    }
+
+    /// Merges consecutive convex fills and strokes with the same paint,
+    /// scissor and blend into single draws
+    pub fn batching(mut self, batching: bool) -> Self {
+        self.batching = batching;
+        self
+    }
 }
 
 impl Default for RenderConfig {
     fn default() -> Self {
-        Self { antialias: true }
+        Self {
+            antialias: true,
+            partial_redraw: false,
+            gpu_timing: false,
+            batching: true,
+        }
     }
 }
 
@@ -52,7 +80,7 @@ impl Drop for Shader {
 }
 
 impl Shader {
-    fn load() -> anyhow::Result<Shader> {
+    fn load() -> nvgx::Result<Shader> {
         unsafe {
             let mut status: gl::types::GLint = std::mem::zeroed();
             let prog = gl::CreateProgram();
@@ -91,10 +119,18 @@ impl Shader {
             gl::AttachShader(prog, vert);
             gl::AttachShader(prog, frag);
 
-            let name_vertex = std::ffi::CString::new("vertex").unwrap();
-            let name_tcoord = std::ffi::CString::new("tcoord").unwrap();
-            gl::BindAttribLocation(prog, 0, name_vertex.as_ptr() as *const i8);
-            gl::BindAttribLocation(prog, 1, name_tcoord.as_ptr() as *const i8);
+            let attribs = [
+                "vertex",
+                "tcoord",
+                "model_matrix_0",
+                "model_matrix_1",
+                "inst_color",
+                "inst_data",
+            ];
+            for (index, name) in attribs.iter().enumerate() {
+                let name = std::ffi::CString::new(*name).unwrap();
+                gl::BindAttribLocation(prog, index as u32, name.as_ptr() as *const i8);
+            }
 
             gl::LinkProgram(prog);
             gl::GetProgramiv(prog, gl::LINK_STATUS, &mut status);
@@ -123,6 +159,8 @@ enum ShaderType {
     FillImage,
     Simple,
     Image,
+    LcdImage,
+    Debug,
 }
 
 #[derive(PartialEq, Eq)]
@@ -131,10 +169,13 @@ enum CallType {
     ConvexFill,
     Stroke,
     Triangles,
+    /// Merged convex fills and strokes, drawn from `Renderer::batch_buf`
+    Batch,
     #[cfg(feature = "wirelines")]
     Lines,
 }
 
+#[derive(PartialEq, Eq)]
 struct Blend {
     src_rgb: gl::types::GLenum,
     dst_rgb: gl::types::GLenum,
@@ -162,6 +203,7 @@ struct Call {
     triangle: GLSlice,
     uniform_offset: usize,
     blend_func: Blend,
+    blend_color: Option<Color>,
 }
 
 struct Texture {
@@ -236,15 +278,26 @@ pub struct Renderer {
     shader: Shader,
     textures: Slab<Texture>,
     view: Extent,
+    device_pixel_ratio: f32,
     vert_buf: GLArrayBuffer,
     inst_buf: GLArrayBuffer,
+    /// Vertex indices of batched calls
+    batch_buf: GLArrayBuffer,
     frag_buf: gl::types::GLuint,
     frag_size: usize,
     calls: Vec<Call>,
     paths: Vec<GLPath>,
     uniforms: Vec<u8>,
+    batch_indices: Vec<u32>,
     config: RenderConfig,
     default_fbo: DefaultFBO,
+    /// Scissor box of partial frames, in framebuffer coordinates
+    damage: Option<[i32; 4]>,
+    fb_bound: bool,
+    timer: Option<GpuTimer>,
+    debug_view: DebugView,
+    /// Draw calls of the latest flushed frame
+    flushed_calls: usize,
 }
 
 impl Drop for Renderer {
@@ -256,13 +309,14 @@ impl Drop for Renderer {
 }
 
 impl Renderer {
-    pub fn create(config: RenderConfig) -> anyhow::Result<Renderer> {
+    pub fn create(config: RenderConfig) -> nvgx::Result<Renderer> {
         unsafe {
             let shader = Shader::load()?;
 
             let vert_buf = GLArrayBuffer::new(BufferUsage::Vertex);
             let inst_buf = GLArrayBuffer::new(BufferUsage::Instance);
-            inst_buf.update_data(bytemuck::bytes_of(&Transform::identity()));
+            inst_buf.update_data(bytemuck::bytes_of(&Instance::default()));
+            let batch_buf = GLArrayBuffer::new(BufferUsage::Instance);
 
             gl::UniformBlockBinding(shader.prog, shader.loc_frag, 0);
             let mut frag_buf: gl::types::GLuint = std::mem::zeroed();
@@ -285,22 +339,39 @@ impl Renderer {
                 shader,
                 textures: Default::default(),
                 view: Default::default(),
+                device_pixel_ratio: 1.0,
                 vert_buf,
                 inst_buf,
+                batch_buf,
                 frag_buf,
                 frag_size,
                 calls: Default::default(),
                 paths: Default::default(),
                 uniforms: Default::default(),
-                config,
+                batch_indices: Default::default(),
                 default_fbo: DefaultFBO {
                     fbo: default_fbo,
                     rbo: default_rbo,
                 },
+                damage: None,
+                fb_bound: false,
+                timer: config.gpu_timing.then(GpuTimer::new).flatten(),
+                debug_view: DebugView::None,
+                flushed_calls: 0,
+                config,
             })
         }
     }
 
+    /// Draws the next frames in `view` instead of their regular look
+    pub fn set_debug_view(&mut self, view: DebugView) {
+        self.debug_view = view;
+    }
+
+    pub fn debug_view(&self) -> DebugView {
+        self.debug_view
+    }
+
     fn set_uniforms(&self, offset: usize, img: Option<usize>) {
         unsafe {
             gl::BindBufferRange(
@@ -446,6 +517,106 @@ impl Renderer {
         }
     }
 
+    #[inline]
+    unsafe fn do_batch(&self, call: &Call, inst_count: i32) {
+        self.set_uniforms(call.uniform_offset, call.image);
+        self.batch_buf.bind_indices();
+        unsafe {
+            gl::DrawElementsInstanced(
+                gl::TRIANGLES,
+                call.triangle.count as i32,
+                gl::UNSIGNED_INT,
+                (call.triangle.offset as usize * std::mem::size_of::<u32>()) as *const _,
+                inst_count,
+            );
+        }
+    }
+
+    /// Draws the fill and stroke geometry of `call` in the color of the debug
+    /// view, only the fringes of fills for [`DebugView::Fringe`]
+    unsafe fn do_debug_overlay(&self, call: &Call, inst_count: i32, uniform_offset: usize) {
+        let wireframe = self.debug_view == DebugView::Wireframe;
+        match call.call_type {
+            CallType::Fill(_) | CallType::ConvexFill | CallType::Stroke | CallType::Batch => {}
+            _ => return,
+        }
+        let paths = &self.paths[call.path_range.clone()];
+        self.set_uniforms(uniform_offset, None);
+        for path in paths {
+            unsafe {
+                if wireframe && path.fill.count > 0 {
+                    gl::DrawArraysInstanced(
+                        gl::TRIANGLE_FAN,
+                        path.fill.offset as i32,
+                        path.fill.count as i32,
+                        inst_count,
+                    );
+                }
+                // strokes have no fill, their strips are no fringes
+                if path.stroke.count > 0 && (wireframe || path.fill.count > 0) {
+                    gl::DrawArraysInstanced(
+                        gl::TRIANGLE_STRIP,
+                        path.stroke.offset as i32,
+                        path.stroke.count as i32,
+                        inst_count,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Merges runs of convex fills and strokes of the frame's vertices that
+    /// share image, blend and uniforms into single indexed draws
+    fn batch_calls(&mut self) {
+        let calls = std::mem::take(&mut self.calls);
+        let mut batched: Vec<Call> = Vec::with_capacity(calls.len());
+        for call in calls {
+            if let Some(last) = batched.last_mut() {
+                if self.batchable(last, &call) {
+                    if last.call_type != CallType::Batch {
+                        let offset = self.batch_indices.len() as u32;
+                        append_indices(
+                            &self.paths[last.path_range.clone()],
+                            &mut self.batch_indices,
+                        );
+                        last.call_type = CallType::Batch;
+                        last.triangle = GLSlice { offset, count: 0 };
+                    }
+                    append_indices(
+                        &self.paths[call.path_range.clone()],
+                        &mut self.batch_indices,
+                    );
+                    last.path_range.end = call.path_range.end;
+                    last.triangle.count = self.batch_indices.len() as u32 - last.triangle.offset;
+                    continue;
+                }
+            }
+            batched.push(call);
+        }
+        self.calls = batched;
+    }
+
+    fn batchable(&self, last: &Call, call: &Call) -> bool {
+        let uniforms = |offset: usize| {
+            let start = offset * self.frag_size;
+            &self.uniforms[start..start + std::mem::size_of::<FragUniforms>()]
+        };
+        matches!(
+            last.call_type,
+            CallType::ConvexFill | CallType::Stroke | CallType::Batch
+        ) && matches!(call.call_type, CallType::ConvexFill | CallType::Stroke)
+            && last.vert_buff.is_none()
+            && call.vert_buff.is_none()
+            && last.instances.is_none()
+            && call.instances.is_none()
+            && last.path_range.end == call.path_range.start
+            && last.image == call.image
+            && last.blend_func == call.blend_func
+            && last.blend_color.is_none()
+            && call.blend_color.is_none()
+            && uniforms(last.uniform_offset) == uniforms(call.uniform_offset)
+    }
+
     fn convert_paint(
         &self,
         paint: &PaintPattern,
@@ -545,7 +716,15 @@ impl Renderer {
     }
 }
 
-fn shader_error(shader: gl::types::GLuint, filename: &str) -> anyhow::Error {
+/// Indices of the fans and strips of `paths` in drawing order
+fn append_indices(paths: &[GLPath], indices: &mut Vec<u32>) {
+    for path in paths {
+        utils::triangle_fan_indices(path.fill.offset, path.fill.count, indices);
+        utils::triangle_strip_indices(path.stroke.offset, path.stroke.count, indices);
+    }
+}
+
+fn shader_error(shader: gl::types::GLuint, filename: &str) -> nvgx::Error {
     unsafe {
         let mut data: [gl::types::GLchar; 512 + 1] = std::mem::zeroed();
         let mut len: gl::types::GLsizei = std::mem::zeroed();
@@ -555,15 +734,15 @@ fn shader_error(shader: gl::types::GLuint, filename: &str) -> anyhow::Error {
         }
         data[len as usize] = 0;
         let err_msg = std::ffi::CStr::from_ptr(data.as_ptr());
-        anyhow!(
+        nvgx::Error::backend(format!(
             "failed to compile shader: {}: {}",
             filename,
             err_msg.to_string_lossy()
-        )
+        ))
     }
 }
 
-fn program_error(prog: gl::types::GLuint) -> anyhow::Error {
+fn program_error(prog: gl::types::GLuint) -> nvgx::Error {
     unsafe {
         let mut data: [gl::types::GLchar; 512 + 1] = std::mem::zeroed();
         let mut len: gl::types::GLsizei = std::mem::zeroed();
@@ -573,7 +752,10 @@ fn program_error(prog: gl::types::GLuint) -> anyhow::Error {
         }
         data[len as usize] = 0;
         let err_msg = std::ffi::CStr::from_ptr(data.as_ptr());
-        anyhow!("failed to link program: {}", err_msg.to_string_lossy())
+        nvgx::Error::backend(format!(
+            "failed to link program: {}",
+            err_msg.to_string_lossy()
+        ))
     }
 }
 
@@ -3,7 +3,7 @@ use std::ffi::c_void;
 use std::ops::Range;
 use std::sync::Arc;
 
-use super::{Call, CallType, FragUniforms, GLPath, ShaderType, Texture};
+use super::{Blend, Call, CallType, FragUniforms, GLPath, ShaderType, Texture};
 use super::{GLSlice, Renderer};
 use nvgx::*;
 
@@ -12,6 +12,7 @@ pub struct GLArrayBuffer {
     vbo: gl::types::GLuint,
     vao: gl::types::GLuint,
     attached_inst: Cell<(gl::types::GLuint, i32)>,
+    size: Cell<usize>,
 }
 
 impl GLArrayBuffer {
@@ -28,6 +29,7 @@ impl GLArrayBuffer {
                 vbo,
                 vao,
                 attached_inst: Cell::new(std::mem::zeroed()),
+                size: Cell::new(0),
             };
         }
     }
@@ -67,7 +69,7 @@ impl GLArrayBuffer {
         unsafe {
             self.attached_inst.set((inst_vbo, offset));
             let elem_size = std::mem::size_of::<f32>();
-            let stride = elem_size as i32 * 6;
+            let stride = std::mem::size_of::<Instance>() as i32;
             let offset_ptr = offset * stride;
             gl::BindVertexArray(self.vao);
             gl::BindBuffer(gl::ARRAY_BUFFER, inst_vbo);
@@ -87,10 +89,34 @@ impl GLArrayBuffer {
                 stride,
                 (4 * elem_size + offset_ptr as usize) as *const c_void,
             );
-            gl::EnableVertexAttribArray(2);
-            gl::EnableVertexAttribArray(3);
-            gl::VertexAttribDivisor(2, 1);
-            gl::VertexAttribDivisor(3, 1);
+            // color, then paint offset and user data
+            gl::VertexAttribPointer(
+                4,
+                4,
+                gl::FLOAT,
+                gl::FALSE,
+                stride,
+                (6 * elem_size + offset_ptr as usize) as *const c_void,
+            );
+            gl::VertexAttribPointer(
+                5,
+                4,
+                gl::FLOAT,
+                gl::FALSE,
+                stride,
+                (10 * elem_size + offset_ptr as usize) as *const c_void,
+            );
+            for index in 2..6 {
+                gl::EnableVertexAttribArray(index);
+                gl::VertexAttribDivisor(index, 1);
+            }
+        }
+    }
+
+    /// Draws indexed by this buffer, with the vertex array bound
+    pub(crate) fn bind_indices(&self) {
+        unsafe {
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.vbo);
         }
     }
 
@@ -104,6 +130,28 @@ impl GLArrayBuffer {
                 gl::STREAM_DRAW,
             );
         }
+        self.size.set(dat.len());
+    }
+
+    pub fn update_data_range(&self, offset: usize, dat: &[u8]) -> nvgx::Result<()> {
+        let size = self.size.get();
+        if offset + dat.len() > size {
+            return Err(Error::BufferRange {
+                offset,
+                len: dat.len(),
+                size,
+            });
+        }
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            gl::BufferSubData(
+                gl::ARRAY_BUFFER,
+                offset as isize,
+                dat.len() as isize,
+                dat.as_ptr() as *const c_void,
+            );
+        }
+        Ok(())
     }
 }
 
@@ -116,6 +164,27 @@ impl Drop for GLArrayBuffer {
     }
 }
 
+impl Renderer {
+    /// Binds the vertices and instances of `call`, returns the instance count
+    unsafe fn bind_buffers(&self, call: &Call) -> i32 {
+        let (inst_vbo, inst_slice) = call.instances.as_ref().map(|v| (v.0.vbo, v.1)).unwrap_or((
+            self.inst_buf.vbo,
+            GLSlice {
+                offset: 0,
+                count: 1,
+            },
+        ));
+
+        let vertex_buffer = call
+            .vert_buff
+            .as_ref()
+            .map(|v| v.as_ref())
+            .unwrap_or(&self.vert_buf);
+        vertex_buffer.set_inst_binding(inst_vbo, inst_slice.offset as i32);
+        inst_slice.count as i32
+    }
+}
+
 impl nvgx::RendererDevice for Renderer {
     type VertexBuffer = Arc<GLArrayBuffer>;
 
@@ -123,7 +192,7 @@ impl nvgx::RendererDevice for Renderer {
         self.config.antialias
     }
 
-    fn resize(&mut self, _width: u32, _height: u32) -> anyhow::Result<()> {
+    fn resize(&mut self, _width: u32, _height: u32) -> nvgx::Result<()> {
         Ok(())
     }
 
@@ -131,7 +200,7 @@ impl nvgx::RendererDevice for Renderer {
         &mut self,
         _buffer_size: usize,
         usage: BufferUsage,
-    ) -> anyhow::Result<Self::VertexBuffer> {
+    ) -> nvgx::Result<Self::VertexBuffer> {
         return Ok(Arc::new(GLArrayBuffer::new(usage)));
     }
 
@@ -139,7 +208,7 @@ impl nvgx::RendererDevice for Renderer {
         &mut self,
         buffer: Option<&Self::VertexBuffer>,
         dat: &[u8],
-    ) -> anyhow::Result<()> {
+    ) -> nvgx::Result<()> {
         if let Some(buffer) = buffer {
             buffer.update_data(dat);
         } else {
@@ -148,6 +217,19 @@ impl nvgx::RendererDevice for Renderer {
         Ok(())
     }
 
+    fn update_vertex_buffer_range(
+        &mut self,
+        buffer: &Self::VertexBuffer,
+        data: &[u8],
+        range: Range<usize>,
+    ) -> nvgx::Result<()> {
+        buffer.update_data_range(range.start, &data[range])
+    }
+
+    fn vertex_buffer_size(&self, buffer: &Self::VertexBuffer) -> Option<usize> {
+        Some(buffer.size.get())
+    }
+
     fn create_texture(
         &mut self,
         texture_type: TextureType,
@@ -155,7 +237,15 @@ impl nvgx::RendererDevice for Renderer {
         height: u32,
         flags: ImageFlags,
         data: Option<&[u8]>,
-    ) -> anyhow::Result<ImageId> {
+    ) -> nvgx::Result<ImageId> {
+        let max = unsafe {
+            let mut max = 0;
+            gl::GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max);
+            max as u32
+        };
+        if width > max || height > max {
+            return Err(Error::TextureTooLarge { width, height, max });
+        }
         let tex = unsafe {
             let mut tex: gl::types::GLuint = std::mem::zeroed();
             gl::GenTextures(1, &mut tex);
@@ -273,13 +363,13 @@ impl nvgx::RendererDevice for Renderer {
         Ok(id)
     }
 
-    fn delete_texture(&mut self, img: ImageId) -> anyhow::Result<()> {
+    fn delete_texture(&mut self, img: ImageId) -> nvgx::Result<()> {
         if let Some(texture) = self.textures.get(img) {
             unsafe { gl::DeleteTextures(1, &texture.tex) }
             self.textures.remove(img);
             Ok(())
         } else {
-            bail!("texture '{}' not found", img);
+            Err(Error::InvalidImage(img))
         }
     }
 
@@ -291,7 +381,7 @@ impl nvgx::RendererDevice for Renderer {
         width: u32,
         height: u32,
         data: &[u8],
-    ) -> anyhow::Result<()> {
+    ) -> nvgx::Result<()> {
         if let Some(texture) = self.textures.get(img) {
             unsafe {
                 gl::BindTexture(gl::TEXTURE_2D, texture.tex);
@@ -338,32 +428,82 @@ impl nvgx::RendererDevice for Renderer {
             }
             Ok(())
         } else {
-            bail!("texture '{}' not found", img);
+            Err(Error::InvalidImage(img))
         }
     }
 
-    fn texture_size(&self, img: ImageId) -> anyhow::Result<(u32, u32)> {
+    fn texture_size(&self, img: ImageId) -> nvgx::Result<(u32, u32)> {
         if let Some(texture) = self.textures.get(img) {
             Ok((texture.width, texture.height))
         } else {
-            bail!("texture '{}' not found", img);
+            Err(Error::InvalidImage(img))
         }
     }
 
-    fn viewport(&mut self, extent: Extent, _device_pixel_ratio: f32) -> anyhow::Result<()> {
+    fn viewport(&mut self, extent: Extent, device_pixel_ratio: f32) -> nvgx::Result<()> {
         self.view = extent;
+        self.device_pixel_ratio = device_pixel_ratio;
         Ok(())
     }
 
-    fn cancel(&mut self) -> anyhow::Result<()> {
+    fn damage(&mut self, area: Option<Bounds>) -> nvgx::Result<bool> {
+        self.damage = match area {
+            Some(area) if self.config.partial_redraw => {
+                // window coordinates to framebuffer pixels, counted from the bottom
+                let ratio = self.device_pixel_ratio;
+                let x = (area.min.x * ratio).floor() as i32;
+                let y = ((self.view.height - area.max.y) * ratio).floor() as i32;
+                let width = (area.max.x * ratio).ceil() as i32 - x;
+                let height = ((self.view.height - area.min.y) * ratio).ceil() as i32 - y;
+                Some([x, y, width, height])
+            }
+            _ => None,
+        };
+        Ok(self.damage.is_some())
+    }
+
+    fn cancel(&mut self) -> nvgx::Result<()> {
         self.paths.clear();
         self.calls.clear();
         self.uniforms.clear();
+        self.batch_indices.clear();
         Ok(())
     }
 
-    fn flush(&mut self) -> anyhow::Result<()> {
+    fn flush(&mut self) -> nvgx::Result<()> {
+        self.flushed_calls = 0;
         if !self.calls.is_empty() {
+            if let Some(timer) = &mut self.timer {
+                timer.begin();
+            }
+            if self.config.batching {
+                self.batch_calls();
+                if !self.batch_indices.is_empty() {
+                    self.batch_buf
+                        .update_data(bytemuck::cast_slice(&self.batch_indices));
+                }
+            }
+            self.flushed_calls = self.calls.len();
+            let debug_offset = self.get_uniform_offset();
+            if self.debug_view != DebugView::None {
+                let view = self.debug_view;
+                // twice, as fills read a second uniform for their cover pass
+                for _ in 0..2 {
+                    self.append_uniforms(FragUniforms {
+                        inner_color: view.color(),
+                        stroke_thr: -1.0,
+                        type_: ShaderType::Debug as i32,
+                        ..FragUniforms::default()
+                    });
+                }
+                if view == DebugView::Overdraw {
+                    for call in &mut self.calls {
+                        call.uniform_offset = debug_offset;
+                        call.blend_func = view.blend().into();
+                        call.blend_color = None;
+                    }
+                }
+            }
             unsafe {
                 gl::UseProgram(self.shader.prog);
 
@@ -372,7 +512,13 @@ impl nvgx::RendererDevice for Renderer {
                 gl::FrontFace(gl::CCW);
                 gl::Enable(gl::BLEND);
                 gl::Disable(gl::DEPTH_TEST);
-                gl::Disable(gl::SCISSOR_TEST);
+                match self.damage.filter(|_| !self.fb_bound) {
+                    Some([x, y, width, height]) => {
+                        gl::Enable(gl::SCISSOR_TEST);
+                        gl::Scissor(x, y, width, height);
+                    }
+                    None => gl::Disable(gl::SCISSOR_TEST),
+                }
                 gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
                 gl::StencilMask(0xffffffff);
                 gl::StencilOp(gl::KEEP, gl::KEEP, gl::KEEP);
@@ -398,21 +544,7 @@ impl nvgx::RendererDevice for Renderer {
                 gl::BindBuffer(gl::UNIFORM_BUFFER, self.frag_buf);
 
                 for call in &self.calls {
-                    let (inst_vbo, inst_slice) =
-                        call.instances.as_ref().map(|v| (v.0.vbo, v.1)).unwrap_or((
-                            self.inst_buf.vbo,
-                            GLSlice {
-                                offset: 0,
-                                count: 1,
-                            },
-                        ));
-
-                    let vertex_buffer = call
-                        .vert_buff
-                        .as_ref()
-                        .map(|v| v.as_ref())
-                        .unwrap_or(&self.vert_buf);
-                    vertex_buffer.set_inst_binding(inst_vbo, inst_slice.offset as i32);
+                    let inst_count = self.bind_buffers(call);
 
                     let blend = &call.blend_func;
 
@@ -422,32 +554,67 @@ impl nvgx::RendererDevice for Renderer {
                         blend.src_alpha,
                         blend.dst_alpha,
                     );
+                    if let Some(color) = call.blend_color {
+                        gl::BlendColor(color.r, color.g, color.b, color.a);
+                    }
 
-                    let inst_count = inst_slice.count as i32;
                     match call.call_type {
                         CallType::Fill(ft) => self.do_fill(&call, ft, inst_count),
                         CallType::ConvexFill => self.do_convex_fill(&call, inst_count),
                         CallType::Stroke => self.do_stroke(&call, inst_count),
                         CallType::Triangles => self.do_triangles(&call, inst_count),
+                        CallType::Batch => self.do_batch(call, inst_count),
                         #[cfg(feature = "wirelines")]
                         CallType::Lines => self.do_lines(&call, inst_count),
                     }
                 }
 
+                if matches!(self.debug_view, DebugView::Wireframe | DebugView::Fringe) {
+                    let blend: Blend = self.debug_view.blend().into();
+                    gl::BlendFuncSeparate(
+                        blend.src_rgb,
+                        blend.dst_rgb,
+                        blend.src_alpha,
+                        blend.dst_alpha,
+                    );
+                    if self.debug_view == DebugView::Wireframe {
+                        gl::PolygonMode(gl::FRONT_AND_BACK, gl::LINE);
+                        gl::Disable(gl::CULL_FACE);
+                    }
+                    for call in &self.calls {
+                        let inst_count = self.bind_buffers(call);
+                        self.do_debug_overlay(call, inst_count, debug_offset);
+                    }
+                    gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+                }
+
                 gl::BindVertexArray(0);
                 gl::Disable(gl::CULL_FACE);
+                gl::Disable(gl::SCISSOR_TEST);
                 gl::BindBuffer(gl::ARRAY_BUFFER, 0);
                 gl::UseProgram(0);
                 gl::BindTexture(gl::TEXTURE_2D, 0);
             }
+            if let Some(timer) = &mut self.timer {
+                timer.end();
+            }
         }
 
         self.paths.clear();
         self.calls.clear();
         self.uniforms.clear();
+        self.batch_indices.clear();
         Ok(())
     }
 
+    fn gpu_time(&mut self) -> Option<std::time::Duration> {
+        self.timer.as_mut().and_then(|timer| timer.poll())
+    }
+
+    fn flushed_calls(&self) -> Option<usize> {
+        Some(self.flushed_calls)
+    }
+
     fn fill(
         &mut self,
         vertex_buffer: Option<Self::VertexBuffer>,
@@ -459,7 +626,7 @@ impl nvgx::RendererDevice for Renderer {
         fringe: f32,
         bounds_offset: Option<usize>,
         paths: &[nvgx::PathSlice],
-    ) -> anyhow::Result<()> {
+    ) -> nvgx::Result<()> {
         let path_offset = self.paths.len();
 
         for path in paths {
@@ -492,6 +659,7 @@ impl nvgx::RendererDevice for Renderer {
             },
             uniform_offset: self.get_uniform_offset(),
             blend_func: composite_operation.into(),
+            blend_color: None,
             vert_buff: vertex_buffer,
             instances: instances.map(|(insts, r)| (insts, r.into())),
         };
@@ -522,7 +690,7 @@ impl nvgx::RendererDevice for Renderer {
         fringe: f32,
         stroke_width: f32,
         paths: &[nvgx::PathSlice],
-    ) -> anyhow::Result<()> {
+    ) -> nvgx::Result<()> {
         let path_offset = self.paths.len();
 
         for path in paths {
@@ -540,6 +708,7 @@ impl nvgx::RendererDevice for Renderer {
             triangle: Default::default(),
             uniform_offset: self.get_uniform_offset(),
             blend_func: composite_operation.into(),
+            blend_color: None,
             vert_buff: vertex_buffer,
             instances: instances.map(|(insts, r)| (insts, r.into())),
         };
@@ -557,7 +726,7 @@ impl nvgx::RendererDevice for Renderer {
         composite_operation: nvgx::CompositeOperationState,
         scissor: &nvgx::Scissor,
         slice: VertexSlice,
-    ) -> anyhow::Result<()> {
+    ) -> nvgx::Result<()> {
         let call = Call {
             call_type: CallType::Triangles,
             image: paint.image,
@@ -565,6 +734,7 @@ impl nvgx::RendererDevice for Renderer {
             triangle: slice.into(),
             uniform_offset: self.get_uniform_offset(),
             blend_func: composite_operation.into(),
+            blend_color: None,
             vert_buff: vertex_buffer,
             instances: instances.map(|(insts, r)| (insts, r.into())),
         };
@@ -576,6 +746,40 @@ impl nvgx::RendererDevice for Renderer {
         Ok(())
     }
 
+    fn lcd_triangles(
+        &mut self,
+        vertex_buffer: Option<Self::VertexBuffer>,
+        instances: Option<(Self::VertexBuffer, Range<u32>)>,
+        paint: &nvgx::PaintPattern,
+        scissor: &nvgx::Scissor,
+        slice: VertexSlice,
+    ) -> nvgx::Result<()> {
+        // The shader outputs the per-channel coverage, which weights the text
+        // color held in the blend color.
+        let call = Call {
+            call_type: CallType::Triangles,
+            image: paint.image,
+            path_range: 0..0,
+            triangle: slice.into(),
+            uniform_offset: self.get_uniform_offset(),
+            blend_func: Blend {
+                src_rgb: gl::CONSTANT_COLOR,
+                dst_rgb: gl::ONE_MINUS_SRC_COLOR,
+                src_alpha: gl::ONE,
+                dst_alpha: gl::ONE_MINUS_SRC_ALPHA,
+            },
+            blend_color: Some(paint.inner_color),
+            vert_buff: vertex_buffer,
+            instances: instances.map(|(insts, r)| (insts, r.into())),
+        };
+        self.calls.push(call);
+
+        let mut uniforms = self.convert_paint(paint, scissor, 1.0, 1.0, -1.0);
+        uniforms.type_ = ShaderType::LcdImage as i32;
+        self.append_uniforms(uniforms);
+        Ok(())
+    }
+
     #[cfg(feature = "wirelines")]
     fn wirelines(
         &mut self,
@@ -585,7 +789,7 @@ impl nvgx::RendererDevice for Renderer {
         composite_operation: nvgx::CompositeOperationState,
         scissor: &nvgx::Scissor,
         paths: &[nvgx::PathSlice],
-    ) -> anyhow::Result<()> {
+    ) -> nvgx::Result<()> {
         let path_offset = self.paths.len();
 
         for path in paths {
@@ -603,6 +807,7 @@ impl nvgx::RendererDevice for Renderer {
             triangle: Default::default(),
             uniform_offset: self.get_uniform_offset(),
             blend_func: composite_operation.into(),
+            blend_color: None,
             vert_buff: vertex_buffer,
             instances: instances.map(|(insts, r)| (insts, r.into())),
         };
@@ -613,7 +818,12 @@ impl nvgx::RendererDevice for Renderer {
         Ok(())
     }
 
-    fn clear(&mut self, clear_color: Color) -> anyhow::Result<()> {
+    fn clear(&mut self, clear_color: Color) -> nvgx::Result<()> {
+        // overdraw adds up from black
+        let clear_color = match self.debug_view {
+            DebugView::Overdraw => Color::rgba(0.0, 0.0, 0.0, 1.0),
+            _ => clear_color,
+        };
         unsafe {
             gl::Viewport(0, 0, self.view.width as i32, self.view.height as i32);
             gl::ClearColor(clear_color.r, clear_color.g, clear_color.b, clear_color.a);
@@ -21,6 +21,9 @@ layout(std140) uniform frag {
 uniform sampler2D tex;
 in vec2 ftcoord;
 in vec2 fpos;
+// premultiplied instance color, paint offset and user data
+in vec4 ftint;
+in vec4 fdata;
 out vec4 outColor;
 
 float sdroundrect(vec2 pt, vec2 ext, float rad) {
@@ -47,20 +50,20 @@ void main(void) {
 
     if (type == 0) {
         // Gradient
-        vec2 pt = (paintMat * vec3(fpos,1.0)).xy;
+        vec2 pt = (paintMat * vec3(fpos - fdata.xy, 1.0)).xy;
         float d = clamp((sdroundrect(pt, extent, radius) + feather * 0.5) / feather, 0.0, 1.0);
         vec4 color = mix(innerCol, outerCol, d);
         color *= strokeAlpha * scissor;
-        result = color;
+        result = color * ftint;
     } else if (type == 1) {
         // Image
-        vec2 pt = (paintMat * vec3(fpos, 1.0)).xy / extent;
+        vec2 pt = (paintMat * vec3(fpos - fdata.xy, 1.0)).xy / extent;
         vec4 color = texture(tex, pt);
         if (texType == 1) color = vec4(color.xyz * color.w, color.w);
         if (texType == 2) color = vec4(color.x);
         color *= innerCol;
         color *= strokeAlpha * scissor;
-        result = color;
+        result = color * ftint;
     } else if (type == 2) {
         // Stencil fill
         result = vec4(1, 1, 1, 1);
@@ -70,7 +73,20 @@ void main(void) {
         if (texType == 1) color = vec4(color.xyz * color.w, color.w);
         if (texType == 2) color = vec4(color.x);
         color *= scissor;
-        result = color * innerCol;
+        result = color * innerCol * ftint;
+    } else if (type == 4) {
+        // LCD text, one texel per subpixel
+        ivec2 tc = ivec2(ftcoord * vec2(textureSize(tex, 0)));
+        vec3 coverage = vec3(
+            texelFetch(tex, tc - ivec2(1, 0), 0).x,
+            texelFetch(tex, tc, 0).x,
+            texelFetch(tex, tc + ivec2(1, 0), 0).x
+        );
+        coverage *= innerCol.a * scissor;
+        result = vec4(coverage, max(coverage.r, max(coverage.g, coverage.b)));
+    } else if (type == 5) {
+        // Debug views, flat color
+        result = innerCol;
     }
 
     outColor = result;
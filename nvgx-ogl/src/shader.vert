@@ -5,8 +5,12 @@ in vec2 vertex;
 in vec2 tcoord;
 in vec4 model_matrix_0;
 in vec2 model_matrix_1;
+in vec4 inst_color;
+in vec4 inst_data;
 out vec2 ftcoord;
 out vec2 fpos;
+out vec4 ftint;
+out vec4 fdata;
 
 void main(void) {
     mat2 model = mat2(
@@ -15,5 +19,7 @@ void main(void) {
     vec2 v = (model * vertex) + model_matrix_1;
     ftcoord = tcoord;
     fpos = v.xy;
+    ftint = vec4(inst_color.rgb * inst_color.a, inst_color.a);
+    fdata = inst_data;
     gl_Position = vec4(2.0 * v.x / viewSize.x - 1.0, 1.0 - 2.0 * v.y / viewSize.y, 0, 1);
 }
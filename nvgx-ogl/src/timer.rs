@@ -0,0 +1,75 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Flushes measured at once, further ones are skipped until results arrive
+const MAX_PENDING: usize = 4;
+
+/// GPU time of flushes, measured by `GL_TIME_ELAPSED` queries and read
+/// frames later to avoid stalls
+pub(crate) struct GpuTimer {
+    /// Queries of measured flushes, oldest first
+    pending: VecDeque<gl::types::GLuint>,
+    free: Vec<gl::types::GLuint>,
+    measuring: bool,
+    last: Option<Duration>,
+}
+
+impl GpuTimer {
+    /// `None` without timer queries, which are core in OpenGL 3.3
+    pub(crate) fn new() -> Option<GpuTimer> {
+        if !gl::BeginQuery::is_loaded() || !gl::GetQueryObjectui64v::is_loaded() {
+            return None;
+        }
+        Some(GpuTimer {
+            pending: VecDeque::new(),
+            free: Vec::new(),
+            measuring: false,
+            last: None,
+        })
+    }
+
+    pub(crate) fn begin(&mut self) {
+        if self.pending.len() >= MAX_PENDING {
+            return;
+        }
+        let query = self.free.pop().unwrap_or_else(|| unsafe {
+            let mut query = 0;
+            gl::GenQueries(1, &mut query);
+            query
+        });
+        unsafe { gl::BeginQuery(gl::TIME_ELAPSED, query) };
+        self.pending.push_back(query);
+        self.measuring = true;
+    }
+
+    pub(crate) fn end(&mut self) {
+        if self.measuring {
+            unsafe { gl::EndQuery(gl::TIME_ELAPSED) };
+            self.measuring = false;
+        }
+    }
+
+    /// Time of the latest flush whose query has its result available
+    pub(crate) fn poll(&mut self) -> Option<Duration> {
+        while let Some(&query) = self.pending.front() {
+            let mut available = 0;
+            unsafe { gl::GetQueryObjectiv(query, gl::QUERY_RESULT_AVAILABLE, &mut available) };
+            if available == 0 {
+                break;
+            }
+            let mut nanos = 0;
+            unsafe { gl::GetQueryObjectui64v(query, gl::QUERY_RESULT, &mut nanos) };
+            self.last = Some(Duration::from_nanos(nanos));
+            self.free.push(self.pending.pop_front().unwrap());
+        }
+        self.last
+    }
+}
+
+impl Drop for GpuTimer {
+    fn drop(&mut self) {
+        for query in self.pending.drain(..).chain(self.free.drain(..)) {
+            unsafe { gl::DeleteQueries(1, &query) };
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Replays a frame trace written by `Context::capture_frame` and saves the
+//! rendered frame as a PNG.
+//!
+//! ```text
+//! nvgx-replay <trace> <output.png>
+//! ```
+
+use anyhow::{anyhow, bail};
+use nvgx::{Context, RendererDevice, TextureType, Trace};
+use nvgx_wgpu::{RenderConfig, Renderer};
+
+/// Draws the frame of `trace` into a new context on `renderer`
+fn replay<R: RendererDevice>(renderer: R, trace: &Trace) -> anyhow::Result<Context<R>> {
+    let mut context = Context::create(renderer)?;
+    trace.replay(&mut context)?;
+    Ok(context)
+}
+
+fn headless_renderer(width: u32, height: u32) -> anyhow::Result<Renderer> {
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+    let adapter =
+        pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+    let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+        required_features: adapter.features() & wgpu::Features::POLYGON_MODE_LINE,
+        required_limits: wgpu::Limits::default(),
+        label: None,
+        memory_hints: wgpu::MemoryHints::Performance,
+        trace: wgpu::Trace::Off,
+    }))?;
+    let config = RenderConfig::default().format(TextureType::RGBA);
+    Ok(Renderer::create_headless(config, device, queue, width, height)?)
+}
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        bail!("Usage: {} <trace> <output.png>", args[0]);
+    }
+
+    let trace = Trace::load(&args[1])?;
+    let (extent, _) = trace
+        .viewport()
+        .ok_or_else(|| anyhow!("The trace has no begun frame"))?;
+    let width = extent.width.ceil().max(1.0) as u32;
+    let height = extent.height.ceil().max(1.0) as u32;
+
+    let context = replay(headless_renderer(width, height)?, &trace)?;
+    let pixels = context.renderer().read_pixels()?;
+    let image = image::RgbaImage::from_raw(width, height, pixels)
+        .ok_or_else(|| anyhow!("Unexpected frame size"))?;
+    image.save(&args[2])?;
+    Ok(())
+}
@@ -1,6 +1,6 @@
 use std::{ops::Range, sync::Arc};
 
-use nvgx::{CompositeOperationState, PathFillType, VertexSlice};
+use nvgx::{Color, CompositeOperationState, PathFillType, VertexSlice};
 
 use super::unifroms::RenderCommand;
 
@@ -10,6 +10,9 @@ pub(crate) enum CallType {
     ConvexFill,
     Stroke,
     Triangles,
+    LcdTriangles,
+    /// Merged convex fills and strokes, drawn from `Mesh::batch_index_buffer`
+    Batch,
     #[cfg(feature = "wirelines")]
     Lines,
 }
@@ -21,6 +24,7 @@ pub(crate) struct Call {
     pub triangle: VertexSlice,
     pub uniform_offset: usize,
     pub blend_func: CompositeOperationState,
+    pub blend_color: Option<Color>,
     pub vertex_buffer: Option<Arc<wgpu::Buffer>>,
     pub instances: Option<(Arc<wgpu::Buffer>, Range<u32>)>,
 }
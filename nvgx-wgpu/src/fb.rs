@@ -29,7 +29,7 @@ impl RenderFrameBufferDevice for Renderer {
         return self.resources.config.format;
     }
 
-    fn create_fb(&mut self, width: u32, height: u32, image: ImageId) -> anyhow::Result<Self::FB> {
+    fn create_fb(&mut self, width: u32, height: u32, image: ImageId) -> nvgx::Result<Self::FB> {
         let stencil = StencilTexture::new(&self.device, width, height);
         Ok(FrameBuffer {
             stencil,
@@ -39,16 +39,16 @@ impl RenderFrameBufferDevice for Renderer {
         })
     }
 
-    fn delete_fb(&mut self, _fb: Self::FB) -> anyhow::Result<()> {
+    fn delete_fb(&mut self, _fb: Self::FB) -> nvgx::Result<()> {
         Ok(())
     }
 
-    fn bind(&mut self, fb: &Self::FB) -> anyhow::Result<()> {
+    fn bind(&mut self, fb: &Self::FB) -> nvgx::Result<()> {
         self.target_fb = Some((fb.image(), fb.stencil.view.clone()));
         Ok(())
     }
 
-    fn unbind(&mut self) -> anyhow::Result<()> {
+    fn unbind(&mut self) -> nvgx::Result<()> {
         self.target_fb = None;
         Ok(())
     }
@@ -1,10 +1,12 @@
-use nvgx::Transform;
+use nvgx::Instance;
 
-const INSTANCE_ATTRIBS: [wgpu::VertexAttribute; 3] =
-    wgpu::vertex_attr_array![2 => Float32x2, 3 => Float32x2, 4 => Float32x2];
+const INSTANCE_ATTRIBS: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+    2 => Float32x2, 3 => Float32x2, 4 => Float32x2,
+    5 => Float32x4, 6 => Float32x4
+];
 
 pub const INSTANCE_DESC: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
-    array_stride: std::mem::size_of::<Transform>() as wgpu::BufferAddress,
+    array_stride: std::mem::size_of::<Instance>() as wgpu::BufferAddress,
     step_mode: wgpu::VertexStepMode::Instance,
     attributes: &INSTANCE_ATTRIBS,
 };
@@ -1,7 +1,3 @@
-#[macro_use]
-#[allow(unused)]
-extern crate anyhow;
-
 use std::ops::Deref;
 
 use call::{Call, CallType, GpuPath};
@@ -9,7 +5,8 @@ use mesh::Mesh;
 use nvgx::*;
 use pipeline::{PipelineConfig, PipelineManager, PipelineUsage};
 use texture::{texture_type_map, TextureManager};
-use unifroms::{RenderCommand, Unifrom};
+use timer::GpuTimer;
+use unifroms::{RenderCommand, ShaderType, Unifrom};
 use wgpu::{util::DeviceExt, TextureView};
 
 mod call;
@@ -19,11 +16,18 @@ mod mesh;
 mod pipeline;
 mod renderer;
 mod texture;
+mod timer;
 mod unifroms;
 
 pub struct RenderConfig {
     pub antialias: bool,
     pub format: nvgx::TextureType,
+    /// Measures the GPU time of frames, the device needs
+    /// `Features::TIMESTAMP_QUERY`
+    pub gpu_timing: bool,
+    /// Merges consecutive convex fills and strokes with the same paint,
+    /// scissor and blend into single draws
+    pub batching: bool,
 }
 
 impl RenderConfig {
@@ -37,6 +41,16 @@ impl RenderConfig {
         self
     }
 
+    pub fn gpu_timing(mut self, gpu_timing: bool) -> Self {
+        self.gpu_timing = gpu_timing;
+        self
+    }
+
+    pub fn batching(mut self, batching: bool) -> Self {
+        self.batching = batching;
+        self
+    }
+
     pub fn format_match(&self, format: &wgpu::TextureFormat) -> bool {
         format == &texture_type_map(self.format)
     }
@@ -47,6 +61,8 @@ impl Default for RenderConfig {
         Self {
             antialias: true,
             format: nvgx::TextureType::BGRA,
+            gpu_timing: false,
+            batching: true,
         }
     }
 }
@@ -60,6 +76,10 @@ pub struct RenderResource {
     render_unifrom: Unifrom<Vec<RenderCommand>>,
     texture_manager: TextureManager,
     default_instace: wgpu::Buffer,
+    debug_view: DebugView,
+    /// First of the uniforms appended for the debug view
+    debug_uniform: usize,
+    batch_indices: Vec<u32>,
 }
 
 impl RenderResource {
@@ -270,6 +290,44 @@ impl RenderResource {
         render_pass.draw(call.triangle_vert(), instance_slice.clone());
     }
 
+    #[inline]
+    fn do_lcd_triangles(
+        &self,
+        call: &Call,
+        render_pass: &mut wgpu::RenderPass<'_>,
+        pipeline_manager: &PipelineManager,
+    ) {
+        let buffer = call
+            .vertex_buffer
+            .as_ref()
+            .map(|v| v.deref())
+            .unwrap_or(&self.mesh.vertex_buffer);
+        let (instance_buffer, instance_slice) = call
+            .instances
+            .as_ref()
+            .map(|i| (i.0.deref(), i.1.clone()))
+            .unwrap_or((&self.default_instace, 0..1));
+        let color = call.blend_color.unwrap_or_default();
+        render_pass.set_pipeline(pipeline_manager.lcd_triangles.pipeline());
+        render_pass.set_blend_constant(wgpu::Color {
+            r: color.r as f64,
+            g: color.g as f64,
+            b: color.b as f64,
+            a: color.a as f64,
+        });
+        render_pass.set_bind_group(0, &self.viewsize_uniform.bind_group, &[]);
+        render_pass.set_bind_group(
+            1,
+            &self.render_unifrom.bind_group,
+            &[call.uniform_offset(0)],
+        );
+        render_pass.set_bind_group(2, self.texture_manager.get_bindgroup(call.image), &[]);
+        render_pass.set_vertex_buffer(0, buffer.slice(..));
+        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+
+        render_pass.draw(call.triangle_vert(), instance_slice.clone());
+    }
+
     #[inline]
     #[cfg(feature = "wirelines")]
     fn do_lines(
@@ -305,6 +363,164 @@ impl RenderResource {
         }
     }
 
+    /// Draws the fill and stroke geometry of `call` in the color of the debug
+    /// view, only the fringes of fills for [`DebugView::Fringe`]
+    #[inline]
+    fn do_debug_overlay(
+        &self,
+        call: &Call,
+        render_pass: &mut wgpu::RenderPass<'_>,
+        pipeline_manager: &PipelineManager,
+    ) {
+        let wireframe = self.debug_view == DebugView::Wireframe;
+        match call.call_type {
+            CallType::Fill(_) | CallType::ConvexFill | CallType::Stroke | CallType::Batch => {}
+            _ => return,
+        }
+        let paths = &self.paths[call.path_range.clone()];
+        let buffer = call
+            .vertex_buffer
+            .as_deref()
+            .unwrap_or(&self.mesh.vertex_buffer);
+        let (instance_buffer, instance_slice) = call
+            .instances
+            .as_ref()
+            .map(|i| (i.0.deref(), i.1.clone()))
+            .unwrap_or((&self.default_instace, 0..1));
+        render_pass.set_bind_group(0, &self.viewsize_uniform.bind_group, &[]);
+        render_pass.set_bind_group(
+            1,
+            &self.render_unifrom.bind_group,
+            &[(self.debug_uniform * size_of::<RenderCommand>()) as u32],
+        );
+        render_pass.set_bind_group(2, self.texture_manager.get_bindgroup(None), &[]);
+        render_pass.set_index_buffer(self.mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.set_vertex_buffer(0, buffer.slice(..));
+        render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+
+        if wireframe {
+            render_pass.set_pipeline(pipeline_manager.wireframe_fan.pipeline());
+            for path in paths.iter().filter(|path| path.fill.count > 2) {
+                render_pass.draw_indexed(
+                    0..path.triangle_fan_count() * 3,
+                    path.triangle_fan_offset(),
+                    instance_slice.clone(),
+                );
+            }
+            render_pass.set_pipeline(pipeline_manager.wireframe_strip.pipeline());
+        } else {
+            render_pass.set_pipeline(pipeline_manager.fill_stroke.pipeline());
+            render_pass.set_stencil_reference(0);
+        }
+        // strokes have no fill, their strips are no fringes
+        for path in paths.iter().filter(|path| wireframe || path.fill.count > 0) {
+            render_pass.draw(path.stroke_vert(), instance_slice.clone());
+        }
+    }
+
+    #[inline]
+    fn do_batch(
+        &self,
+        call: &Call,
+        render_pass: &mut wgpu::RenderPass<'_>,
+        pipeline_manager: &PipelineManager,
+    ) {
+        render_pass.set_pipeline(pipeline_manager.batch.pipeline());
+        render_pass.set_stencil_reference(0);
+        render_pass.set_bind_group(0, &self.viewsize_uniform.bind_group, &[]);
+        render_pass.set_bind_group(
+            1,
+            &self.render_unifrom.bind_group,
+            &[call.uniform_offset(0)],
+        );
+        render_pass.set_bind_group(2, self.texture_manager.get_bindgroup(call.image), &[]);
+        render_pass.set_index_buffer(
+            self.mesh.batch_index_buffer.slice(..),
+            wgpu::IndexFormat::Uint32,
+        );
+        render_pass.set_vertex_buffer(0, self.mesh.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.default_instace.slice(..));
+
+        render_pass.draw_indexed(call.triangle_vert(), 0, 0..1);
+    }
+
+    /// Merges runs of convex fills and strokes of the frame's vertices that
+    /// share image, blend and uniforms into single indexed draws
+    fn batch_calls(&mut self) {
+        let calls = std::mem::take(&mut self.calls);
+        let mut batched: Vec<Call> = Vec::with_capacity(calls.len());
+        for call in calls {
+            if let Some(last) = batched.last_mut() {
+                if self.batchable(last, &call) {
+                    if last.call_type != CallType::Batch {
+                        let offset = self.batch_indices.len();
+                        append_indices(
+                            &self.paths[last.path_range.clone()],
+                            &mut self.batch_indices,
+                        );
+                        last.call_type = CallType::Batch;
+                        last.triangle = VertexSlice { offset, count: 0 };
+                    }
+                    append_indices(
+                        &self.paths[call.path_range.clone()],
+                        &mut self.batch_indices,
+                    );
+                    last.path_range.end = call.path_range.end;
+                    last.triangle.count = self.batch_indices.len() - last.triangle.offset;
+                    continue;
+                }
+            }
+            batched.push(call);
+        }
+        self.calls = batched;
+    }
+
+    fn batchable(&self, last: &Call, call: &Call) -> bool {
+        let uniforms = |offset: usize| bytemuck::bytes_of(&self.render_unifrom.value[offset]);
+        matches!(
+            last.call_type,
+            CallType::ConvexFill | CallType::Stroke | CallType::Batch
+        ) && matches!(call.call_type, CallType::ConvexFill | CallType::Stroke)
+            && last.vertex_buffer.is_none()
+            && call.vertex_buffer.is_none()
+            && last.instances.is_none()
+            && call.instances.is_none()
+            && last.path_range.end == call.path_range.start
+            && last.image == call.image
+            && last.blend_func == call.blend_func
+            && last.blend_color.is_none()
+            && call.blend_color.is_none()
+            && uniforms(last.uniform_offset) == uniforms(call.uniform_offset)
+    }
+
+    /// Appends the uniforms of the debug view, with which overdraw draws
+    /// every call
+    fn prepare_debug_view(&mut self) {
+        let view = self.debug_view;
+        self.debug_uniform = self.render_unifrom.offset();
+        if view == DebugView::None {
+            return;
+        }
+        let command = RenderCommand {
+            inner_color: view.color(),
+            stroke_thr: -1.0,
+            render_type: ShaderType::Debug as u32,
+            ..Default::default()
+        };
+        // twice, as fills read a second uniform for their cover pass
+        self.render_unifrom.value.extend([command; 2]);
+        if view == DebugView::Overdraw {
+            for call in &mut self.calls {
+                if call.call_type == CallType::LcdTriangles {
+                    call.call_type = CallType::Triangles;
+                }
+                call.uniform_offset = self.debug_uniform;
+                call.blend_func = view.blend();
+                call.blend_color = None;
+            }
+        }
+    }
+
     fn render(
         &self,
         device: &wgpu::Device,
@@ -314,10 +530,14 @@ impl RenderResource {
         stencil_view: &TextureView,
         pipeline_manager: &mut PipelineManager,
         clear_cmd: Option<wgpu::Color>,
+        scissor: Option<[u32; 4]>,
+        timer: Option<&mut GpuTimer>,
     ) {
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Nvg Flush Render Encoder"),
         });
+        let timestamp_writes = timer.as_deref().and_then(GpuTimer::timestamp_writes);
+        let measured = timestamp_writes.is_some();
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("NVG Render Pass"),
@@ -345,9 +565,12 @@ impl RenderResource {
                         store: wgpu::StoreOp::Store,
                     },
                 })],
-
+                timestamp_writes,
                 ..Default::default()
             });
+            if let Some([x, y, width, height]) = scissor {
+                render_pass.set_scissor_rect(x, y, width, height);
+            }
             for call in &self.calls {
                 match call.call_type {
                     CallType::Fill(t) => {
@@ -411,6 +634,26 @@ impl RenderResource {
                         );
                         self.do_triangles(call, &mut render_pass, &pipeline_manager);
                     }
+                    CallType::Batch => {
+                        pipeline_manager.update_pipeline(
+                            device,
+                            PipelineConfig {
+                                format,
+                                usage: PipelineUsage::Batch(call.blend_func),
+                            },
+                        );
+                        self.do_batch(call, &mut render_pass, pipeline_manager);
+                    }
+                    CallType::LcdTriangles => {
+                        pipeline_manager.update_pipeline(
+                            device,
+                            PipelineConfig {
+                                format,
+                                usage: PipelineUsage::LcdTriangles,
+                            },
+                        );
+                        self.do_lcd_triangles(call, &mut render_pass, pipeline_manager);
+                    }
                     #[cfg(feature = "wirelines")]
                     CallType::Lines => {
                         pipeline_manager.update_pipeline(
@@ -424,30 +667,212 @@ impl RenderResource {
                     }
                 }
             }
+            if matches!(self.debug_view, DebugView::Wireframe | DebugView::Fringe) {
+                for usage in [
+                    PipelineUsage::WireframeFan,
+                    PipelineUsage::WireframeStrip,
+                    PipelineUsage::FillStroke(self.debug_view.blend()),
+                ] {
+                    pipeline_manager.update_pipeline(device, PipelineConfig { format, usage });
+                }
+                for call in &self.calls {
+                    self.do_debug_overlay(call, &mut render_pass, pipeline_manager);
+                }
+            }
+        }
+        match timer.filter(|_| measured) {
+            Some(timer) => {
+                timer.resolve(&mut encoder);
+                queue.submit(std::iter::once(encoder.finish()));
+                timer.submitted();
+            }
+            None => {
+                queue.submit(std::iter::once(encoder.finish()));
+            }
         }
-        queue.submit(std::iter::once(encoder.finish()));
     }
 }
 
+/// Indices of the fans and strips of `paths` in drawing order
+fn append_indices(paths: &[GpuPath], indices: &mut Vec<u32>) {
+    for path in paths {
+        let (fill, stroke) = (path.fill, path.stroke);
+        utils::triangle_fan_indices(fill.offset as u32, fill.count as u32, indices);
+        utils::triangle_strip_indices(stroke.offset as u32, stroke.count as u32, indices);
+    }
+}
+
+/// Where frames are rendered when no framebuffer is bound
+enum RenderTarget {
+    Surface(wgpu::Surface<'static>),
+    /// Offscreen texture of a headless renderer
+    Texture(wgpu::Texture),
+}
+
 pub struct Renderer {
     device: wgpu::Device,
     queue: wgpu::Queue,
-    surface: wgpu::Surface<'static>,
+    target: RenderTarget,
     surface_config: wgpu::SurfaceConfiguration,
     pipeline_manager: PipelineManager,
     target_fb: Option<(ImageId, TextureView)>,
     clear_cmd: Option<wgpu::Color>,
+    /// Scissor rectangle of partial frames, in pixels
+    damage: Option<[u32; 4]>,
+    device_pixel_ratio: f32,
+    /// Keeps the previous frame of a surface for partial frames
+    canvas: Option<wgpu::Texture>,
     resources: RenderResource,
+    timer: Option<GpuTimer>,
+    /// Draw calls of the latest flushed frame
+    flushed_calls: usize,
 }
 
 impl Renderer {
+    /// Renderer drawing into `surface`. Frames redraw only their damaged
+    /// area when `surface_config.usage` includes `TextureUsages::COPY_DST`.
     pub fn create(
         config: RenderConfig,
         device: wgpu::Device,
         queue: wgpu::Queue,
         surface: wgpu::Surface<'static>,
         surface_config: wgpu::SurfaceConfiguration,
-    ) -> anyhow::Result<Self> {
+    ) -> nvgx::Result<Self> {
+        Self::create_with_target(
+            config,
+            device,
+            queue,
+            RenderTarget::Surface(surface),
+            surface_config,
+        )
+    }
+
+    /// Renderer drawing into an offscreen texture of `width` x `height`
+    /// instead of a surface, read back with [`Renderer::read_pixels`].
+    pub fn create_headless(
+        config: RenderConfig,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        width: u32,
+        height: u32,
+    ) -> nvgx::Result<Self> {
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: texture_type_map(config.format),
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+        };
+        let texture = Self::create_target_texture(&device, &surface_config);
+        Self::create_with_target(
+            config,
+            device,
+            queue,
+            RenderTarget::Texture(texture),
+            surface_config,
+        )
+    }
+
+    fn create_target_texture(
+        device: &wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+    ) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("NVG Target"),
+            size: wgpu::Extent3d {
+                width: surface_config.width,
+                height: surface_config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: surface_config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        })
+    }
+
+    /// Pixels of the last frame of a headless renderer, rows from top to
+    /// bottom with 4 bytes per pixel in the channel order of the format.
+    pub fn read_pixels(&self) -> nvgx::Result<Vec<u8>> {
+        let texture = match &self.target {
+            RenderTarget::Texture(texture) => texture,
+            RenderTarget::Surface(_) => {
+                return Err(nvgx::Error::backend(
+                    "Only headless renderers can be read back",
+                ))
+            }
+        };
+        let (width, height) = (self.surface_config.width, self.surface_config.height);
+        let row_bytes = width * 4;
+        let padded_row_bytes = row_bytes.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("NVG Readback"),
+            size: (padded_row_bytes * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("NVG Readback Encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device
+            .poll(wgpu::PollType::Wait)
+            .map_err(nvgx::Error::backend)?;
+        receiver
+            .recv()
+            .map_err(nvgx::Error::backend)?
+            .map_err(nvgx::Error::backend)?;
+
+        let mut pixels = Vec::with_capacity((row_bytes * height) as usize);
+        for row in slice.get_mapped_range().chunks(padded_row_bytes as usize) {
+            pixels.extend_from_slice(&row[..row_bytes as usize]);
+        }
+        buffer.unmap();
+        Ok(pixels)
+    }
+
+    /// Draws the next frames in `view` instead of their regular look
+    pub fn set_debug_view(&mut self, view: DebugView) {
+        self.resources.debug_view = view;
+    }
+
+    pub fn debug_view(&self) -> DebugView {
+        self.resources.debug_view
+    }
+
+    fn create_with_target(
+        config: RenderConfig,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        target: RenderTarget,
+        surface_config: wgpu::SurfaceConfiguration,
+    ) -> nvgx::Result<Self> {
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("NVG Shader"),
             source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
@@ -473,21 +898,31 @@ impl Renderer {
         let pipeline_manager =
             PipelineManager::new(shader, pipeline_layout, &device, config.format);
 
-        let identity_instance = Transform::identity();
+        let identity_instance = Instance::default();
         let default_instace = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Default Instace"),
             contents: bytemuck::bytes_of(&identity_instance),
             usage: wgpu::BufferUsages::VERTEX,
         });
 
+        let timer = if config.gpu_timing {
+            GpuTimer::new(&device, &queue)
+        } else {
+            None
+        };
         return Ok(Self {
+            timer,
+            flushed_calls: 0,
             device,
             queue,
-            surface,
+            target,
             surface_config,
             target_fb: None,
             pipeline_manager,
             clear_cmd: None,
+            damage: None,
+            device_pixel_ratio: 1.0,
+            canvas: None,
             resources: RenderResource {
                 config,
                 mesh,
@@ -497,6 +932,9 @@ impl Renderer {
                 render_unifrom,
                 texture_manager,
                 default_instace,
+                debug_view: DebugView::None,
+                debug_uniform: 0,
+                batch_indices: Vec::new(),
             },
         });
     }
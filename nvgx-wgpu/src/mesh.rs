@@ -18,6 +18,8 @@ pub struct Mesh {
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub indices: Vec<u32>,
+    /// Vertex indices of batched calls
+    pub batch_index_buffer: wgpu::Buffer,
 }
 
 impl Mesh {
@@ -38,6 +40,7 @@ impl Mesh {
             vertex_buffer: Mesh::create_buffer(device, init_num_vertex),
             index_buffer,
             indices,
+            batch_index_buffer: Mesh::create_index_buffer(device, Self::INDEX_SIZE),
         }
     }
 
@@ -76,6 +79,30 @@ impl Mesh {
         }
     }
 
+    fn create_index_buffer(device: &wgpu::Device, size: u64) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("NVG Batch Index Buffer"),
+            size,
+            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    #[inline]
+    pub fn update_batch_indices(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        indices: &[u32],
+    ) {
+        let data: &[u8] = bytemuck::cast_slice(indices);
+        if self.batch_index_buffer.size() < data.len() as u64 {
+            self.batch_index_buffer.destroy();
+            self.batch_index_buffer = Self::create_index_buffer(device, (data.len() * 2) as u64);
+        }
+        queue.write_buffer(&self.batch_index_buffer, 0, data);
+    }
+
     #[inline]
     pub fn create_buffer(device: &wgpu::Device, buffer_size: usize) -> wgpu::Buffer {
         let buffer_size = align_size::<1024>(buffer_size);
@@ -94,15 +121,46 @@ impl Mesh {
         queue: &wgpu::Queue,
         buffer: &wgpu::Buffer,
         data: &[u8],
-    ) -> anyhow::Result<()> {
+    ) -> nvgx::Result<()> {
         if buffer.size() < data.len() as u64 {
-            Err(anyhow!("Vertex buffer out of memory"))
+            Err(nvgx::Error::BufferRange {
+                offset: 0,
+                len: data.len(),
+                size: buffer.size() as usize,
+            })
         } else {
             queue.write_buffer(&buffer, 0, data);
             Ok(())
         }
     }
 
+    #[inline]
+    pub fn update_buffer_range(
+        &mut self,
+        queue: &wgpu::Queue,
+        buffer: &wgpu::Buffer,
+        offset: u64,
+        data: &[u8],
+    ) -> nvgx::Result<()> {
+        let size = data.len() as u64;
+        if buffer.size() < offset + size {
+            Err(nvgx::Error::BufferRange {
+                offset: offset as usize,
+                len: data.len(),
+                size: buffer.size() as usize,
+            })
+        } else if offset % wgpu::COPY_BUFFER_ALIGNMENT != 0
+            || size % wgpu::COPY_BUFFER_ALIGNMENT != 0
+        {
+            Err(nvgx::Error::backend(
+                "Vertex buffer range is not aligned to 4 bytes",
+            ))
+        } else {
+            queue.write_buffer(buffer, offset, data);
+            Ok(())
+        }
+    }
+
     #[inline]
     pub fn update_inner_buffer(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, data: &[u8]) {
         if self.vertex_buffer.size() < data.len() as u64 {
@@ -11,7 +11,13 @@ pub enum PipelineUsage {
     FillInner(CompositeOperationState),
     FillConvex(CompositeOperationState),
     Triangles(CompositeOperationState),
+    LcdTriangles,
     Lines(CompositeOperationState),
+    /// Triangle outlines of fans drawn as lists, for the wireframe view
+    WireframeFan,
+    /// Triangle outlines of strips, for the wireframe view
+    WireframeStrip,
+    Batch(CompositeOperationState),
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -70,11 +76,37 @@ impl PipelineConfig {
             | PipelineUsage::FillInner(blend)
             | PipelineUsage::FillConvex(blend)
             | PipelineUsage::Triangles(blend)
-            | PipelineUsage::Lines(blend) => wgpu::ColorTargetState {
+            | PipelineUsage::Lines(blend)
+            | PipelineUsage::Batch(blend) => wgpu::ColorTargetState {
                 format: self.get_color_format(),
                 blend: Some(Self::to_wgpu_blend_state(blend)),
                 write_mask: wgpu::ColorWrites::ALL,
             },
+            PipelineUsage::WireframeFan | PipelineUsage::WireframeStrip => wgpu::ColorTargetState {
+                format: self.get_color_format(),
+                blend: Some(Self::to_wgpu_blend_state(
+                    &nvgx::DebugView::Wireframe.blend(),
+                )),
+                write_mask: wgpu::ColorWrites::ALL,
+            },
+            // Per-channel coverage weights the text color held in the blend
+            // constant.
+            PipelineUsage::LcdTriangles => wgpu::ColorTargetState {
+                format: self.get_color_format(),
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::Constant,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrc,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            },
         }
     }
 
@@ -83,7 +115,8 @@ impl PipelineConfig {
         match &self.usage {
             PipelineUsage::FillStencil(_)
             | PipelineUsage::FillConvex(_)
-            | PipelineUsage::Triangles(_) => wgpu::PrimitiveState {
+            | PipelineUsage::Triangles(_)
+            | PipelineUsage::LcdTriangles => wgpu::PrimitiveState {
                 topology: wgpu::PrimitiveTopology::TriangleList,
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw,
@@ -92,6 +125,15 @@ impl PipelineConfig {
                 polygon_mode: wgpu::PolygonMode::Fill,
                 conservative: false,
             },
+            PipelineUsage::Batch(_) => wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                unclipped_depth: false,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                conservative: false,
+            },
             PipelineUsage::FillStroke(_) | PipelineUsage::FillInner(_) => wgpu::PrimitiveState {
                 topology: wgpu::PrimitiveTopology::TriangleStrip,
                 strip_index_format: None,
@@ -110,6 +152,24 @@ impl PipelineConfig {
                 polygon_mode: wgpu::PolygonMode::Line,
                 conservative: false,
             },
+            PipelineUsage::WireframeFan => wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: wgpu::PolygonMode::Line,
+                conservative: false,
+            },
+            PipelineUsage::WireframeStrip => wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: wgpu::PolygonMode::Line,
+                conservative: false,
+            },
         }
     }
 
@@ -150,7 +210,9 @@ impl PipelineConfig {
                     write_mask: 0xff,
                 },
             },
-            PipelineUsage::FillStroke(_) | PipelineUsage::FillConvex(_) => wgpu::StencilState {
+            PipelineUsage::FillStroke(_)
+            | PipelineUsage::FillConvex(_)
+            | PipelineUsage::Batch(_) => wgpu::StencilState {
                 front: wgpu::StencilFaceState {
                     compare: wgpu::CompareFunction::Equal,
                     pass_op: wgpu::StencilOperation::Keep,
@@ -172,7 +234,11 @@ impl PipelineConfig {
                 read_mask: 0xff,
                 write_mask: 0xff,
             },
-            PipelineUsage::Triangles(_) | PipelineUsage::Lines(_) => wgpu::StencilState {
+            PipelineUsage::Triangles(_)
+            | PipelineUsage::LcdTriangles
+            | PipelineUsage::Lines(_)
+            | PipelineUsage::WireframeFan
+            | PipelineUsage::WireframeStrip => wgpu::StencilState {
                 front: wgpu::StencilFaceState::IGNORE,
                 back: wgpu::StencilFaceState::IGNORE,
                 read_mask: 0xff,
@@ -283,7 +349,11 @@ pub struct PipelineManager {
     pub fill_inner: Pipeline,
     pub fill_convex: Pipeline,
     pub triangles: Pipeline,
+    pub lcd_triangles: Pipeline,
     pub wirelines: Pipeline,
+    pub wireframe_fan: Pipeline,
+    pub wireframe_strip: Pipeline,
+    pub batch: Pipeline,
 }
 
 impl PipelineManager {
@@ -330,6 +400,13 @@ impl PipelineManager {
                 usage: PipelineUsage::Triangles(default_blend),
             },
         );
+        let lcd_triangles = builder.create(
+            device,
+            PipelineConfig {
+                format,
+                usage: PipelineUsage::LcdTriangles,
+            },
+        );
         let wirelines = builder.create(
             &device,
             PipelineConfig {
@@ -337,6 +414,27 @@ impl PipelineManager {
                 usage: PipelineUsage::Lines(default_blend),
             },
         );
+        let wireframe_fan = builder.create(
+            device,
+            PipelineConfig {
+                format,
+                usage: PipelineUsage::WireframeFan,
+            },
+        );
+        let wireframe_strip = builder.create(
+            device,
+            PipelineConfig {
+                format,
+                usage: PipelineUsage::WireframeStrip,
+            },
+        );
+        let batch = builder.create(
+            device,
+            PipelineConfig {
+                format,
+                usage: PipelineUsage::Batch(default_blend),
+            },
+        );
         return Self {
             builder,
             fill_stencil,
@@ -344,7 +442,11 @@ impl PipelineManager {
             fill_inner,
             fill_convex,
             triangles,
+            lcd_triangles,
             wirelines,
+            wireframe_fan,
+            wireframe_strip,
+            batch,
         };
     }
 
@@ -381,12 +483,36 @@ impl PipelineManager {
                         .update_pipeline(config, device, &mut self.triangles);
                 }
             }
+            PipelineUsage::LcdTriangles => {
+                if self.lcd_triangles.config != config {
+                    self.builder
+                        .update_pipeline(config, device, &mut self.lcd_triangles);
+                }
+            }
             PipelineUsage::Lines(_) => {
                 if self.wirelines.config != config {
                     self.builder
                         .update_pipeline(config, device, &mut self.wirelines);
                 }
             }
+            PipelineUsage::WireframeFan => {
+                if self.wireframe_fan.config != config {
+                    self.builder
+                        .update_pipeline(config, device, &mut self.wireframe_fan);
+                }
+            }
+            PipelineUsage::WireframeStrip => {
+                if self.wireframe_strip.config != config {
+                    self.builder
+                        .update_pipeline(config, device, &mut self.wireframe_strip);
+                }
+            }
+            PipelineUsage::Batch(_) => {
+                if self.batch.config != config {
+                    self.builder
+                        .update_pipeline(config, device, &mut self.batch);
+                }
+            }
         }
     }
 }
@@ -9,7 +9,7 @@ use crate::{
     unifroms::{RenderCommand, ShaderType},
 };
 
-use super::{call::CallType, mesh::Mesh, Renderer};
+use super::{call::CallType, mesh::Mesh, RenderTarget, Renderer};
 
 impl nvgx::RendererDevice for Renderer {
     type VertexBuffer = Arc<wgpu::Buffer>;
@@ -22,7 +22,7 @@ impl nvgx::RendererDevice for Renderer {
         &mut self,
         buffer_size: usize,
         _usage: BufferUsage,
-    ) -> anyhow::Result<Self::VertexBuffer> {
+    ) -> nvgx::Result<Self::VertexBuffer> {
         return Ok(Arc::new(Mesh::create_buffer(&self.device, buffer_size)));
     }
 
@@ -30,7 +30,7 @@ impl nvgx::RendererDevice for Renderer {
         &mut self,
         buffer: Option<&Self::VertexBuffer>,
         data: &[u8],
-    ) -> anyhow::Result<()> {
+    ) -> nvgx::Result<()> {
         if let Some(buffer) = buffer {
             self.resources
                 .mesh
@@ -43,10 +43,47 @@ impl nvgx::RendererDevice for Renderer {
         Ok(())
     }
 
-    fn resize(&mut self, _width: u32, _height: u32) -> anyhow::Result<()> {
+    fn update_vertex_buffer_range(
+        &mut self,
+        buffer: &Self::VertexBuffer,
+        data: &[u8],
+        range: Range<usize>,
+    ) -> nvgx::Result<()> {
+        self.resources.mesh.update_buffer_range(
+            &self.queue,
+            buffer.as_ref(),
+            range.start as u64,
+            &data[range],
+        )
+    }
+
+    fn vertex_buffer_size(&self, buffer: &Self::VertexBuffer) -> Option<usize> {
+        Some(buffer.size() as usize)
+    }
+
+    fn delete_vertex_buffer(&mut self, buffer: Self::VertexBuffer) -> nvgx::Result<()> {
+        // pending draw calls still hold a clone otherwise
+        if let Ok(buffer) = Arc::try_unwrap(buffer) {
+            buffer.destroy();
+        }
+        Ok(())
+    }
+
+    fn resize(&mut self, _width: u32, _height: u32) -> nvgx::Result<()> {
         self.surface_config.width = _width;
         self.surface_config.height = _height;
-        self.surface.configure(&self.device, &self.surface_config);
+        match &mut self.target {
+            RenderTarget::Surface(surface) => surface.configure(&self.device, &self.surface_config),
+            RenderTarget::Texture(texture) => {
+                *texture = Renderer::create_target_texture(&self.device, &self.surface_config)
+            }
+        }
+        if self.canvas.is_some() {
+            self.canvas = Some(Renderer::create_target_texture(
+                &self.device,
+                &self.surface_config,
+            ));
+        }
         self.resources
             .texture_manager
             .configure_stencil(&self.device, &self.surface_config);
@@ -60,7 +97,11 @@ impl nvgx::RendererDevice for Renderer {
         height: u32,
         flags: nvgx::ImageFlags,
         data: Option<&[u8]>,
-    ) -> anyhow::Result<nvgx::ImageId> {
+    ) -> nvgx::Result<nvgx::ImageId> {
+        let max = self.device.limits().max_texture_dimension_2d;
+        if width > max || height > max {
+            return Err(nvgx::Error::TextureTooLarge { width, height, max });
+        }
         Ok(self.resources.texture_manager.create(
             &self.device,
             &self.queue,
@@ -75,7 +116,10 @@ impl nvgx::RendererDevice for Renderer {
         ) as nvgx::ImageId)
     }
 
-    fn delete_texture(&mut self, img: nvgx::ImageId) -> anyhow::Result<()> {
+    fn delete_texture(&mut self, img: nvgx::ImageId) -> nvgx::Result<()> {
+        if self.resources.texture_manager.get(img).is_none() {
+            return Err(nvgx::Error::InvalidImage(img));
+        }
         self.resources.texture_manager.remove(img as usize);
         Ok(())
     }
@@ -88,12 +132,12 @@ impl nvgx::RendererDevice for Renderer {
         width: u32,
         height: u32,
         data: &[u8],
-    ) -> anyhow::Result<()> {
+    ) -> nvgx::Result<()> {
         let texture = self
             .resources
             .texture_manager
             .get_mut(img as usize)
-            .ok_or_else(|| anyhow::anyhow!("Texture not found"))?;
+            .ok_or(nvgx::Error::InvalidImage(img))?;
         texture.update(
             &self.queue,
             data,
@@ -107,30 +151,87 @@ impl nvgx::RendererDevice for Renderer {
         Ok(())
     }
 
-    fn texture_size(&self, img: nvgx::ImageId) -> anyhow::Result<(u32, u32)> {
+    fn texture_size(&self, img: nvgx::ImageId) -> nvgx::Result<(u32, u32)> {
         let texture = self
             .resources
             .texture_manager
             .get(img as usize)
-            .ok_or_else(|| anyhow::anyhow!("Texture not found"))?;
+            .ok_or(nvgx::Error::InvalidImage(img))?;
         let size = texture.size();
         Ok((size.width, size.height))
     }
 
-    fn viewport(&mut self, extent: nvgx::Extent, _device_pixel_ratio: f32) -> anyhow::Result<()> {
+    fn viewport(&mut self, extent: nvgx::Extent, device_pixel_ratio: f32) -> nvgx::Result<()> {
         self.resources.viewsize_uniform.value = extent;
+        self.device_pixel_ratio = device_pixel_ratio;
         Ok(())
     }
 
+    fn damage(&mut self, area: Option<nvgx::Bounds>) -> nvgx::Result<bool> {
+        let area = match (area, &self.target) {
+            (Some(area), RenderTarget::Surface(_))
+                if self
+                    .surface_config
+                    .usage
+                    .contains(wgpu::TextureUsages::COPY_DST) =>
+            {
+                if self.canvas.is_none() {
+                    self.canvas = Some(Renderer::create_target_texture(
+                        &self.device,
+                        &self.surface_config,
+                    ));
+                }
+                area
+            }
+            (Some(area), RenderTarget::Texture(_)) => area,
+            _ => {
+                self.damage = None;
+                self.canvas = None;
+                return Ok(false);
+            }
+        };
+        // window coordinates to surface pixels
+        let (width, height) = (self.surface_config.width, self.surface_config.height);
+        let ratio = self.device_pixel_ratio;
+        let x = ((area.min.x * ratio).floor() as u32).min(width);
+        let y = ((area.min.y * ratio).floor() as u32).min(height);
+        let right = ((area.max.x * ratio).ceil() as u32).clamp(x, width);
+        let bottom = ((area.max.y * ratio).ceil() as u32).clamp(y, height);
+        self.damage = Some([x, y, right - x, bottom - y]);
+        Ok(true)
+    }
+
     #[inline]
-    fn cancel(&mut self) -> anyhow::Result<()> {
+    fn cancel(&mut self) -> nvgx::Result<()> {
         self.resources.calls.clear();
         self.resources.paths.clear();
         self.resources.render_unifrom.value.clear();
+        self.resources.batch_indices.clear();
         Ok(())
     }
 
-    fn flush(&mut self) -> anyhow::Result<()> {
+    fn gpu_time(&mut self) -> Option<std::time::Duration> {
+        let device = &self.device;
+        self.timer.as_mut().and_then(|timer| timer.poll(device))
+    }
+
+    fn flushed_calls(&self) -> Option<usize> {
+        Some(self.flushed_calls)
+    }
+
+    fn flush(&mut self) -> nvgx::Result<()> {
+        if self.resources.config.batching {
+            self.resources.batch_calls();
+            if !self.resources.batch_indices.is_empty() {
+                self.resources.mesh.update_batch_indices(
+                    &self.device,
+                    &self.queue,
+                    &self.resources.batch_indices,
+                );
+            }
+        }
+        self.flushed_calls = self.resources.calls.len();
+        self.resources.prepare_debug_view();
         self.resources
             .viewsize_uniform
             .update_buffer(&self.device, &self.queue);
@@ -148,23 +249,59 @@ impl nvgx::RendererDevice for Renderer {
                 &stencil_view,
                 &mut self.pipeline_manager,
                 self.clear_cmd.take(),
+                None,
+                self.timer.as_mut(),
             );
         } else {
-            let output = self.surface.get_current_texture().unwrap();
-            let view = output
-                .texture
-                .create_view(&wgpu::TextureViewDescriptor::default());
-
-            self.resources.render(
-                &self.device,
-                &self.queue,
-                &view,
-                self.resources.config.format,
-                self.resources.texture_manager.stencil_view(),
-                &mut self.pipeline_manager,
-                self.clear_cmd.take(),
-            );
-            output.present();
+            let (output, view) = match (&self.target, &self.canvas) {
+                (RenderTarget::Surface(surface), Some(canvas)) => (
+                    Some(surface_texture(surface)?),
+                    canvas.create_view(&wgpu::TextureViewDescriptor::default()),
+                ),
+                (RenderTarget::Surface(surface), None) => {
+                    let output = surface_texture(surface)?;
+                    let view = output
+                        .texture
+                        .create_view(&wgpu::TextureViewDescriptor::default());
+                    (Some(output), view)
+                }
+                (RenderTarget::Texture(texture), _) => (
+                    None,
+                    texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                ),
+            };
+
+            // an empty damaged area has nothing to draw
+            let clear_cmd = self.clear_cmd.take();
+            if !matches!(self.damage, Some([_, _, 0, _] | [_, _, _, 0])) {
+                self.resources.render(
+                    &self.device,
+                    &self.queue,
+                    &view,
+                    self.resources.config.format,
+                    self.resources.texture_manager.stencil_view(),
+                    &mut self.pipeline_manager,
+                    clear_cmd,
+                    self.damage,
+                    self.timer.as_mut(),
+                );
+            }
+            if let Some(output) = output {
+                if let Some(canvas) = &self.canvas {
+                    let mut encoder =
+                        self.device
+                            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                                label: Some("NVG Canvas Copy Encoder"),
+                            });
+                    encoder.copy_texture_to_texture(
+                        canvas.as_image_copy(),
+                        output.texture.as_image_copy(),
+                        canvas.size(),
+                    );
+                    self.queue.submit(std::iter::once(encoder.finish()));
+                }
+                output.present();
+            }
         };
         return self.cancel();
     }
@@ -180,7 +317,7 @@ impl nvgx::RendererDevice for Renderer {
         fringe: f32,
         bounds_offset: Option<usize>,
         paths: &[nvgx::PathSlice],
-    ) -> anyhow::Result<()> {
+    ) -> nvgx::Result<()> {
         let path_offset = self.resources.paths.len();
         let mut fill_vertex_count = 0;
         self.resources.paths.extend(paths.iter().filter_map(|p| {
@@ -215,6 +352,7 @@ impl nvgx::RendererDevice for Renderer {
             },
             uniform_offset: self.resources.render_unifrom.offset(),
             blend_func: composite_operation,
+            blend_color: None,
             vertex_buffer,
             instances,
         };
@@ -247,7 +385,7 @@ impl nvgx::RendererDevice for Renderer {
         fringe: f32,
         stroke_width: f32,
         paths: &[nvgx::PathSlice],
-    ) -> anyhow::Result<()> {
+    ) -> nvgx::Result<()> {
         let path_offset = self.resources.paths.len();
 
         self.resources.paths.extend(paths.iter().filter_map(|p| {
@@ -264,6 +402,7 @@ impl nvgx::RendererDevice for Renderer {
             path_range: path_offset..self.resources.paths.len(),
             uniform_offset: self.resources.render_unifrom.offset(),
             blend_func: composite_operation,
+            blend_color: None,
             vertex_buffer,
             triangle: VertexSlice::default(),
             instances,
@@ -289,7 +428,7 @@ impl nvgx::RendererDevice for Renderer {
         composite_operation: nvgx::CompositeOperationState,
         scissor: &nvgx::Scissor,
         slice: VertexSlice,
-    ) -> anyhow::Result<()> {
+    ) -> nvgx::Result<()> {
         let call = Call {
             call_type: CallType::Triangles,
             image: paint.image,
@@ -297,6 +436,7 @@ impl nvgx::RendererDevice for Renderer {
             path_range: 0..0,
             uniform_offset: self.resources.render_unifrom.offset(),
             blend_func: composite_operation,
+            blend_color: None,
             vertex_buffer,
             instances,
         };
@@ -309,8 +449,41 @@ impl nvgx::RendererDevice for Renderer {
         Ok(())
     }
 
-    fn clear(&mut self, color: nvgx::Color) -> anyhow::Result<()> {
+    fn lcd_triangles(
+        &mut self,
+        vertex_buffer: Option<Self::VertexBuffer>,
+        instances: Option<(Self::VertexBuffer, Range<u32>)>,
+        paint: &nvgx::PaintPattern,
+        scissor: &nvgx::Scissor,
+        slice: VertexSlice,
+    ) -> nvgx::Result<()> {
+        let call = Call {
+            call_type: CallType::LcdTriangles,
+            image: paint.image,
+            triangle: slice,
+            path_range: 0..0,
+            uniform_offset: self.resources.render_unifrom.offset(),
+            blend_func: Default::default(),
+            blend_color: Some(paint.inner_color),
+            vertex_buffer,
+            instances,
+        };
+
+        self.resources.calls.push(call);
+
+        self.resources.render_unifrom.value.push(
+            RenderCommand::new(self, paint, scissor, 1.0, 1.0, -1.0).set_type(ShaderType::LcdImage),
+        );
+        Ok(())
+    }
+
+    fn clear(&mut self, color: nvgx::Color) -> nvgx::Result<()> {
         self.cancel()?;
+        // overdraw adds up from black
+        let color = match self.resources.debug_view {
+            nvgx::DebugView::Overdraw => nvgx::Color::rgba(0.0, 0.0, 0.0, 1.0),
+            _ => color,
+        };
         self.clear_cmd = Some(wgpu::Color {
             r: color.r as f64,
             g: color.g as f64,
@@ -329,7 +502,7 @@ impl nvgx::RendererDevice for Renderer {
         composite_operation: nvgx::CompositeOperationState,
         scissor: &nvgx::Scissor,
         paths: &[nvgx::PathSlice],
-    ) -> anyhow::Result<()> {
+    ) -> nvgx::Result<()> {
         let path_offset = self.resources.paths.len();
 
         self.resources.paths.extend(paths.iter().filter_map(|p| {
@@ -346,6 +519,7 @@ impl nvgx::RendererDevice for Renderer {
             path_range: path_offset..self.resources.paths.len(),
             uniform_offset: self.resources.render_unifrom.offset(),
             blend_func: composite_operation,
+            blend_color: None,
             vertex_buffer,
             triangle: VertexSlice::default(),
             instances,
@@ -360,3 +534,12 @@ impl nvgx::RendererDevice for Renderer {
         Ok(())
     }
 }
+
+/// Texture of the next frame, a lost or outdated surface has to be configured
+/// again by [`nvgx::RendererDevice::resize`]
+fn surface_texture(surface: &wgpu::Surface) -> nvgx::Result<wgpu::SurfaceTexture> {
+    surface.get_current_texture().map_err(|err| match err {
+        wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated => nvgx::Error::SurfaceLost,
+        err => nvgx::Error::backend(err),
+    })
+}
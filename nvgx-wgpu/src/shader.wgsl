@@ -7,12 +7,18 @@ struct InstanceInput {
     @location(2) model_matrix_0: vec2<f32>,
     @location(3) model_matrix_1: vec2<f32>,
     @location(4) model_matrix_2: vec2<f32>,
+    @location(5) color: vec4f,
+    // paint offset and user data
+    @location(6) data: vec4f,
 }
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4f,
     @location(0) ftcoord: vec2f,
     @location(1) fpos: vec2f,
+    // premultiplied instance color
+    @location(2) tint: vec4f,
+    @location(3) data: vec4f,
 }
 
 @group(0) @binding(0)
@@ -29,6 +35,8 @@ fn vs_main(vert_in: VertexInput, inst_in: InstanceInput) -> VertexOutput {
     var out: VertexOutput;
     out.ftcoord = vert_in.tcoord;
     out.fpos = v.xy;
+    out.tint = vec4f(inst_in.color.rgb * inst_in.color.a, inst_in.color.a);
+    out.data = inst_in.data;
     out.clip_position = vec4f(2.0 * v.x / view_size.x - 1.0, 1.0 - 2.0 * v.y / view_size.y, 0.0, 1.0);
     return out;
 }
@@ -37,6 +45,8 @@ const ShaderTypeFillGradient: u32 = 0;
 const ShaderTypeFillImage: u32 = 1;
 const ShaderTypeSimple: u32 = 2; // for stencil
 const ShaderTypeImage: u32 = 3;
+const ShaderTypeLcdImage: u32 = 4;
+const ShaderTypeDebug: u32 = 5;
 
 struct RenderUnifrom {
     scissor_mat: mat3x3f,
@@ -87,18 +97,18 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4f {
 
     let render_type = render_uniform.render_type;
     if render_type == ShaderTypeFillGradient {
-        let pt = (render_uniform.paint_mat * vec3(in.fpos, 1.0)).xy;
+        let pt = (render_uniform.paint_mat * vec3(in.fpos - in.data.xy, 1.0)).xy;
         let d = clamp((sdroundrect(pt, render_uniform.extent, render_uniform.radius) + render_uniform.feather * 0.5) / render_uniform.feather, 0.0, 1.0);
-        return mix(render_uniform.inner_color, render_uniform.outer_color, d) * stroke_alpha * scissor;
+        return mix(render_uniform.inner_color, render_uniform.outer_color, d) * stroke_alpha * scissor * in.tint;
     } else if render_type == ShaderTypeFillImage {
-        let pt = (render_uniform.paint_mat * vec3(in.fpos, 1.0)).xy / render_uniform.extent;
+        let pt = (render_uniform.paint_mat * vec3(in.fpos - in.data.xy, 1.0)).xy / render_uniform.extent;
         var color = textureSample(frag_texture, frag_sampler, pt);
         if (render_uniform.texture_type == 1) {
             color = vec4(color.xyz * color.w, color.w);
         } else if (render_uniform.texture_type == 2) {
             color = vec4(color.x);
         }
-        return color * render_uniform.inner_color * stroke_alpha * scissor;
+        return color * render_uniform.inner_color * stroke_alpha * scissor * in.tint;
     } else if render_type == ShaderTypeImage {
         var color = textureSample(frag_texture, frag_sampler, in.ftcoord);
         if (render_uniform.texture_type == 1) {
@@ -106,7 +116,19 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4f {
         } else if (render_uniform.texture_type == 2) { 
             color = vec4(color.x);
         }
-        return color * scissor * render_uniform.inner_color;
+        return color * scissor * render_uniform.inner_color * in.tint;
+    } else if render_type == ShaderTypeLcdImage {
+        // LCD text, one texel per subpixel
+        let tc = vec2i(in.ftcoord * vec2f(textureDimensions(frag_texture)));
+        let coverage = vec3f(
+            textureLoad(frag_texture, tc - vec2i(1, 0), 0).x,
+            textureLoad(frag_texture, tc, 0).x,
+            textureLoad(frag_texture, tc + vec2i(1, 0), 0).x,
+        ) * render_uniform.inner_color.a * scissor;
+        return vec4f(coverage, max(coverage.r, max(coverage.g, coverage.b)));
+    } else if render_type == ShaderTypeDebug {
+        // debug views, flat color
+        return render_uniform.inner_color;
     }
     // for stencil
     return vec4f(1.0, 1.0, 1.0, 1.0);
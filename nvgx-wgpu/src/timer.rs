@@ -0,0 +1,115 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const TIMESTAMPS_SIZE: u64 = 2 * std::mem::size_of::<u64>() as u64;
+
+/// GPU time of render passes, measured by timestamp queries and read back
+/// without waiting. Passes are not measured while a readback is pending.
+pub(crate) struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    /// Whether mapping the readback buffer succeeded, once it is done
+    mapped: Arc<Mutex<Option<bool>>>,
+    /// The readback buffer is in use by a measured pass
+    pending: bool,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+    last: Option<Duration>,
+}
+
+impl GpuTimer {
+    /// `None` when the device lacks `Features::TIMESTAMP_QUERY`
+    pub(crate) fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<GpuTimer> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("NVG Timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("NVG Timestamp Resolve Buffer"),
+            size: TIMESTAMPS_SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("NVG Timestamp Readback Buffer"),
+            size: TIMESTAMPS_SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Some(GpuTimer {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            mapped: Default::default(),
+            pending: false,
+            period: queue.get_timestamp_period(),
+            last: None,
+        })
+    }
+
+    /// Timestamps of the next render pass, `None` while a readback is pending
+    pub(crate) fn timestamp_writes(&self) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        if self.pending {
+            return None;
+        }
+        Some(wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(0),
+            end_of_pass_write_index: Some(1),
+        })
+    }
+
+    /// Copies the timestamps written by a measured pass for reading
+    pub(crate) fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.resolve_query_set(&self.query_set, 0..2, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            TIMESTAMPS_SIZE,
+        );
+    }
+
+    /// Starts reading the timestamps back, after the measured pass was
+    /// submitted
+    pub(crate) fn submitted(&mut self) {
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                *mapped.lock().unwrap() = Some(result.is_ok());
+            });
+        self.pending = true;
+    }
+
+    /// Time of the latest measured pass whose timestamps were read back
+    pub(crate) fn poll(&mut self, device: &wgpu::Device) -> Option<Duration> {
+        if !self.pending {
+            return self.last;
+        }
+        let _ = device.poll(wgpu::PollType::Poll);
+        let mapped = match self.mapped.lock().unwrap().take() {
+            Some(mapped) => mapped,
+            None => return self.last,
+        };
+        if mapped {
+            {
+                let data = self.readback_buffer.slice(..).get_mapped_range();
+                let timestamps: &[u64] = bytemuck::cast_slice(&data);
+                let ticks = timestamps[1].saturating_sub(timestamps[0]);
+                self.last = Some(Duration::from_nanos(
+                    (ticks as f64 * self.period as f64) as u64,
+                ));
+            }
+            self.readback_buffer.unmap();
+        }
+        self.pending = false;
+        self.last
+    }
+}
@@ -9,6 +9,8 @@ pub enum ShaderType {
     FillImage,
     Simple,
     Image,
+    LcdImage,
+    Debug,
 }
 
 #[repr(C)]
@@ -0,0 +1,315 @@
+use super::Context;
+use crate::{BufferUsage, RendererDevice, Vertex};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Bytes of freed buffers kept for reuse by default, see
+/// [`Context::set_buffer_pool_limit`]
+const DEFAULT_POOL_LIMIT: usize = 4 * 1024 * 1024;
+
+type ReturnedBuffers<R> = Arc<Mutex<Vec<FreeBuffer<R>>>>;
+
+/// A vertex buffer of the pool, not used by any path, instances or text mesh
+struct FreeBuffer<R: RendererDevice> {
+    buffer: R::VertexBuffer,
+    /// Bytes held by the buffer
+    size: usize,
+    /// Bytes asked for when the buffer was created
+    requested: usize,
+    usage: BufferUsage,
+}
+
+/// Vertex buffer of a path, instances or text mesh, handed back to the pool
+/// of its context when dropped
+pub(crate) struct PooledBuffer<R: RendererDevice> {
+    pub(crate) buffer: R::VertexBuffer,
+    /// Bytes held by the buffer, as reported by the renderer
+    size: usize,
+    /// Bytes asked for when the buffer was created
+    requested: usize,
+    usage: BufferUsage,
+    returned: ReturnedBuffers<R>,
+}
+
+impl<R: RendererDevice> Drop for PooledBuffer<R> {
+    fn drop(&mut self) {
+        if let Ok(mut returned) = self.returned.lock() {
+            returned.push(FreeBuffer {
+                buffer: self.buffer.clone(),
+                size: self.size,
+                requested: self.requested,
+                usage: self.usage,
+            });
+        }
+    }
+}
+
+/// Vertex buffers owned by a context
+pub(super) struct BufferPool<R: RendererDevice> {
+    /// Buffers dropped since the last frame, which may still be drawn from
+    returned: ReturnedBuffers<R>,
+    /// Buffers free for reuse, oldest first
+    free: VecDeque<FreeBuffer<R>>,
+    limit: usize,
+    stats: BufferStats,
+}
+
+impl<R: RendererDevice> Default for BufferPool<R> {
+    fn default() -> Self {
+        BufferPool {
+            returned: Default::default(),
+            free: VecDeque::new(),
+            limit: DEFAULT_POOL_LIMIT,
+            stats: BufferStats::default(),
+        }
+    }
+}
+
+/// Vertex buffers of paths, instances and text meshes
+#[derive(Debug, Copy, Clone, Default)]
+pub struct BufferStats {
+    /// Buffers not deleted yet, including the pooled ones
+    pub live_buffers: usize,
+    /// Bytes of the live buffers, as allocated by the renderer when it
+    /// reports them
+    pub live_bytes: usize,
+    /// Buffers kept for reuse
+    pub pooled_buffers: usize,
+    pub pooled_bytes: usize,
+}
+
+impl<R: RendererDevice> Context<R> {
+    pub fn buffer_stats(&self) -> BufferStats {
+        self.buffers.stats
+    }
+
+    /// Bytes of freed vertex buffers kept for reuse, the oldest ones above
+    /// are deleted at the beginning of each frame
+    pub fn set_buffer_pool_limit(&mut self, bytes: usize) {
+        self.buffers.limit = bytes;
+    }
+
+    /// Deletes the buffers kept for reuse
+    pub fn trim_buffer_pool(&mut self) -> crate::Result<()> {
+        self.delete_pooled_buffers(0)
+    }
+
+    /// Uploads `data` into `slot`, replacing its buffer with one of the pool
+    /// when it is too small
+    pub(super) fn upload_vertex_buffer(
+        &mut self,
+        slot: &mut Option<PooledBuffer<R>>,
+        data: &[u8],
+        usage: BufferUsage,
+    ) -> crate::Result<()> {
+        if usage == BufferUsage::Vertex {
+            self.stats.uploaded_vertices += data.len() / std::mem::size_of::<Vertex>();
+        }
+        if let Some(pooled) = slot {
+            if pooled.size >= data.len() {
+                self.renderer
+                    .update_vertex_buffer(Some(&pooled.buffer), data)?;
+                // renderers may reallocate buffers on updates
+                let size = self.renderer.vertex_buffer_size(&pooled.buffer);
+                let size = size.unwrap_or(pooled.size);
+                self.buffers.stats.live_bytes = self.buffers.stats.live_bytes + size - pooled.size;
+                pooled.size = size;
+                return Ok(());
+            }
+        }
+        // pooled buffers asked for more than twice the size would waste
+        // memory
+        let found = self.buffers.free.iter().position(|free| {
+            free.usage == usage && free.size >= data.len() && free.requested <= data.len() * 2
+        });
+        let free = match found {
+            Some(index) => {
+                let free = self.buffers.free.remove(index).unwrap();
+                self.buffers.stats.pooled_buffers -= 1;
+                self.buffers.stats.pooled_bytes -= free.size;
+                free
+            }
+            None => {
+                let buffer = self.renderer.create_vertex_buffer(data.len(), usage)?;
+                self.buffers.stats.live_buffers += 1;
+                self.buffers.stats.live_bytes += data.len();
+                FreeBuffer {
+                    buffer,
+                    size: data.len(),
+                    requested: data.len(),
+                    usage,
+                }
+            }
+        };
+        self.renderer
+            .update_vertex_buffer(Some(&free.buffer), data)?;
+        let size = self.renderer.vertex_buffer_size(&free.buffer);
+        let size = size.unwrap_or(free.size);
+        self.buffers.stats.live_bytes = self.buffers.stats.live_bytes + size - free.size;
+        // the replaced buffer returns to the pool at the next frame
+        *slot = Some(PooledBuffer {
+            buffer: free.buffer,
+            size,
+            requested: free.requested,
+            usage,
+            returned: self.buffers.returned.clone(),
+        });
+        Ok(())
+    }
+
+    /// Makes the buffers dropped before the frame available for reuse
+    pub(super) fn collect_buffers(&mut self) -> crate::Result<()> {
+        let returned = std::mem::take(&mut *self.buffers.returned.lock().unwrap());
+        for free in returned {
+            self.buffers.stats.pooled_buffers += 1;
+            self.buffers.stats.pooled_bytes += free.size;
+            self.buffers.free.push_back(free);
+        }
+        self.delete_pooled_buffers(self.buffers.limit)
+    }
+
+    fn delete_pooled_buffers(&mut self, limit: usize) -> crate::Result<()> {
+        while self.buffers.stats.pooled_bytes > limit {
+            let free = match self.buffers.free.pop_front() {
+                Some(free) => free,
+                None => break,
+            };
+            self.buffers.stats.pooled_buffers -= 1;
+            self.buffers.stats.pooled_bytes -= free.size;
+            self.buffers.stats.live_buffers -= 1;
+            self.buffers.stats.live_bytes -= free.size;
+            self.renderer.delete_vertex_buffer(free.buffer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::tests::NullRenderer;
+
+    fn context() -> Context<NullRenderer> {
+        Context::create(NullRenderer::default()).unwrap()
+    }
+
+    fn upload(ctx: &mut Context<NullRenderer>, len: usize) -> Option<PooledBuffer<NullRenderer>> {
+        let mut slot = None;
+        ctx.upload_vertex_buffer(&mut slot, &vec![0; len], BufferUsage::Vertex)
+            .unwrap();
+        slot
+    }
+
+    fn buffer(slot: &Option<PooledBuffer<NullRenderer>>) -> usize {
+        slot.as_ref().unwrap().buffer
+    }
+
+    #[test]
+    fn dropped_buffers_are_reused() {
+        let mut ctx = context();
+        let slot = upload(&mut ctx, 1000);
+        let first = buffer(&slot);
+        drop(slot);
+
+        // the buffer may still be drawn from until the next frame
+        let stats = ctx.buffer_stats();
+        assert_eq!((stats.live_buffers, stats.live_bytes), (1, 1000));
+        assert_eq!(stats.pooled_buffers, 0);
+        assert_ne!(buffer(&upload(&mut ctx, 1000)), first);
+
+        ctx.collect_buffers().unwrap();
+        let stats = ctx.buffer_stats();
+        assert_eq!((stats.live_buffers, stats.live_bytes), (2, 2000));
+        assert_eq!((stats.pooled_buffers, stats.pooled_bytes), (2, 2000));
+
+        let slot = upload(&mut ctx, 600);
+        assert_eq!(buffer(&slot), first);
+        let stats = ctx.buffer_stats();
+        assert_eq!((stats.live_buffers, stats.live_bytes), (2, 2000));
+        assert_eq!((stats.pooled_buffers, stats.pooled_bytes), (1, 1000));
+    }
+
+    #[test]
+    fn reuse_window() {
+        let mut ctx = context();
+        drop(upload(&mut ctx, 1000));
+        ctx.collect_buffers().unwrap();
+
+        // too large, more than twice the size or of another usage
+        for len in [1001, 499] {
+            let slot = upload(&mut ctx, len);
+            assert_eq!(buffer(&slot), ctx.renderer().buffers.len() - 1);
+        }
+        let mut slot = None;
+        ctx.upload_vertex_buffer(&mut slot, &[0; 800], BufferUsage::Instance)
+            .unwrap();
+        assert_eq!(buffer(&slot), 3);
+        assert_eq!(ctx.buffer_stats().pooled_buffers, 1);
+
+        assert_eq!(buffer(&upload(&mut ctx, 500)), 0);
+        assert_eq!(ctx.buffer_stats().pooled_buffers, 0);
+    }
+
+    #[test]
+    fn slots_keep_fitting_buffers() {
+        let mut ctx = context();
+        let mut slot = upload(&mut ctx, 1000);
+        ctx.upload_vertex_buffer(&mut slot, &[0; 1000], BufferUsage::Vertex)
+            .unwrap();
+        assert_eq!(buffer(&slot), 0);
+
+        // the outgrown buffer returns to the pool at the next frame
+        ctx.upload_vertex_buffer(&mut slot, &[0; 1500], BufferUsage::Vertex)
+            .unwrap();
+        assert_eq!(buffer(&slot), 1);
+        assert_eq!(ctx.buffer_stats().live_bytes, 2500);
+        ctx.collect_buffers().unwrap();
+        assert_eq!(ctx.buffer_stats().pooled_bytes, 1000);
+    }
+
+    #[test]
+    fn eviction_above_limit() {
+        let mut ctx = context();
+        ctx.set_buffer_pool_limit(2500);
+        let slots: Vec<_> = (0..3).map(|_| upload(&mut ctx, 1000)).collect();
+        drop(slots);
+        ctx.collect_buffers().unwrap();
+
+        // the oldest buffer is deleted
+        assert_eq!(ctx.renderer().buffers, vec![None, Some(1000), Some(1000)]);
+        let stats = ctx.buffer_stats();
+        assert_eq!((stats.live_buffers, stats.live_bytes), (2, 2000));
+        assert_eq!((stats.pooled_buffers, stats.pooled_bytes), (2, 2000));
+
+        ctx.trim_buffer_pool().unwrap();
+        assert!(ctx.renderer().buffers.iter().all(Option::is_none));
+        let stats = ctx.buffer_stats();
+        assert_eq!((stats.live_buffers, stats.live_bytes), (0, 0));
+        assert_eq!((stats.pooled_buffers, stats.pooled_bytes), (0, 0));
+    }
+
+    #[test]
+    fn reported_sizes() {
+        let mut ctx = context();
+        ctx.renderer_mut().buffer_alignment = 1024;
+        let slot = upload(&mut ctx, 100);
+        assert_eq!(ctx.buffer_stats().live_bytes, 1024);
+        drop(slot);
+        ctx.collect_buffers().unwrap();
+        assert_eq!(ctx.buffer_stats().pooled_bytes, 1024);
+
+        // the rounded up buffer holds more than it was created for
+        let mut slot = upload(&mut ctx, 900);
+        assert_eq!(buffer(&slot), 0);
+        ctx.upload_vertex_buffer(&mut slot, &[0; 1000], BufferUsage::Vertex)
+            .unwrap();
+        assert_eq!(buffer(&slot), 0);
+        let stats = ctx.buffer_stats();
+        assert_eq!((stats.live_buffers, stats.live_bytes), (1, 1024));
+        assert_eq!(stats.pooled_bytes, 0);
+
+        let _slot = upload(&mut ctx, 2000);
+        let stats = ctx.buffer_stats();
+        assert_eq!((stats.live_buffers, stats.live_bytes), (2, 3072));
+    }
+}
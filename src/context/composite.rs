@@ -1,4 +1,5 @@
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BlendFactor {
     Zero,
     One,
@@ -14,6 +15,7 @@ pub enum BlendFactor {
 }
 
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BasicCompositeOperation {
     SrcOver,
     SrcIn,
@@ -29,6 +31,7 @@ pub enum BasicCompositeOperation {
 }
 
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CompositeOperation {
     Basic(BasicCompositeOperation),
     BlendFunc {
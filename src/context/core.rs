@@ -1,6 +1,15 @@
+use super::buffer_pool::BufferPool;
+use super::core_image::ImageRegistry;
 use super::core_path_cache::PathCommandsWithCache;
-use super::{Align, BasicCompositeOperation, CompositeOperation, CompositeOperationState};
-use crate::fonts::{FontId, Fonts, LayoutChar};
+use super::damage::DamageTracker;
+use super::picture::{DrawCommand, PictureRecorder};
+#[cfg(feature = "trace")]
+use super::trace::{TraceCommand, TraceRecorder};
+use super::{
+    Align, BasicCompositeOperation, CompositeOperation, CompositeOperationState, FrameStats,
+    TextAntialias, TextDecoration, WritingMode,
+};
+use crate::fonts::{FontId, Fonts, LayoutChar, SpanRun};
 use crate::paint::{LineCap, LineJoin, PaintPattern};
 use crate::renderer::Scissor;
 use crate::{Color, Extent, Paint, PathFillType, Point, Rect, RendererDevice, Transform};
@@ -16,6 +25,10 @@ pub(super) struct State {
     pub(super) letter_spacing: f32,
     pub(super) line_height: f32,
     pub(super) text_align: Align,
+    pub(super) writing_mode: WritingMode,
+    pub(super) text_decoration: TextDecoration,
+    pub(super) text_decoration_skip_ink: bool,
+    pub(super) text_antialias: TextAntialias,
     pub(super) font_id: FontId,
 }
 
@@ -37,6 +50,10 @@ impl Default for State {
             letter_spacing: 0.0,
             line_height: 1.0,
             text_align: Align::LEFT | Align::BASELINE,
+            writing_mode: WritingMode::Horizontal,
+            text_decoration: TextDecoration::empty(),
+            text_decoration_skip_ink: false,
+            text_antialias: TextAntialias::Grayscale,
             font_id: 0,
         }
     }
@@ -52,14 +69,18 @@ pub struct Context<R: RendererDevice> {
     pub(super) device_pixel_ratio: f32,
     pub(super) fonts: Fonts,
     pub(super) layout_chars: Vec<LayoutChar>,
-    pub(super) draw_call_count: usize,
-    pub(super) fill_triangles_count: usize,
-    pub(super) stroke_triangles_count: usize,
-    pub(super) text_triangles_count: usize,
+    pub(super) span_runs: Vec<SpanRun>,
+    pub(super) stats: FrameStats,
+    pub(super) damage: DamageTracker,
+    pub(super) images: ImageRegistry,
+    pub(super) buffers: BufferPool<R>,
+    pub(super) pictures: PictureRecorder,
+    #[cfg(feature = "trace")]
+    pub(super) trace: TraceRecorder,
 }
 
 impl<R: RendererDevice> Context<R> {
-    pub fn create(mut renderer: R) -> anyhow::Result<Context<R>> {
+    pub fn create(mut renderer: R) -> crate::Result<Context<R>> {
         let fonts = Fonts::new(&mut renderer)?;
         Ok(Context {
             path_cache: PathCommandsWithCache::default(),
@@ -71,10 +92,14 @@ impl<R: RendererDevice> Context<R> {
             device_pixel_ratio: 1.0,
             fonts,
             layout_chars: Default::default(),
-            draw_call_count: 0,
-            fill_triangles_count: 0,
-            stroke_triangles_count: 0,
-            text_triangles_count: 0,
+            span_runs: Default::default(),
+            stats: Default::default(),
+            damage: Default::default(),
+            images: Default::default(),
+            buffers: Default::default(),
+            pictures: Default::default(),
+            #[cfg(feature = "trace")]
+            trace: Default::default(),
         })
     }
 
@@ -88,7 +113,8 @@ impl<R: RendererDevice> Context<R> {
         &mut self.renderer
     }
 
-    pub fn resize(&mut self, width: u32, height: u32) -> anyhow::Result<()> {
+    pub fn resize(&mut self, width: u32, height: u32) -> crate::Result<()> {
+        self.invalidate_all();
         self.renderer.resize(width, height)
     }
 
@@ -98,7 +124,14 @@ impl<R: RendererDevice> Context<R> {
         self.device_pixel_ratio = ratio;
     }
 
-    pub fn clear(&mut self, color: Color) -> anyhow::Result<()> {
+    pub fn clear(&mut self, color: Color) -> crate::Result<()> {
+        self.record(|| DrawCommand::Clear(color));
+        if self.pictures.active() {
+            return Ok(());
+        }
+        if let Some(area) = self.damage() {
+            return self.clear_damage(area, color);
+        }
         return self.renderer.clear(color);
     }
 
@@ -106,34 +139,52 @@ impl<R: RendererDevice> Context<R> {
         &mut self,
         window_extent: E,
         device_pixel_ratio: f32,
-    ) -> anyhow::Result<()> {
+    ) -> crate::Result<()> {
+        let window_extent = window_extent.into();
+        #[cfg(feature = "trace")]
+        self.record_trace(|| TraceCommand::BeginFrame(window_extent, device_pixel_ratio));
+        // framebuffer frames are nested in a frame still using the images
+        // and buffers
+        if self.damage.suspended == 0 {
+            self.release_images()?;
+            self.collect_buffers()?;
+        }
         self.states.clear();
         self.states.push(Default::default());
         self.set_device_pixel_ratio(device_pixel_ratio);
-        self.renderer
-            .viewport(window_extent.into(), device_pixel_ratio)?;
-        self.draw_call_count = 0;
-        self.fill_triangles_count = 0;
-        self.stroke_triangles_count = 0;
-        self.text_triangles_count = 0;
+        self.renderer.viewport(window_extent, device_pixel_ratio)?;
+        self.begin_damage(window_extent)?;
         Ok(())
     }
 
-    pub fn cancel_frame(&mut self) -> anyhow::Result<()> {
+    pub fn cancel_frame(&mut self) -> crate::Result<()> {
         self.renderer.cancel()?;
+        #[cfg(feature = "trace")]
+        self.cancel_capture();
         Ok(())
     }
 
-    pub fn end_frame(&mut self) -> anyhow::Result<()> {
+    /// Draws the frame and returns what it took, including the uploads since
+    /// the previous frame
+    pub fn end_frame(&mut self) -> crate::Result<FrameStats> {
         let cache = &mut self.path_cache.cache;
         self.renderer
             .update_vertex_buffer(None, bytemuck::cast_slice(&cache.vertices))?;
         self.renderer.flush()?;
+        self.stats.renderer_calls = self.renderer.flushed_calls();
+        self.stats.uploaded_vertices += cache.vertices.len();
         cache.reset();
-        Ok(())
+        #[cfg(feature = "trace")]
+        self.finish_capture()?;
+        self.stats.texture_uploads += self.fonts.uploads;
+        self.fonts.uploads = 0;
+        self.stats.atlas_usage = self.fonts.atlas_usage();
+        self.stats.gpu_time = self.renderer.gpu_time();
+        Ok(std::mem::take(&mut self.stats))
     }
 
     pub fn save(&mut self) {
+        self.record(|| DrawCommand::Save);
         if let Some(last) = self.states.last() {
             let last = last.clone();
             self.states.push(last);
@@ -141,7 +192,8 @@ impl<R: RendererDevice> Context<R> {
     }
 
     pub fn restore(&mut self) {
-        if self.states.len() <= 1 {
+        self.record(|| DrawCommand::Restore);
+        if self.states.len() <= self.pictures.state_depth() {
             return;
         }
         self.states.pop();
@@ -155,40 +207,79 @@ impl<R: RendererDevice> Context<R> {
         self.states.last_mut().unwrap()
     }
 
+    /// Adds `cmd` to the picture being recorded, or else to the frame being
+    /// captured
+    #[inline]
+    pub(super) fn record<F: FnOnce() -> DrawCommand>(&mut self, cmd: F) {
+        if self.pictures.active() {
+            self.pictures.push(cmd());
+            return;
+        }
+        #[cfg(feature = "trace")]
+        self.record_trace(|| TraceCommand::Draw(cmd()));
+        #[cfg(not(feature = "trace"))]
+        let _ = cmd;
+    }
+
+    /// Runs `f` without capturing the calls it makes
+    pub(super) fn unrecorded<T, F: FnOnce(&mut Self) -> T>(&mut self, f: F) -> T {
+        self.pictures.suspended += 1;
+        #[cfg(feature = "trace")]
+        {
+            self.trace.suspended += 1;
+        }
+        let result = f(self);
+        self.pictures.suspended -= 1;
+        #[cfg(feature = "trace")]
+        {
+            self.trace.suspended -= 1;
+        }
+        result
+    }
+
     pub fn reset(&mut self) {
+        self.record(|| DrawCommand::Reset);
         *self.state_mut() = Default::default();
     }
 
     pub fn shape_antialias(&mut self, enabled: bool) {
+        self.record(|| DrawCommand::ShapeAntialias(enabled));
         self.state_mut().paint.antialias = enabled;
     }
 
     pub fn stroke_width(&mut self, width: f32) {
+        self.record(|| DrawCommand::StrokeWidth(width));
         self.state_mut().paint.stroke_width = width;
     }
 
     pub fn miter_limit(&mut self, limit: f32) {
+        self.record(|| DrawCommand::MiterLimit(limit));
         self.state_mut().paint.miter_limit = limit;
     }
 
     pub fn line_cap(&mut self, cap: LineCap) {
+        self.record(|| DrawCommand::LineCap(cap));
         self.state_mut().paint.line_cap = cap;
     }
 
     pub fn line_join(&mut self, join: LineJoin) {
+        self.record(|| DrawCommand::LineJoin(join));
         self.state_mut().paint.line_join = join;
     }
 
     pub fn global_alpha(&mut self, alpha: f32) {
+        self.record(|| DrawCommand::GlobalAlpha(alpha));
         self.state_mut().paint.alpha = alpha;
     }
 
     pub fn transform(&mut self, xform: Transform) {
+        self.record(|| DrawCommand::Transform(xform));
         let state = self.state_mut();
         state.xform = xform * state.xform;
     }
 
     pub fn reset_transform(&mut self) {
+        self.record(|| DrawCommand::ResetTransform);
         self.state_mut().xform = Transform::identity();
     }
 
@@ -218,22 +309,26 @@ impl<R: RendererDevice> Context<R> {
 
     pub fn stroke_paint<T: Into<PaintPattern>>(&mut self, paint: T) {
         let mut paint = paint.into();
+        self.record(|| DrawCommand::StrokePaint(paint));
         paint.xform *= self.state().xform;
         self.state_mut().paint.stroke = paint;
     }
 
     pub fn fill_paint<T: Into<PaintPattern>>(&mut self, paint: T) {
         let mut paint = paint.into();
+        self.record(|| DrawCommand::FillPaint(paint));
         paint.xform *= self.state().xform;
         self.state_mut().paint.fill = paint;
     }
 
     pub fn fill_type(&mut self, fill_type: PathFillType) {
+        self.record(|| DrawCommand::FillType(fill_type));
         self.state_mut().fill_type = fill_type;
     }
 
     pub fn scissor<T: Into<Rect>>(&mut self, rect: T) {
         let rect = rect.into();
+        self.record(|| DrawCommand::Scissor(rect));
         let state = self.state_mut();
         let x = rect.xy.x;
         let y = rect.xy.y;
@@ -249,10 +344,11 @@ impl<R: RendererDevice> Context<R> {
 
     pub fn intersect_scissor<T: Into<Rect>>(&mut self, rect: T) {
         let rect = rect.into();
+        self.record(|| DrawCommand::IntersectScissor(rect));
         let state = self.state_mut();
 
         if state.scissor.extent.width < 0.0 {
-            self.scissor(rect);
+            self.unrecorded(|ctx| ctx.scissor(rect));
             return;
         }
 
@@ -264,16 +360,16 @@ impl<R: RendererDevice> Context<R> {
         let pxform = state.scissor.xform * invxorm;
         let tex = ex * pxform.0[0].abs() + ey * pxform.0[2].abs();
         let tey = ex * pxform.0[1].abs() + ey * pxform.0[3].abs();
-        self.scissor(
-            Rect::new(
-                Point::new(pxform.0[4] - tex, pxform.0[5] - tey),
-                Extent::new(tex * 2.0, tey * 2.0),
-            )
-            .intersect(rect),
-        );
+        let rect = Rect::new(
+            Point::new(pxform.0[4] - tex, pxform.0[5] - tey),
+            Extent::new(tex * 2.0, tey * 2.0),
+        )
+        .intersect(rect);
+        self.unrecorded(|ctx| ctx.scissor(rect));
     }
 
     pub fn reset_scissor(&mut self) {
+        self.record(|| DrawCommand::ResetScissor);
         let state = self.state_mut();
         state.scissor.xform = Transform::default();
         state.scissor.extent.width = -1.0;
@@ -281,6 +377,7 @@ impl<R: RendererDevice> Context<R> {
     }
 
     pub fn global_composite_operation(&mut self, op: CompositeOperation) {
+        self.record(|| DrawCommand::CompositeOperation(op));
         self.state_mut().composite_operation = op.into();
     }
 }
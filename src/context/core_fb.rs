@@ -5,18 +5,28 @@ use std::{
 
 use crate::{FrameBufferDevice, RenderFrameBufferDevice};
 
-use super::{Context, ImageFlags};
+use super::{Context, FrameStats, ImageFlags};
 
 pub struct FrameBufferContext<'a, 'b, R: RenderFrameBufferDevice> {
     context: &'a mut Context<R>,
+    /// Stats of the frame the framebuffer is drawn in, restored when done
+    outer_stats: FrameStats,
     _fb_marker: std::marker::PhantomData<&'b R::FB>,
 }
 
 impl<'a, 'b, R: RenderFrameBufferDevice> FrameBufferContext<'a, 'b, R> {
-    fn new(context: &'a mut Context<R>, fb: &'b R::FB) -> anyhow::Result<Self> {
+    fn new(context: &'a mut Context<R>, fb: &'b R::FB) -> crate::Result<Self> {
         context.renderer.bind(fb)?;
+        context.damage.suspended += 1;
+        context.stats.texture_uploads += std::mem::take(&mut context.fonts.uploads);
+        let outer_stats = std::mem::take(&mut context.stats);
+        #[cfg(feature = "trace")]
+        {
+            context.trace.suspended += 1;
+        }
         return Ok(FrameBufferContext {
             context,
+            outer_stats,
             _fb_marker: PhantomData::default(),
         });
     }
@@ -25,6 +35,12 @@ impl<'a, 'b, R: RenderFrameBufferDevice> FrameBufferContext<'a, 'b, R> {
 impl<'a, 'b, R: RenderFrameBufferDevice> Drop for FrameBufferContext<'a, 'b, R> {
     fn drop(&mut self) {
         self.context.renderer.unbind().unwrap();
+        self.context.damage.suspended -= 1;
+        self.context.stats = self.outer_stats;
+        #[cfg(feature = "trace")]
+        {
+            self.context.trace.suspended -= 1;
+        }
     }
 }
 
@@ -49,7 +65,7 @@ impl<R: RenderFrameBufferDevice> Context<R> {
         height: u32,
         flags: ImageFlags,
         data: Option<&[u8]>,
-    ) -> anyhow::Result<R::FB> {
+    ) -> crate::Result<R::FB> {
         let image = self.create_image(
             width,
             height,
@@ -69,7 +85,7 @@ impl<R: RenderFrameBufferDevice> Context<R> {
     }
 
     #[inline]
-    pub fn delete_fb(&mut self, fb: R::FB) -> anyhow::Result<()> {
+    pub fn delete_fb(&mut self, fb: R::FB) -> crate::Result<()> {
         let image = fb.image();
         self.renderer.delete_fb(fb)?;
         return self.delete_image(image);
@@ -79,7 +95,7 @@ impl<R: RenderFrameBufferDevice> Context<R> {
     pub fn bind<'a, 'b>(
         &'a mut self,
         fb: &'b R::FB,
-    ) -> anyhow::Result<FrameBufferContext<'a, 'b, R>> {
+    ) -> crate::Result<FrameBufferContext<'a, 'b, R>> {
         return FrameBufferContext::new(self, fb);
     }
 }
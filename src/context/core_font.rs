@@ -1,15 +1,78 @@
+use super::damage::vertex_bounds;
+use super::picture::{DrawCommand, RecordedSpan};
+#[cfg(feature = "trace")]
+use super::trace::TraceCommand;
 use super::Context;
-use super::{Align, TextMetrics};
-use crate::fonts::FontId;
+use super::{
+    Align, FontMetrics, GlyphMetrics, GlyphPosition, GlyphPositioning, TextAntialias,
+    TextDecoration, TextEllipsis, TextMetrics, WritingMode,
+};
+use crate::fonts::{CustomGlyph, FontId, TextSpan};
 use crate::path::cache::Vertex;
-use crate::{Extent, Point, RendererDevice};
+use crate::{
+    Bounds, Color, CompositeOperationState, Extent, PaintPattern, Point, RendererDevice, Transform,
+};
+use std::ops::Range;
+
+/// Whether LCD text can be drawn with `paint`. LCD coverage blends a single
+/// color over the destination, so gradients, images and other composite
+/// operations are drawn with grayscale antialiasing.
+pub(super) fn lcd_paint(
+    paint: &PaintPattern,
+    composite_operation: CompositeOperationState,
+) -> bool {
+    paint.image.is_none()
+        && paint.inner_color == paint.outer_color
+        && composite_operation == CompositeOperationState::default()
+}
+
+pub(super) fn push_quad(
+    vertices: &mut Vec<Vertex>,
+    xform: &Transform,
+    invscale: f32,
+    bounds: &Bounds,
+    uv: &Bounds,
+    rotated: bool,
+) {
+    let lt = xform.transform_point(Point::new(bounds.min.x * invscale, bounds.min.y * invscale));
+    let rt = xform.transform_point(Point::new(bounds.max.x * invscale, bounds.min.y * invscale));
+    let lb = xform.transform_point(Point::new(bounds.min.x * invscale, bounds.max.y * invscale));
+    let rb = xform.transform_point(Point::new(bounds.max.x * invscale, bounds.max.y * invscale));
+
+    // Rotated glyphs are stored upright in the texture and turned 90°
+    // clockwise on screen.
+    let (uv_lt, uv_rt, uv_lb, uv_rb) = if rotated {
+        (
+            (uv.min.x, uv.max.y),
+            (uv.min.x, uv.min.y),
+            (uv.max.x, uv.max.y),
+            (uv.max.x, uv.min.y),
+        )
+    } else {
+        (
+            (uv.min.x, uv.min.y),
+            (uv.max.x, uv.min.y),
+            (uv.min.x, uv.max.y),
+            (uv.max.x, uv.max.y),
+        )
+    };
+
+    vertices.extend([
+        Vertex::new(lt.x, lt.y, uv_lt.0, uv_lt.1),
+        Vertex::new(rb.x, rb.y, uv_rb.0, uv_rb.1),
+        Vertex::new(rt.x, rt.y, uv_rt.0, uv_rt.1),
+        Vertex::new(lt.x, lt.y, uv_lt.0, uv_lt.1),
+        Vertex::new(lb.x, lb.y, uv_lb.0, uv_lb.1),
+        Vertex::new(rb.x, rb.y, uv_rb.0, uv_rb.1),
+    ]);
+}
 
 impl<R: RendererDevice> Context<R> {
     pub fn create_font_from_file<N: Into<String>, P: AsRef<std::path::Path>>(
         &mut self,
         name: N,
         path: P,
-    ) -> anyhow::Result<FontId> {
+    ) -> crate::Result<FontId> {
         self.create_font(name, std::fs::read(path)?)
     }
 
@@ -17,7 +80,7 @@ impl<R: RendererDevice> Context<R> {
         &mut self,
         name: N,
         data: D,
-    ) -> anyhow::Result<FontId> {
+    ) -> crate::Result<FontId> {
         self.fonts.add_font(name, data)
     }
 
@@ -35,40 +98,108 @@ impl<R: RendererDevice> Context<R> {
         }
     }
 
+    /// Draws `glyph` in place of `c` in horizontal text of any font.
+    pub fn add_custom_glyph(&mut self, c: char, glyph: CustomGlyph) -> crate::Result<()> {
+        self.fonts.add_custom_glyph(&self.renderer, c, glyph)
+    }
+
+    pub fn remove_custom_glyph(&mut self, c: char) -> Option<CustomGlyph> {
+        self.fonts.remove_custom_glyph(c)
+    }
+
     pub fn font_size(&mut self, size: f32) {
+        self.record(|| DrawCommand::FontSize(size));
         self.state_mut().font_size = size;
     }
 
     pub fn text_letter_spacing(&mut self, spacing: f32) {
+        self.record(|| DrawCommand::LetterSpacing(spacing));
         self.state_mut().letter_spacing = spacing;
     }
 
     pub fn text_line_height(&mut self, line_height: f32) {
+        self.record(|| DrawCommand::LineHeight(line_height));
         self.state_mut().line_height = line_height;
     }
 
     pub fn text_align(&mut self, align: Align) {
+        self.record(|| DrawCommand::TextAlign(align));
         self.state_mut().text_align = align;
     }
 
+    /// Sets the decoration lines drawn with the text.
+    pub fn text_decoration(&mut self, decoration: TextDecoration) {
+        self.record(|| DrawCommand::TextDecoration(decoration));
+        self.state_mut().text_decoration = decoration;
+    }
+
+    /// Sets whether underlines and overlines are interrupted where they
+    /// would cross the glyphs, e.g. at descenders.
+    pub fn text_decoration_skip_ink(&mut self, skip_ink: bool) {
+        self.record(|| DrawCommand::TextDecorationSkipInk(skip_ink));
+        self.state_mut().text_decoration_skip_ink = skip_ink;
+    }
+
+    /// Sets the direction in which [`Context::text`] advances. Decorations
+    /// are only drawn for horizontal text.
+    pub fn text_writing_mode(&mut self, mode: WritingMode) {
+        self.record(|| DrawCommand::WritingMode(mode));
+        self.state_mut().writing_mode = mode;
+    }
+
+    /// Sets how the coverage of text glyphs is rasterized. LCD antialiasing
+    /// applies to horizontal text only.
+    pub fn text_antialias(&mut self, antialias: TextAntialias) {
+        self.record(|| DrawCommand::TextAntialias(antialias));
+        self.state_mut().text_antialias = antialias;
+    }
+
+    /// Sets where glyphs are placed on the pixel grid, see
+    /// [`crate::Fonts::set_glyph_positioning`].
+    pub fn text_glyph_positioning(&mut self, positioning: GlyphPositioning) {
+        #[cfg(feature = "trace")]
+        self.record_trace(|| TraceCommand::GlyphPositioning(positioning));
+        self.fonts.set_glyph_positioning(positioning);
+    }
+
     pub fn fontid(&mut self, id: FontId) {
+        self.record(|| DrawCommand::FontId(id));
         self.state_mut().font_id = id;
     }
 
     pub fn font<N: AsRef<str>>(&mut self, name: N) {
+        self.record(|| DrawCommand::Font(name.as_ref().to_string()));
         if let Some(id) = self.find_font(name) {
             self.state_mut().font_id = id;
         }
     }
 
-    pub fn text<S: AsRef<str>, P: Into<Point>>(&mut self, pt: P, text: S) -> anyhow::Result<()> {
+    pub fn text<S: AsRef<str>, P: Into<Point>>(&mut self, pt: P, text: S) -> crate::Result<()> {
+        let pt = pt.into();
+        self.record(|| DrawCommand::Text(pt, text.as_ref().to_string()));
+        if self.pictures.active() {
+            return Ok(());
+        }
         let state = self.states.last().unwrap();
         let scale = state.xform.font_scale() * self.device_pixel_ratio;
-        let xform = &state.xform;
-        let invscale = 1.0 / scale;
-        let pt = pt.into();
 
-        self.fonts.layout_text(
+        if state.writing_mode == WritingMode::Vertical {
+            self.fonts.layout_text_vertical(
+                &mut self.renderer,
+                text.as_ref(),
+                state.font_id,
+                (pt.x * scale, pt.y * scale).into(),
+                state.font_size * scale,
+                state.text_align,
+                state.letter_spacing * scale,
+                true,
+                &mut self.layout_chars,
+            )?;
+            let paint = state.paint.fill;
+            return self.draw_glyphs(0..self.layout_chars.len(), &[], paint, false);
+        }
+
+        let run = self.fonts.layout_text(
             &mut self.renderer,
             text.as_ref(),
             state.font_id,
@@ -76,59 +207,338 @@ impl<R: RendererDevice> Context<R> {
             state.font_size * scale,
             state.text_align,
             state.letter_spacing * scale,
-            true,
+            false,
             &mut self.layout_chars,
         )?;
 
+        let paint = state.paint.fill;
+        let lcd = state.text_antialias == TextAntialias::Lcd
+            && lcd_paint(&paint, state.composite_operation);
+        self.fonts
+            .cache_glyphs(&mut self.renderer, &mut self.layout_chars, lcd)?;
+        // decorations skip the ink of the glyphs as they are drawn
+        let state = self.states.last().unwrap();
+        let decorations = if state.text_decoration.is_empty() {
+            Vec::new()
+        } else {
+            self.fonts.layout_decorations(
+                state.font_id,
+                state.font_size * scale,
+                state.text_decoration,
+                state.text_decoration_skip_ink,
+                &run,
+                &self.layout_chars,
+            )
+        };
+        self.draw_glyphs(0..self.layout_chars.len(), &decorations, paint, lcd)
+    }
+
+    /// Draws text fitted into `max_lines` lines of `max_width`, replacing the
+    /// overflowing part with an ellipsis. Lines are aligned like
+    /// [`Context::text`] and spaced with the current line height.
+    pub fn text_truncated<S: AsRef<str>, P: Into<Point>>(
+        &mut self,
+        pt: P,
+        text: S,
+        max_width: f32,
+        max_lines: usize,
+        ellipsis: TextEllipsis,
+    ) -> crate::Result<()> {
+        let pt = pt.into();
+        self.record(|| {
+            DrawCommand::TextTruncated(
+                pt,
+                text.as_ref().to_string(),
+                max_width,
+                max_lines,
+                ellipsis,
+            )
+        });
+        if self.pictures.active() {
+            return Ok(());
+        }
+        let lines = self.truncate_text(text, max_width, max_lines, ellipsis);
+        let line_height = self.font_metrics().map_or(0.0, |m| m.line_height())
+            * self.states.last().unwrap().line_height;
+        self.unrecorded(|ctx| {
+            for (i, line) in lines.iter().enumerate() {
+                ctx.text((pt.x, pt.y + i as f32 * line_height), line)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns the lines [`Context::text_truncated`] would draw.
+    pub fn truncate_text<S: AsRef<str>>(
+        &self,
+        text: S,
+        max_width: f32,
+        max_lines: usize,
+        ellipsis: TextEllipsis,
+    ) -> Vec<String> {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        self.fonts.truncate_text(
+            text.as_ref(),
+            state.font_id,
+            state.font_size * scale,
+            state.letter_spacing * scale,
+            max_width * scale,
+            max_lines,
+            ellipsis,
+        )
+    }
+
+    /// Draws attributed text on a single line, aligned with the current text
+    /// align.
+    pub fn rich_text<P: Into<Point>>(&mut self, pt: P, spans: &[TextSpan]) -> crate::Result<()> {
+        let pt = pt.into();
+        self.record(|| DrawCommand::RichText(pt, None, RecordedSpan::from_spans(spans)));
+        if self.pictures.active() {
+            return Ok(());
+        }
+        self.draw_spans(pt, None, spans)
+    }
+
+    /// Draws attributed text wrapped at `break_width`. Lines are aligned
+    /// within the box with the current text align and spaced with the
+    /// current line height.
+    pub fn rich_text_box<P: Into<Point>>(
+        &mut self,
+        pt: P,
+        break_width: f32,
+        spans: &[TextSpan],
+    ) -> crate::Result<()> {
+        let pt = pt.into();
+        self.record(|| {
+            DrawCommand::RichText(pt, Some(break_width), RecordedSpan::from_spans(spans))
+        });
+        if self.pictures.active() {
+            return Ok(());
+        }
+        self.draw_spans(pt, Some(break_width), spans)
+    }
+
+    /// Measures attributed text in the current coordinate space, wrapping it
+    /// at `break_width` if given.
+    pub fn rich_text_size(&self, spans: &[TextSpan], break_width: Option<f32>) -> Extent {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        let size = self.fonts.spans_size(
+            spans,
+            scale,
+            state.line_height,
+            break_width.map(|width| width * scale),
+        );
+        Extent::new(size.width / scale, size.height / scale)
+    }
+
+    fn draw_spans(
+        &mut self,
+        pt: Point,
+        break_width: Option<f32>,
+        spans: &[TextSpan],
+    ) -> crate::Result<()> {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        let xform = state.xform;
+        let skip_ink = state.text_decoration_skip_ink;
+        // the glyphs of all spans are cached at once, in one raster mode
+        let lcd = state.text_antialias == TextAntialias::Lcd
+            && spans
+                .iter()
+                .all(|span| lcd_paint(&span.paint, state.composite_operation));
+        let mut runs = std::mem::take(&mut self.span_runs);
+
+        self.fonts.layout_spans(
+            spans,
+            scale,
+            (pt.x * scale, pt.y * scale).into(),
+            state.text_align,
+            state.line_height,
+            break_width.map(|width| width * scale),
+            &mut self.layout_chars,
+            &mut runs,
+        );
+
+        self.fonts
+            .cache_glyphs(&mut self.renderer, &mut self.layout_chars, lcd)?;
+
+        // Consecutive runs of the same span, i.e. a span wrapped over several
+        // lines, are drawn together.
+        let mut groups = Vec::new();
+        let mut i = 0;
+        while i < runs.len() {
+            let span_idx = runs[i].span;
+            let span = &spans[span_idx];
+            let mut chars = runs[i].chars.clone();
+            let mut decorations = Vec::new();
+            while i < runs.len() && runs[i].span == span_idx {
+                if !span.decoration.is_empty() {
+                    decorations.extend(self.fonts.layout_decorations(
+                        span.font_id,
+                        span.size * scale,
+                        span.decoration,
+                        skip_ink,
+                        &runs[i].run,
+                        &self.layout_chars[runs[i].chars.clone()],
+                    ));
+                }
+                chars.end = runs[i].chars.end;
+                i += 1;
+            }
+            groups.push((span_idx, chars, decorations));
+        }
+        self.span_runs = runs;
+
+        for (span_idx, chars, decorations) in groups {
+            let mut paint = spans[span_idx].paint;
+            paint.xform *= xform;
+            self.draw_glyphs(chars, &decorations, paint, lcd)?;
+        }
+        Ok(())
+    }
+
+    fn draw_glyphs(
+        &mut self,
+        chars: Range<usize>,
+        decorations: &[Bounds],
+        mut paint: PaintPattern,
+        lcd: bool,
+    ) -> crate::Result<()> {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        let xform = &state.xform;
+        let invscale = 1.0 / scale;
+
         let cache = &mut self.path_cache.cache;
         let offset = cache.vertices.len();
-        for lc in &self.layout_chars {
-            let lt = xform.transform_point(Point::new(
-                lc.bounds.min.x * invscale,
-                lc.bounds.min.y * invscale,
-            ));
-            let rt = xform.transform_point(Point::new(
-                lc.bounds.max.x * invscale,
-                lc.bounds.min.y * invscale,
-            ));
-            let lb = xform.transform_point(Point::new(
-                lc.bounds.min.x * invscale,
-                lc.bounds.max.y * invscale,
-            ));
-            let rb = xform.transform_point(Point::new(
-                lc.bounds.max.x * invscale,
-                lc.bounds.max.y * invscale,
-            ));
-
-            cache.vertices.extend([
-                Vertex::new(lt.x, lt.y, lc.uv.min.x, lc.uv.min.y),
-                Vertex::new(rb.x, rb.y, lc.uv.max.x, lc.uv.max.y),
-                Vertex::new(rt.x, rt.y, lc.uv.max.x, lc.uv.min.y),
-                Vertex::new(lt.x, lt.y, lc.uv.min.x, lc.uv.min.y),
-                Vertex::new(lb.x, lb.y, lc.uv.min.x, lc.uv.max.y),
-                Vertex::new(rb.x, rb.y, lc.uv.max.x, lc.uv.max.y),
-            ]);
+        let uv = self.fonts.solid_uv();
+        for bounds in decorations {
+            push_quad(&mut cache.vertices, xform, invscale, bounds, &uv, false);
+        }
+        let layout_chars = &self.layout_chars[chars];
+        for lc in layout_chars.iter().filter(|lc| lc.image.is_none()) {
+            push_quad(
+                &mut cache.vertices,
+                xform,
+                invscale,
+                &lc.bounds,
+                &lc.uv,
+                lc.rotated,
+            );
         }
 
-        let mut paint = state.paint.fill.clone();
-        paint.image = Some(self.fonts.img.clone());
+        paint.image = Some(self.fonts.img);
         paint.inner_color.a *= state.paint.alpha;
         paint.outer_color.a *= state.paint.alpha;
 
-        self.renderer.triangles(
-            None,
-            None,
-            &paint,
-            state.composite_operation,
-            &state.scissor,
-            crate::VertexSlice {
+        let slice = crate::VertexSlice {
+            offset,
+            count: cache.vertices.len() - offset,
+        };
+        let vertices = &cache.vertices[offset..];
+        if self.damage.cull(|| vertex_bounds(vertices), &state.scissor) {
+            cache.vertices.truncate(offset);
+        } else if slice.count > 0 {
+            if lcd {
+                self.renderer
+                    .lcd_triangles(None, None, &paint, &state.scissor, slice)?;
+            } else {
+                self.renderer.triangles(
+                    None,
+                    None,
+                    &paint,
+                    state.composite_operation,
+                    &state.scissor,
+                    slice,
+                )?;
+            }
+            self.stats.text_calls += 1;
+            self.stats.text_triangles += slice.count / 3;
+        }
+
+        // Custom glyphs are drawn in their own colors, one call per run of
+        // the same image.
+        let mut image_paint = PaintPattern::from(Color::rgba(1.0, 1.0, 1.0, state.paint.alpha));
+        let mut images = layout_chars
+            .iter()
+            .filter(|lc| lc.image.is_some())
+            .peekable();
+        while let Some(first) = images.next() {
+            let offset = cache.vertices.len();
+            push_quad(
+                &mut cache.vertices,
+                xform,
+                invscale,
+                &first.bounds,
+                &first.uv,
+                false,
+            );
+            while let Some(lc) = images.next_if(|lc| lc.image == first.image) {
+                push_quad(
+                    &mut cache.vertices,
+                    xform,
+                    invscale,
+                    &lc.bounds,
+                    &lc.uv,
+                    false,
+                );
+            }
+            let vertices = &cache.vertices[offset..];
+            if self.damage.cull(|| vertex_bounds(vertices), &state.scissor) {
+                cache.vertices.truncate(offset);
+                continue;
+            }
+            image_paint.image = first.image;
+            let slice = crate::VertexSlice {
                 offset,
                 count: cache.vertices.len() - offset,
-            },
-        )?;
+            };
+            self.renderer.triangles(
+                None,
+                None,
+                &image_paint,
+                state.composite_operation,
+                &state.scissor,
+                slice,
+            )?;
+            self.stats.text_calls += 1;
+            self.stats.text_triangles += slice.count / 3;
+        }
         Ok(())
     }
 
+    /// Returns the positions of the characters of `text` as drawn by
+    /// [`Context::text`] in horizontal writing mode, in the current
+    /// coordinate space.
+    pub fn text_glyph_positions<S: AsRef<str>, P: Into<Point>>(
+        &self,
+        pt: P,
+        text: S,
+    ) -> Vec<GlyphPosition> {
+        let state = self.states.last().unwrap();
+        let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        let invscale = 1.0 / scale;
+        let pt = pt.into();
+        let mut positions = self.fonts.text_glyph_positions(
+            text.as_ref(),
+            state.font_id,
+            (pt.x * scale, pt.y * scale).into(),
+            state.font_size * scale,
+            state.text_align,
+            state.letter_spacing * scale,
+        );
+        for position in &mut positions {
+            position.x *= invscale;
+            position.min_x *= invscale;
+            position.max_x *= invscale;
+        }
+        positions
+    }
+
+    /// Returns the vertical metrics of the current font in device pixels,
+    /// scaled by the current transform and the device pixel ratio.
     pub fn text_metrics(&self) -> TextMetrics {
         let state = self.states.last().unwrap();
         let scale = state.xform.font_scale() * self.device_pixel_ratio;
@@ -136,9 +546,33 @@ impl<R: RendererDevice> Context<R> {
             .text_metrics(state.font_id, state.font_size * scale)
     }
 
+    /// Returns the metrics of the current font at the current font size, in
+    /// the current coordinate space. Unlike `text_metrics`, the sizes are not
+    /// scaled by the transform or the device pixel ratio.
+    pub fn font_metrics(&self) -> Option<FontMetrics> {
+        let state = self.states.last().unwrap();
+        self.fonts.font_metrics(state.font_id, state.font_size)
+    }
+
+    /// Returns the metrics of the glyph for `c` in the current font, or its
+    /// fallbacks, at the current font size, in the current coordinate space,
+    /// unscaled like `font_metrics`.
+    pub fn glyph_metrics(&self, c: char) -> Option<GlyphMetrics> {
+        let state = self.states.last().unwrap();
+        self.fonts.glyph_metrics(state.font_id, c, state.font_size)
+    }
+
     pub fn text_size<S: AsRef<str>>(&self, text: S) -> Extent {
         let state = self.states.last().unwrap();
         let scale = state.xform.font_scale() * self.device_pixel_ratio;
+        if state.writing_mode == WritingMode::Vertical {
+            return self.fonts.text_size_vertical(
+                text.as_ref(),
+                state.font_id,
+                state.font_size * scale,
+                state.letter_spacing * scale,
+            );
+        }
         self.fonts.text_size(
             text.as_ref(),
             state.font_id,
@@ -1,30 +1,111 @@
 use super::{Context, ImageFlags, ImageId, TextureType};
-use crate::RendererDevice;
+use crate::{Error, RendererDevice};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+type ReleasedImages = Arc<Mutex<Vec<(ImageId, u32)>>>;
+
+/// Tells deleted images apart from later ones reusing their ids
+#[derive(Default)]
+pub(super) struct ImageRegistry {
+    /// Bumped each time the image of an id is deleted
+    generations: HashMap<ImageId, u32>,
+    /// Images of dropped handles, deleted at the next frame
+    released: ReleasedImages,
+}
+
+/// An image owned by a handle. Dropping the handle deletes the image at the
+/// beginning of the next frame, once no queued drawing uses it anymore. The
+/// id for the calls taking an [`ImageId`] is given by [`Context::image_id`],
+/// which checks that the handle is still valid.
+#[derive(Debug)]
+pub struct Image {
+    id: ImageId,
+    generation: u32,
+    released: ReleasedImages,
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        if let Ok(mut released) = self.released.lock() {
+            released.push((self.id, self.generation));
+        }
+    }
+}
 
 impl<R: RendererDevice> Context<R> {
+    /// Takes ownership of the image `img`, which is deleted when the returned
+    /// handle is dropped.
+    pub fn own_image(&mut self, img: ImageId) -> Image {
+        Image {
+            id: img,
+            generation: self.images.generations.get(&img).copied().unwrap_or(0),
+            released: self.images.released.clone(),
+        }
+    }
+
+    /// Id of the image held by `image`. Fails when the image was deleted
+    /// with [`Context::delete_image`] or belongs to another context.
+    pub fn image_id(&self, image: &Image) -> crate::Result<ImageId> {
+        if !Arc::ptr_eq(&image.released, &self.images.released) {
+            return Err(Error::InvalidImage(image.id));
+        }
+        let generation = self.images.generations.get(&image.id).copied();
+        if generation.unwrap_or(0) != image.generation {
+            return Err(Error::InvalidImage(image.id));
+        }
+        Ok(image.id)
+    }
+
+    /// Deletes the images whose handles were dropped. All of them are
+    /// deleted even if some fail, the first failure is returned.
+    pub(super) fn release_images(&mut self) -> crate::Result<()> {
+        let released = std::mem::take(&mut *self.images.released.lock().unwrap());
+        let mut result = Ok(());
+        for (img, generation) in released {
+            if self.images.generations.get(&img).copied().unwrap_or(0) == generation {
+                let deleted = self.delete_image(img);
+                if result.is_ok() {
+                    result = deleted;
+                }
+            }
+        }
+        result
+    }
+
     pub fn create_image_init<D: AsRef<[u8]>>(
         &mut self,
         flags: ImageFlags,
         data: D,
-    ) -> anyhow::Result<ImageId> {
+    ) -> crate::Result<ImageId> {
         let img = image::load_from_memory(data.as_ref())?;
         let img = img.to_rgba8();
         let dimensions = img.dimensions();
-        let img = self.renderer.create_texture(
-            TextureType::RGBA,
+        self.create_image(
             dimensions.0,
             dimensions.1,
+            TextureType::RGBA,
             flags,
             Some(&img.into_raw()),
-        )?;
-        Ok(img)
+        )
+    }
+
+    /// Like [`Context::create_image_init`], returning a handle which deletes
+    /// the image when dropped
+    pub fn create_image_init_owned<D: AsRef<[u8]>>(
+        &mut self,
+        flags: ImageFlags,
+        data: D,
+    ) -> crate::Result<Image> {
+        let img = self.create_image_init(flags, data)?;
+        Ok(self.own_image(img))
     }
 
     pub fn create_image_from_file<P: AsRef<std::path::Path>>(
         &mut self,
         flags: ImageFlags,
         path: P,
-    ) -> anyhow::Result<ImageId> {
+    ) -> crate::Result<ImageId> {
         self.create_image_init(flags, std::fs::read(path)?)
     }
 
@@ -35,20 +116,40 @@ impl<R: RendererDevice> Context<R> {
         fmt: TextureType,
         flags: ImageFlags,
         data: Option<&[u8]>,
-    ) -> anyhow::Result<ImageId> {
+    ) -> crate::Result<ImageId> {
         let img = self
             .renderer
             .create_texture(fmt, width, height, flags, data)?;
+        if data.is_some() {
+            self.stats.texture_uploads += 1;
+        }
+        #[cfg(feature = "trace")]
+        self.trace
+            .image_created(img, fmt, width, height, flags, data);
         Ok(img)
     }
 
+    /// Like [`Context::create_image`], returning a handle which deletes the
+    /// image when dropped
+    pub fn create_image_owned(
+        &mut self,
+        width: u32,
+        height: u32,
+        fmt: TextureType,
+        flags: ImageFlags,
+        data: Option<&[u8]>,
+    ) -> crate::Result<Image> {
+        let img = self.create_image(width, height, fmt, flags, data)?;
+        Ok(self.own_image(img))
+    }
+
     /// area: Some(x, y, w, h)
     pub fn update_image(
         &mut self,
         img: ImageId,
         data: &[u8],
         area: Option<(u32, u32, u32, u32)>,
-    ) -> anyhow::Result<()> {
+    ) -> crate::Result<()> {
         let (x, y, w, h) = if let Some(area) = area {
             area
         } else {
@@ -56,16 +157,93 @@ impl<R: RendererDevice> Context<R> {
             (0, 0, w, h)
         };
         self.renderer.update_texture(img, x, y, w, h, data)?;
+        self.stats.texture_uploads += 1;
+        #[cfg(feature = "trace")]
+        self.trace.image_updated(img, (x, y, w, h), data);
         Ok(())
     }
 
-    pub fn image_size(&self, img: ImageId) -> anyhow::Result<(u32, u32)> {
+    pub fn image_size(&self, img: ImageId) -> crate::Result<(u32, u32)> {
         let res = self.renderer.texture_size(img)?;
         Ok(res)
     }
 
-    pub fn delete_image(&mut self, img: ImageId) -> anyhow::Result<()> {
+    pub fn delete_image(&mut self, img: ImageId) -> crate::Result<()> {
         self.renderer.delete_texture(img)?;
+        let generation = self.images.generations.entry(img).or_insert(0);
+        *generation = generation.wrapping_add(1);
+        #[cfg(feature = "trace")]
+        self.trace.image_deleted(img);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::tests::NullRenderer;
+    use crate::Extent;
+
+    fn context() -> Context<NullRenderer> {
+        Context::create(NullRenderer::default()).unwrap()
+    }
+
+    fn image(ctx: &mut Context<NullRenderer>) -> Image {
+        ctx.create_image_owned(4, 2, TextureType::RGBA, ImageFlags::empty(), None)
+            .unwrap()
+    }
+
+    fn begin_frame(ctx: &mut Context<NullRenderer>) {
+        ctx.begin_frame(Extent::new(100.0, 100.0), 1.0).unwrap();
+        ctx.end_frame().unwrap();
+    }
+
+    #[test]
+    fn dropped_image_deleted_next_frame() {
+        let mut ctx = context();
+        let img = image(&mut ctx);
+        let id = ctx.image_id(&img).unwrap();
+        drop(img);
+        assert_eq!(ctx.image_size(id).unwrap(), (4, 2));
+        begin_frame(&mut ctx);
+        assert!(ctx.image_size(id).is_err());
+    }
+
+    #[test]
+    fn deleted_image_goes_stale() {
+        let mut ctx = context();
+        let img = image(&mut ctx);
+        let id = ctx.image_id(&img).unwrap();
+        ctx.delete_image(id).unwrap();
+        assert!(ctx.image_id(&img).is_err());
+
+        // a new image reusing the id is neither reached through the old
+        // handle nor deleted when it is dropped
+        let reused = image(&mut ctx);
+        assert_eq!(ctx.image_id(&reused).unwrap(), id);
+        assert!(ctx.image_id(&img).is_err());
+        drop(img);
+        begin_frame(&mut ctx);
+        assert_eq!(ctx.image_id(&reused).unwrap(), id);
+        assert_eq!(ctx.image_size(id).unwrap(), (4, 2));
+    }
+
+    #[test]
+    fn image_of_other_context() {
+        let mut ctx = context();
+        let mut other = context();
+        let img = image(&mut ctx);
+        let same_id = image(&mut other);
+        let id = ctx.image_id(&img).unwrap();
+        assert_eq!(other.image_id(&same_id).unwrap(), id);
+        assert!(other.image_id(&img).is_err());
+
+        // dropping it deletes the image of its own context only
+        drop(img);
+        begin_frame(&mut other);
+        begin_frame(&mut ctx);
+        assert!(ctx.image_size(id).is_err());
+        assert!(other.image_id(&same_id).is_ok());
+        assert_eq!(other.image_size(id).unwrap(), (4, 2));
+    }
+}
@@ -1,16 +1,25 @@
 use std::ops::Range;
+use std::time::Instant;
 
 use crate::{BufferUsage, DrawPathStyle, RendererDevice};
-use crate::{Instances, Paint};
+use crate::{Instance, Instances, Paint};
 
-use super::core_path_cache::PathRefWithCache;
+use super::damage::{instanced_bounds, paths_bounds};
+use super::picture::{recorded_instances, DrawCommand};
+use super::stats::{fill_triangles, stroke_triangles};
 use super::*;
 
 impl<R: RendererDevice> Context<R> {
-    pub fn fill(&mut self) -> anyhow::Result<()> {
+    pub fn fill(&mut self) -> crate::Result<()> {
+        self.record(|| DrawCommand::Fill);
+        if self.pictures.active() {
+            return Ok(());
+        }
         let state = self.states.last().unwrap();
         let paint = &state.paint;
         self.path_cache.fill_type = state.fill_type;
+        let first_vertex = self.path_cache.cache.vertices.len();
+        let start = Instant::now();
         let bounds_offset = Self::expand_fill_path(
             &mut self.path_cache,
             &state.paint,
@@ -18,8 +27,15 @@ impl<R: RendererDevice> Context<R> {
             self.tess_tol,
             self.fringe_width,
         );
-
+        self.stats.tessellation_time += start.elapsed();
         let fill_slice = self.path_cache.get_fill_slice();
+        let vertices = &self.path_cache.cache.vertices;
+        let bounds = || paths_bounds(vertices, fill_slice);
+        if self.damage.cull(bounds, &state.scissor) {
+            self.path_cache.cache.vertices.truncate(first_vertex);
+            return Ok(());
+        }
+
         self.renderer.fill(
             None,
             None,
@@ -32,20 +48,16 @@ impl<R: RendererDevice> Context<R> {
             fill_slice,
         )?;
 
-        self.draw_call_count += fill_slice.len();
-        self.fill_triangles_count += fill_slice.iter().fold(0, |mut acc, path_slice| {
-            if path_slice.num_fill > 2 {
-                acc += path_slice.num_fill - 2;
-            }
-            if path_slice.num_stroke > 2 {
-                acc += path_slice.num_stroke - 2;
-            }
-            acc
-        });
+        self.stats.fill_calls += 1;
+        self.stats.fill_triangles += fill_triangles(fill_slice);
         Ok(())
     }
 
-    pub fn stroke(&mut self) -> anyhow::Result<()> {
+    pub fn stroke(&mut self) -> crate::Result<()> {
+        self.record(|| DrawCommand::Stroke);
+        if self.pictures.active() {
+            return Ok(());
+        }
         let state = self.states.last().unwrap();
         let paint = &state.paint;
         let antialias = self.renderer.edge_antialias() && paint.antialias;
@@ -56,6 +68,8 @@ impl<R: RendererDevice> Context<R> {
             self.device_pixel_ratio,
         );
 
+        let first_vertex = self.path_cache.cache.vertices.len();
+        let start = Instant::now();
         Self::expand_stroke_path(
             &mut self.path_cache,
             antialias,
@@ -65,8 +79,15 @@ impl<R: RendererDevice> Context<R> {
             self.tess_tol,
             self.fringe_width,
         );
-
+        self.stats.tessellation_time += start.elapsed();
         let stroke_slice = self.path_cache.get_stroke_slice();
+        let vertices = &self.path_cache.cache.vertices;
+        let bounds = || paths_bounds(vertices, stroke_slice);
+        if self.damage.cull(bounds, &state.scissor) {
+            self.path_cache.cache.vertices.truncate(first_vertex);
+            return Ok(());
+        }
+
         self.renderer.stroke(
             None,
             None,
@@ -78,19 +99,30 @@ impl<R: RendererDevice> Context<R> {
             stroke_slice,
         )?;
 
-        self.draw_call_count += stroke_slice.len();
-        self.fill_triangles_count += stroke_slice
-            .iter()
-            .fold(0, |acc, path_slice| acc + path_slice.num_stroke - 2);
+        self.stats.stroke_calls += 1;
+        self.stats.stroke_triangles += stroke_triangles(stroke_slice);
         Ok(())
     }
 
     #[cfg(feature = "wirelines")]
-    pub fn wirelines(&mut self) -> anyhow::Result<()> {
+    pub fn wirelines(&mut self) -> crate::Result<()> {
+        self.record(|| DrawCommand::Wirelines);
+        if self.pictures.active() {
+            return Ok(());
+        }
+        let first_vertex = self.path_cache.cache.vertices.len();
+        let start = Instant::now();
         Self::expand_wirelines_path(&mut self.path_cache, self.dist_tol, self.tess_tol);
+        self.stats.tessellation_time += start.elapsed();
         let state = self.states.last().unwrap();
         let (stroke_paint, _) = state.paint.get_stroke(false, 1.0, 1.0, 1.0);
         let lines_slice = self.path_cache.get_lines_slice();
+        let vertices = &self.path_cache.cache.vertices;
+        let bounds = || paths_bounds(vertices, lines_slice);
+        if self.damage.cull(bounds, &state.scissor) {
+            self.path_cache.cache.vertices.truncate(first_vertex);
+            return Ok(());
+        }
 
         self.renderer.wirelines(
             None,
@@ -100,26 +132,41 @@ impl<R: RendererDevice> Context<R> {
             &state.scissor,
             lines_slice,
         )?;
-        self.draw_call_count += lines_slice.len();
+        self.stats.line_calls += 1;
         Ok(())
     }
 
-    pub fn update_instances(&mut self, instances: &Instances<R>) -> anyhow::Result<()> {
-        let instances_data = bytemuck::cast_slice(&instances.transforms);
-        if !instances.is_empty() {
-            let mut inner = instances.inner.borrow_mut();
-            let try_update = inner.vertex_buffer.as_ref().and_then(|buffer| {
-                self.renderer
-                    .update_vertex_buffer(Some(&buffer), instances_data)
-                    .ok()
-            });
-            if try_update.is_none() {
-                let buffer = self.renderer.create_vertex_buffer(instances_data.len(), BufferUsage::Instance)?;
-                self.renderer
-                    .update_vertex_buffer(Some(&buffer), instances_data)?;
-                inner.vertex_buffer = Some(buffer);
+    /// Uploads the instances changed since the last call. The buffer grows in
+    /// powers of two, so pushed instances are mostly written on their own.
+    pub fn update_instances(&mut self, instances: &Instances<R>) -> crate::Result<()> {
+        let len = instances.len();
+        let mut inner = instances.inner.lock().unwrap();
+        let dirty = match &inner.dirty {
+            Some(dirty) if len > 0 => dirty.start.min(len)..dirty.end.min(len),
+            _ => return Ok(()),
+        };
+        let inner = &mut *inner;
+        match &inner.vertex_buffer {
+            Some(pooled) if len <= inner.capacity => {
+                if !dirty.is_empty() {
+                    let size = std::mem::size_of::<Instance>();
+                    self.stats.uploaded_instances += dirty.len();
+                    let data = bytemuck::cast_slice(&instances.instances);
+                    let range = dirty.start * size..dirty.end * size;
+                    self.renderer
+                        .update_vertex_buffer_range(&pooled.buffer, data, range)?;
+                }
+            }
+            _ => {
+                let capacity = len.next_power_of_two();
+                let mut data = bytemuck::cast_slice(&instances.instances).to_vec();
+                data.resize(capacity * std::mem::size_of::<Instance>(), 0);
+                self.upload_vertex_buffer(&mut inner.vertex_buffer, &data, BufferUsage::Instance)?;
+                inner.capacity = capacity;
+                self.stats.uploaded_instances += len;
             }
         }
+        inner.dirty = None;
         Ok(())
     }
 
@@ -129,120 +176,61 @@ impl<R: RendererDevice> Context<R> {
         paint: &'a Paint,
         style: DrawPathStyle,
         instances: Option<(&Instances<R>, Range<u32>)>,
-    ) -> anyhow::Result<()> {
+    ) -> crate::Result<()> {
         if style.is_empty() {
             return Ok(());
         }
-        let cached_style = path.inner_cached_style();
-
-        let (fill_cmd, stroke_cmd, lines_cmd) = if cached_style.contains(style) {
-            let fill_cmd = if style.contains(DrawPathStyle::FILL) {
-                Some(path.inner.borrow().draw_slice.bounds_offset)
-            } else {
-                None
-            };
-            let stroke_cmd = if style.contains(DrawPathStyle::STROKE) {
-                let antialias = self.renderer.edge_antialias() && paint.antialias;
-                Some(paint.get_stroke(
-                    antialias,
-                    self.fringe_width,
-                    path.xform.average_scale(),
-                    self.device_pixel_ratio,
-                ))
-            } else {
-                None
-            };
-            let lines_cmd = if style.contains(DrawPathStyle::WIRELINES) {
-                Some(())
-            } else {
-                None
-            };
-            (fill_cmd, stroke_cmd, lines_cmd)
-        } else {
-            // Update Vertex buffer
-            let mut path_cache = PathRefWithCache::new(path);
-            let new_style = cached_style | style;
-            let fill_cmd = if new_style.contains(DrawPathStyle::FILL) {
-                Some(Self::expand_fill_path(
-                    &mut path_cache,
-                    &paint,
-                    self.dist_tol,
-                    self.tess_tol,
-                    self.fringe_width,
-                ))
-            } else {
-                None
-            };
-            let stroke_cmd = if new_style.contains(DrawPathStyle::STROKE) {
-                let antialias = self.renderer.edge_antialias() && paint.antialias;
-                let (stroke_paint, stroke_width) = paint.get_stroke(
-                    antialias,
-                    self.fringe_width,
-                    path.xform.average_scale(),
-                    self.device_pixel_ratio,
-                );
-                Self::expand_stroke_path(
-                    &mut path_cache,
-                    antialias,
-                    stroke_width,
-                    &paint,
-                    self.dist_tol,
-                    self.tess_tol,
-                    self.fringe_width,
-                );
-                Some((stroke_paint, stroke_width))
-            } else {
-                None
-            };
-            let lines_cmd = if style.contains(DrawPathStyle::WIRELINES) {
-                Self::expand_wirelines_path(&mut path_cache, self.dist_tol, self.tess_tol);
-                Some(())
-            } else {
-                None
-            };
-            let vertex_data = bytemuck::cast_slice(&path_cache.cache.vertices);
-            if !path_cache.cache.vertices.is_empty() {
-                let try_update =
-                    path_cache
-                        .path_mut_inner
-                        .vertex_buffer
-                        .as_ref()
-                        .and_then(|buffer| {
-                            self.renderer
-                                .update_vertex_buffer(Some(&buffer), vertex_data)
-                                .ok()
-                        });
-                if try_update.is_none() {
-                    let buffer = self.renderer.create_vertex_buffer(vertex_data.len(), BufferUsage::Vertex)?;
-                    self.renderer
-                        .update_vertex_buffer(Some(&buffer), vertex_data)?;
-                    path_cache.path_mut_inner.vertex_buffer = Some(buffer);
-                }
-            }
+        self.record(|| DrawCommand::DrawPath {
+            path: path.path_comands.clone(),
+            paint: Box::new(paint.clone()),
+            style,
+            instances: recorded_instances(&instances),
+        });
+        if self.pictures.active() {
+            return Ok(());
+        }
+        let start = Instant::now();
+        path.tessellate(&self.tessellation(), paint, style);
+        self.stats.tessellation_time += start.elapsed();
+        self.upload_path(path)?;
 
-            path_cache.path_mut_inner.style = new_style;
-            (fill_cmd, stroke_cmd, lines_cmd)
+        let inner = path.inner.lock().unwrap();
+        let fill_cmd = if style.contains(DrawPathStyle::FILL) {
+            Some(inner.draw_slice.bounds_offset)
+        } else {
+            None
+        };
+        let stroke_cmd = if style.contains(DrawPathStyle::STROKE) {
+            let antialias = self.renderer.edge_antialias() && paint.antialias;
+            Some(paint.get_stroke(
+                antialias,
+                self.fringe_width,
+                path.xform.average_scale(),
+                self.device_pixel_ratio,
+            ))
+        } else {
+            None
+        };
+        let lines_cmd = if style.contains(DrawPathStyle::WIRELINES) {
+            Some(())
+        } else {
+            None
         };
 
-        let instances = instances.and_then(|(insts, range)| {
-            if !insts.is_empty() {
-                let inner = insts.inner.borrow();
-                inner
-                    .vertex_buffer
-                    .clone()
-                    .and_then(|buffer| Some((buffer, range)))
-            } else {
-                None
-            }
-        });
+        let state = self.states.last().unwrap();
+        let bounds = || instanced_bounds(inner.draw_slice.bounds, &instances);
+        if self.damage.cull(bounds, &state.scissor) {
+            return Ok(());
+        }
+
+        let instances = instances.and_then(|(insts, range)| insts.buffer(range));
+        let count = instances.as_ref().map_or(1, |(_, range)| range.len());
 
         // Start Draw-CALLs
-        let state = self.states.last().unwrap();
-        let inner = path.inner.borrow();
         if let Some(bounds_offset) = fill_cmd {
             let fill_slice = &inner.draw_slice.fill;
             self.renderer.fill(
-                inner.vertex_buffer.clone(),
+                inner.vertex_buffer.as_ref().map(|b| b.buffer.clone()),
                 instances.clone(),
                 &paint.get_fill(),
                 state.composite_operation,
@@ -253,22 +241,14 @@ impl<R: RendererDevice> Context<R> {
                 fill_slice,
             )?;
 
-            self.draw_call_count += fill_slice.len();
-            self.fill_triangles_count += fill_slice.iter().fold(0, |mut acc, path_slice| {
-                if path_slice.num_fill > 2 {
-                    acc += path_slice.num_fill - 2;
-                }
-                if path_slice.num_stroke > 2 {
-                    acc += path_slice.num_stroke - 2;
-                }
-                acc
-            });
+            self.stats.fill_calls += 1;
+            self.stats.fill_triangles += fill_triangles(fill_slice) * count;
         }
 
         if let Some((stroke_paint, stroke_width)) = stroke_cmd {
             let stroke_slice = &inner.draw_slice.stroke;
             self.renderer.stroke(
-                inner.vertex_buffer.clone(),
+                inner.vertex_buffer.as_ref().map(|b| b.buffer.clone()),
                 instances.clone(),
                 &stroke_paint,
                 state.composite_operation,
@@ -278,26 +258,121 @@ impl<R: RendererDevice> Context<R> {
                 stroke_slice,
             )?;
 
-            self.draw_call_count += stroke_slice.len();
-            self.fill_triangles_count += stroke_slice
-                .iter()
-                .fold(0, |acc, path_slice| acc + path_slice.num_stroke - 2);
+            self.stats.stroke_calls += 1;
+            self.stats.stroke_triangles += stroke_triangles(stroke_slice) * count;
         }
 
         if let Some(_) = lines_cmd {
             let lines_slice = &inner.draw_slice.lines;
             let (stroke_paint, _) = paint.get_stroke(false, 1.0, 1.0, 1.0);
             self.renderer.wirelines(
-                inner.vertex_buffer.clone(),
+                inner.vertex_buffer.as_ref().map(|b| b.buffer.clone()),
                 instances.clone(),
                 &stroke_paint,
                 state.composite_operation,
                 &state.scissor,
                 lines_slice,
             )?;
-            self.draw_call_count += lines_slice.len();
+            self.stats.line_calls += 1;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::tests::NullRenderer;
+    use crate::{Color, Transform};
+
+    const SIZE: usize = std::mem::size_of::<Instance>();
+
+    fn instances(count: usize) -> Instances<NullRenderer> {
+        Instances::new(
+            (0..count)
+                .map(|i| Instance::new(Transform::translate(i as f32, 0.0)))
+                .collect(),
+        )
+    }
+
+    /// Uploads the instances, returning the byte ranges written
+    fn update(
+        ctx: &mut Context<NullRenderer>,
+        instances: &Instances<NullRenderer>,
+    ) -> Vec<(usize, Range<usize>)> {
+        let uploaded = ctx.renderer().uploads.len();
+        ctx.update_instances(instances).unwrap();
+        ctx.renderer().uploads[uploaded..].to_vec()
+    }
+
+    fn capacity(instances: &Instances<NullRenderer>) -> usize {
+        instances.inner.lock().unwrap().capacity
+    }
+
+    #[test]
+    fn set_uploads_its_range() {
+        let mut ctx = Context::create(NullRenderer::default()).unwrap();
+        let mut insts = instances(3);
+        assert_eq!(update(&mut ctx, &insts), vec![(0, 0..4 * SIZE)]);
+        assert_eq!(update(&mut ctx, &insts), vec![]);
+
+        insts.set(1, Instance::default());
+        assert_eq!(update(&mut ctx, &insts), vec![(0, SIZE..2 * SIZE)]);
+        insts.set(2, Instance::default());
+        insts.set(0, Instance::default());
+        assert_eq!(update(&mut ctx, &insts), vec![(0, 0..3 * SIZE)]);
+        insts.range_mut(1..2)[0] = Instance::default();
+        assert_eq!(update(&mut ctx, &insts), vec![(0, SIZE..2 * SIZE)]);
+    }
+
+    #[test]
+    fn push_grows_by_powers_of_two() {
+        let mut ctx = Context::create(NullRenderer::default()).unwrap();
+        let mut insts = instances(4);
+        assert_eq!(update(&mut ctx, &insts), vec![(0, 0..4 * SIZE)]);
+        assert_eq!(capacity(&insts), 4);
+
+        // the buffer is replaced with one padded to the next power of two
+        insts.push(Instance::default());
+        assert_eq!(update(&mut ctx, &insts), vec![(1, 0..8 * SIZE)]);
+        assert_eq!(capacity(&insts), 8);
+        assert_eq!(ctx.renderer().buffers[1], Some(8 * SIZE));
+
+        // later pushes fit and upload only themselves
+        insts.push(Instance::default());
+        insts.push(Instance::default());
+        assert_eq!(update(&mut ctx, &insts), vec![(1, 5 * SIZE..7 * SIZE)]);
+        insts.push(Instance::default());
+        insts.push(Instance::default());
+        assert_eq!(update(&mut ctx, &insts), vec![(2, 0..16 * SIZE)]);
+        assert_eq!(capacity(&insts), 16);
+    }
+
+    #[test]
+    fn deref_mut_uploads_everything() {
+        let mut ctx = Context::create(NullRenderer::default()).unwrap();
+        let mut insts = instances(5);
+        update(&mut ctx, &insts);
+        insts[3].color = Color::rgb(1.0, 0.0, 0.0);
+        assert_eq!(update(&mut ctx, &insts), vec![(0, 0..5 * SIZE)]);
+
+        insts.truncate(2);
+        assert_eq!(update(&mut ctx, &insts), vec![(0, 0..2 * SIZE)]);
+    }
+
+    #[test]
+    fn empty_instances() {
+        let mut ctx = Context::create(NullRenderer::default()).unwrap();
+        let mut insts = instances(0);
+        assert_eq!(update(&mut ctx, &insts), vec![]);
+        assert!(insts.buffer(0..1).is_none());
+        assert_eq!(ctx.buffer_stats().live_buffers, 0);
+
+        insts.push(Instance::default());
+        assert_eq!(update(&mut ctx, &insts), vec![(0, 0..SIZE)]);
+        insts.clear();
+        assert_eq!(update(&mut ctx, &insts), vec![]);
+        assert!(insts.buffer(0..1).is_none());
+    }
+}
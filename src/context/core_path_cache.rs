@@ -1,4 +1,4 @@
-use std::cell::RefMut;
+use std::sync::MutexGuard;
 
 use crate::{LineJoin, Paint};
 use crate::{PathMutInner, RendererDevice};
@@ -161,7 +161,7 @@ impl DerefMut for PathCommandsWithCache {
 
 pub(crate) struct PathRefWithCache<'a, R: RendererDevice> {
     pub path: &'a PathCommands,
-    pub path_mut_inner: RefMut<'a, PathMutInner<R>>,
+    pub path_mut_inner: MutexGuard<'a, PathMutInner<R>>,
     pub cache: PathCache,
 }
 
@@ -169,7 +169,7 @@ impl<'a, R: RendererDevice> PathRefWithCache<'a, R> {
     pub(crate) fn new(path: &'a Path<R>) -> Self {
         Self {
             path: &path.path_comands,
-            path_mut_inner: path.inner.borrow_mut(),
+            path_mut_inner: path.inner.lock().unwrap(),
             cache: PathCache::default(),
         }
     }
@@ -1,47 +1,57 @@
 use crate::{PathDir, Point, Rect, RendererDevice};
 
+use super::picture::DrawCommand;
 use super::*;
 
 impl<R: RendererDevice> Context<R> {
     #[inline]
     pub fn move_to<P: Into<Point>>(&mut self, pt: P) {
+        let pt = pt.into();
+        self.record(|| DrawCommand::MoveTo(pt));
         self.path_cache.xform = self.state_mut().xform;
         self.path_cache.move_to(pt);
     }
 
     #[inline]
     pub fn line_to<P: Into<Point>>(&mut self, pt: P) {
+        let pt = pt.into();
+        self.record(|| DrawCommand::LineTo(pt));
         self.path_cache.xform = self.state_mut().xform;
         self.path_cache.line_to(pt);
     }
 
     #[inline]
     pub fn bezier_to<P: Into<Point>>(&mut self, cp1: P, cp2: P, pt: P) {
+        let (cp1, cp2, pt) = (cp1.into(), cp2.into(), pt.into());
+        self.record(|| DrawCommand::BezierTo(cp1, cp2, pt));
         self.path_cache.xform = self.state_mut().xform;
         self.path_cache.bezier_to(cp1, cp2, pt);
     }
 
     #[inline]
     pub fn quad_to<P: Into<Point>>(&mut self, cp: P, pt: P) {
+        let (cp, pt) = (cp.into(), pt.into());
+        self.record(|| DrawCommand::QuadTo(cp, pt));
         self.path_cache.xform = self.state_mut().xform;
         self.path_cache.quad_to(cp, pt);
     }
 
     #[inline]
     pub fn arc_to<P: Into<Point>>(&mut self, pt1: P, pt2: P, radius: f32) {
+        let pt1 = pt1.into();
+        let pt2 = pt2.into();
+        self.record(|| DrawCommand::ArcTo(pt1, pt2, radius));
         self.path_cache.xform = self.state_mut().xform;
         if self.path_cache.commands.is_empty() {
             return;
         }
         let pt0 = self.path_cache.last_position;
-        let pt1 = pt1.into();
-        let pt2 = pt2.into();
         if pt0.equals(pt1, self.dist_tol)
             || pt1.equals(pt2, self.dist_tol)
             || pt1.dist_pt_seg(pt0, pt2) < self.dist_tol * self.dist_tol
             || radius < self.dist_tol
         {
-            self.line_to(pt1);
+            self.path_cache.line_to(pt1);
             return;
         }
         self.path_cache.inner_arc_to(pt0, pt1, pt2, radius);
@@ -49,18 +59,24 @@ impl<R: RendererDevice> Context<R> {
 
     #[inline]
     pub fn arc<P: Into<Point>>(&mut self, cp: P, radius: f32, a0: f32, a1: f32, dir: PathDir) {
+        let cp = cp.into();
+        self.record(|| DrawCommand::Arc(cp, radius, a0, a1, dir));
         self.path_cache.xform = self.state_mut().xform;
         self.path_cache.arc(cp, radius, a0, a1, dir);
     }
 
     #[inline]
     pub fn rect<T: Into<Rect>>(&mut self, rect: T) {
+        let rect = rect.into();
+        self.record(|| DrawCommand::Rect(rect));
         self.path_cache.xform = self.state_mut().xform;
         self.path_cache.rect(rect);
     }
 
     #[inline]
     pub fn rounded_rect<T: Into<Rect>>(&mut self, rect: T, radius: f32) {
+        let rect = rect.into();
+        self.record(|| DrawCommand::RoundedRectVarying(rect, [radius; 4]));
         self.path_cache.xform = self.state_mut().xform;
         self.path_cache.rounded_rect(rect, radius);
     }
@@ -74,35 +90,45 @@ impl<R: RendererDevice> Context<R> {
         rb: f32,
         lb: f32,
     ) {
+        let rect = rect.into();
+        self.record(|| DrawCommand::RoundedRectVarying(rect, [lt, rt, rb, lb]));
         self.path_cache.xform = self.state_mut().xform;
         self.path_cache.rounded_rect_varying(rect, lt, rt, rb, lb);
     }
 
     #[inline]
     pub fn ellipse<P: Into<Point>>(&mut self, center: P, radius_x: f32, radius_y: f32) {
+        let center = center.into();
+        self.record(|| DrawCommand::Ellipse(center, radius_x, radius_y));
         self.path_cache.xform = self.state_mut().xform;
         self.path_cache.ellipse(center, radius_x, radius_y);
     }
 
     #[inline]
     pub fn circle<P: Into<Point>>(&mut self, center: P, radius: f32) {
+        let center = center.into();
+        self.record(|| DrawCommand::Ellipse(center, radius, radius));
         self.path_cache.xform = self.state_mut().xform;
         self.path_cache.circle(center, radius);
     }
 
     #[inline]
     pub fn path_winding<D: Into<PathDir>>(&mut self, dir: D) {
+        let dir = dir.into();
+        self.record(|| DrawCommand::PathWinding(dir));
         self.path_cache.path_winding(dir);
     }
 
     #[inline]
     pub fn begin_path(&mut self) {
+        self.record(|| DrawCommand::BeginPath);
         self.path_cache.clear();
         self.path_cache.cache.clear();
     }
 
     #[inline]
     pub fn close_path(&mut self) {
+        self.record(|| DrawCommand::ClosePath);
         self.path_cache.close_path();
     }
 }
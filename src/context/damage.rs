@@ -0,0 +1,382 @@
+use super::{BasicCompositeOperation, CompositeOperation, Context};
+use crate::path::cache::{PathSlice, Vertex};
+use crate::renderer::Scissor;
+use crate::{Bounds, Color, Extent, Instance, Instances, Point, Rect, RendererDevice};
+use std::ops::Range;
+
+/// Areas of the window to redraw, in window coordinates
+#[derive(Default)]
+pub(super) struct DamageTracker {
+    enabled: bool,
+    /// The next frame is redrawn in full
+    full: bool,
+    invalidated: Option<Bounds>,
+    /// Area redrawn in the current frame, `None` when it is redrawn in full
+    area: Option<Bounds>,
+    drawn: Option<Bounds>,
+    /// Nesting depth of framebuffer contexts, which draw untracked
+    pub(super) suspended: usize,
+}
+
+impl DamageTracker {
+    #[inline]
+    fn tracking(&self) -> bool {
+        self.enabled && self.suspended == 0
+    }
+
+    /// Adds the window space `bounds` of a draw call to the frame and
+    /// returns whether they are outside the redrawn area, in which case the
+    /// call is skipped. `bounds` is only computed while tracking.
+    pub(super) fn cull<F: FnOnce() -> Option<Bounds>>(
+        &mut self,
+        bounds: F,
+        scissor: &Scissor,
+    ) -> bool {
+        if !self.tracking() {
+            return false;
+        }
+        let bounds = match (bounds(), scissor_bounds(scissor)) {
+            (Some(bounds), Some(scissor)) if bounds.intersects(&scissor) => Bounds {
+                min: Point::new(
+                    bounds.min.x.max(scissor.min.x),
+                    bounds.min.y.max(scissor.min.y),
+                ),
+                max: Point::new(
+                    bounds.max.x.min(scissor.max.x),
+                    bounds.max.y.min(scissor.max.y),
+                ),
+            },
+            (Some(_), Some(_)) => return true,
+            (Some(bounds), None) => bounds,
+            (None, _) => return false,
+        };
+        self.drawn = Some(match self.drawn {
+            Some(drawn) => drawn.union(&bounds),
+            None => bounds,
+        });
+        match self.area {
+            Some(area) => !area.intersects(&bounds),
+            None => false,
+        }
+    }
+}
+
+/// Bounds of the triangles made of `vertices`
+pub(super) fn vertex_bounds(vertices: &[Vertex]) -> Option<Bounds> {
+    let (first, rest) = vertices.split_first()?;
+    let mut bounds = Bounds {
+        min: Point::new(first.x, first.y),
+        max: Point::new(first.x, first.y),
+    };
+    for v in rest {
+        bounds.min.x = bounds.min.x.min(v.x);
+        bounds.min.y = bounds.min.y.min(v.y);
+        bounds.max.x = bounds.max.x.max(v.x);
+        bounds.max.y = bounds.max.y.max(v.y);
+    }
+    Some(bounds)
+}
+
+/// Bounds of the vertices drawn for `paths`
+pub(super) fn paths_bounds(vertices: &[Vertex], paths: &[PathSlice]) -> Option<Bounds> {
+    paths
+        .iter()
+        .filter_map(|path| {
+            vertex_bounds(&vertices[path.offset..path.offset + path.num_fill + path.num_stroke])
+        })
+        .reduce(|acc, bounds| acc.union(&bounds))
+}
+
+/// Bounds of `bounds` placed by each of `instances`
+fn instances_bounds(bounds: &Bounds, instances: &[Instance]) -> Option<Bounds> {
+    let corners = [
+        bounds.left_top(),
+        bounds.right_top(),
+        bounds.left_bottom(),
+        bounds.right_bottom(),
+    ];
+    let points = instances.iter().flat_map(|inst| {
+        corners
+            .iter()
+            .map(move |pt| inst.xform.transform_point(*pt))
+    });
+    points.fold(None, |acc: Option<Bounds>, pt| {
+        let pt = Bounds { min: pt, max: pt };
+        Some(acc.map_or(pt, |acc| acc.union(&pt)))
+    })
+}
+
+/// Bounds of a draw call of geometry within `bounds`, placed by the
+/// instances in range if there are any
+pub(super) fn instanced_bounds<R: RendererDevice>(
+    bounds: Option<Bounds>,
+    instances: &Option<(&Instances<R>, Range<u32>)>,
+) -> Option<Bounds> {
+    match (bounds, instances) {
+        (Some(bounds), Some((insts, range))) if !insts.is_empty() => {
+            let range = range.start as usize..(range.end as usize).min(insts.len());
+            insts
+                .get(range)
+                .and_then(|insts| instances_bounds(&bounds, insts))
+        }
+        (bounds, _) => bounds,
+    }
+}
+
+/// Window space box around the scissor, `None` when scissoring is off
+fn scissor_bounds(scissor: &Scissor) -> Option<Bounds> {
+    let Extent { width, height } = scissor.extent;
+    if width < 0.0 {
+        return None;
+    }
+    let xform = &scissor.xform.0;
+    let ex = width * xform[0].abs() + height * xform[2].abs();
+    let ey = width * xform[1].abs() + height * xform[3].abs();
+    Some(Bounds {
+        min: Point::new(xform[4] - ex, xform[5] - ey),
+        max: Point::new(xform[4] + ex, xform[5] + ey),
+    })
+}
+
+/// Grows `bounds` to whole device pixels within `window`, so the area
+/// cleared by the context matches the renderer's scissor
+fn snap_to_pixels(bounds: &Bounds, ratio: f32, window: &Bounds) -> Bounds {
+    let snap = |v: f32, round: fn(f32) -> f32, max: f32| (round(v * ratio) / ratio).clamp(0.0, max);
+    Bounds {
+        min: Point::new(
+            snap(bounds.min.x, f32::floor, window.max.x),
+            snap(bounds.min.y, f32::floor, window.max.y),
+        ),
+        max: Point::new(
+            snap(bounds.max.x, f32::ceil, window.max.x),
+            snap(bounds.max.y, f32::ceil, window.max.y),
+        ),
+    }
+}
+
+impl<R: RendererDevice> Context<R> {
+    /// Tracks which areas of the window change. The first frame after
+    /// enabling is redrawn in full, later frames only redraw the areas passed
+    /// to [`Context::invalidate`] when the renderer keeps its previous frame.
+    pub fn set_damage_tracking(&mut self, enabled: bool) {
+        self.damage.enabled = enabled;
+        self.damage.full = true;
+        self.damage.invalidated = None;
+    }
+
+    pub fn damage_tracking(&self) -> bool {
+        self.damage.enabled
+    }
+
+    /// Marks `rect`, in window coordinates, to be redrawn in the next frame
+    pub fn invalidate<T: Into<Rect>>(&mut self, rect: T) {
+        let rect = rect.into();
+        if rect.size.width <= 0.0 || rect.size.height <= 0.0 {
+            return;
+        }
+        let bounds = Bounds::from(rect);
+        self.damage.invalidated = Some(match self.damage.invalidated {
+            Some(invalidated) => invalidated.union(&bounds),
+            None => bounds,
+        });
+    }
+
+    /// Redraws the whole window in the next frame
+    pub fn invalidate_all(&mut self) {
+        self.damage.full = true;
+    }
+
+    /// Whether the next frame has anything to redraw. Always true without
+    /// damage tracking, so idle applications can skip their frames.
+    pub fn needs_redraw(&self) -> bool {
+        !self.damage.enabled || self.damage.full || self.damage.invalidated.is_some()
+    }
+
+    /// Area redrawn in the current frame, `None` when it is redrawn in full.
+    /// Draw calls outside of it are skipped.
+    pub fn damage(&self) -> Option<Bounds> {
+        if self.damage.suspended > 0 {
+            return None;
+        }
+        self.damage.area
+    }
+
+    /// Window space bounds of the draw calls of the current frame with damage
+    /// tracking enabled, including the skipped ones
+    pub fn frame_bounds(&self) -> Option<Bounds> {
+        self.damage.drawn
+    }
+
+    /// Picks the area redrawn by the frame beginning on a window of `extent`
+    pub(super) fn begin_damage(&mut self, extent: Extent) -> crate::Result<()> {
+        if self.damage.suspended > 0 {
+            return Ok(());
+        }
+        self.damage.area = None;
+        self.damage.drawn = None;
+        if !self.damage.enabled {
+            self.renderer.damage(None)?;
+            return Ok(());
+        }
+
+        let window = Bounds {
+            min: Point::new(0.0, 0.0),
+            max: Point::new(extent.width, extent.height),
+        };
+        let area = match self.damage.invalidated {
+            _ if self.damage.full => window,
+            Some(invalidated) => snap_to_pixels(&invalidated, self.device_pixel_ratio, &window),
+            None => Bounds::default(),
+        };
+        if self.renderer.damage(Some(area))? {
+            self.damage.area = Some(area);
+        }
+        self.damage.full = false;
+        self.damage.invalidated = None;
+        Ok(())
+    }
+
+    /// Clears the redrawn area of a partial frame, keeping the rest
+    pub(super) fn clear_damage(&mut self, area: Bounds, color: Color) -> crate::Result<()> {
+        // the current path is kept, only the frame's vertices are shared
+        let mut path_cache = std::mem::take(&mut self.path_cache);
+        self.path_cache.cache.vertices = std::mem::take(&mut path_cache.cache.vertices);
+        let result = self.unrecorded(|ctx| {
+            ctx.save();
+            ctx.reset();
+            ctx.global_composite_operation(CompositeOperation::Basic(
+                BasicCompositeOperation::Copy,
+            ));
+            ctx.shape_antialias(false);
+            ctx.fill_paint(color);
+            ctx.rect(Rect::new(
+                area.min,
+                Extent::new(area.width(), area.height()),
+            ));
+            let result = ctx.fill();
+            ctx.restore();
+            result
+        });
+        path_cache.cache.vertices = std::mem::take(&mut self.path_cache.cache.vertices);
+        self.path_cache = path_cache;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Transform;
+
+    fn bounds(x0: f32, y0: f32, x1: f32, y1: f32) -> Bounds {
+        Bounds {
+            min: Point::new(x0, y0),
+            max: Point::new(x1, y1),
+        }
+    }
+
+    fn assert_bounds(actual: Option<Bounds>, expected: Bounds) {
+        let actual = actual.expect("no bounds");
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-4;
+        assert!(
+            close(actual.min.x, expected.min.x)
+                && close(actual.min.y, expected.min.y)
+                && close(actual.max.x, expected.max.x)
+                && close(actual.max.y, expected.max.y),
+            "{:?} instead of {:?}",
+            actual,
+            expected
+        );
+    }
+
+    fn scissor(x: f32, y: f32, width: f32, height: f32) -> Scissor {
+        Scissor {
+            xform: Transform::translate(x + width * 0.5, y + height * 0.5),
+            extent: Extent::new(width * 0.5, height * 0.5),
+        }
+    }
+
+    fn no_scissor() -> Scissor {
+        Scissor {
+            xform: Transform::default(),
+            extent: Extent::new(-1.0, -1.0),
+        }
+    }
+
+    #[test]
+    fn snaps_to_device_pixels() {
+        let window = bounds(0.0, 0.0, 100.0, 50.0);
+        let area = bounds(10.2, 10.7, 20.2, 20.7);
+        assert_bounds(
+            Some(snap_to_pixels(&area, 1.0, &window)),
+            bounds(10.0, 10.0, 21.0, 21.0),
+        );
+        // half logical pixels on a 2x display
+        assert_bounds(
+            Some(snap_to_pixels(&area, 2.0, &window)),
+            bounds(10.0, 10.5, 20.5, 21.0),
+        );
+        assert_bounds(
+            Some(snap_to_pixels(
+                &bounds(-5.0, 40.0, 120.0, 60.0),
+                1.5,
+                &window,
+            )),
+            bounds(0.0, 40.0, 100.0, 50.0),
+        );
+    }
+
+    #[test]
+    fn vertex_and_instance_bounds() {
+        let vertices = [(1.0, 5.0), (-2.0, 3.0), (4.0, -1.0)]
+            .iter()
+            .map(|&(x, y)| Vertex {
+                x,
+                y,
+                u: 0.0,
+                v: 0.0,
+            })
+            .collect::<Vec<_>>();
+        assert!(vertex_bounds(&[]).is_none());
+        assert_bounds(vertex_bounds(&vertices), bounds(-2.0, -1.0, 4.0, 5.0));
+
+        let square = bounds(0.0, 0.0, 2.0, 2.0);
+        let instances: Vec<Instance> = vec![
+            Transform::translate(10.0, 0.0).into(),
+            (Transform::rotate(std::f32::consts::FRAC_PI_2) * Transform::translate(0.0, 20.0))
+                .into(),
+        ];
+        assert_bounds(
+            instances_bounds(&square, &instances[..1]),
+            bounds(10.0, 0.0, 12.0, 2.0),
+        );
+        // the rotated copy spans x -2..0 and y 20..22
+        assert_bounds(
+            instances_bounds(&square, &instances),
+            bounds(-2.0, 0.0, 12.0, 22.0),
+        );
+    }
+
+    #[test]
+    fn culls_outside_of_area() {
+        let mut tracker = DamageTracker {
+            enabled: true,
+            area: Some(bounds(0.0, 0.0, 10.0, 10.0)),
+            ..Default::default()
+        };
+        assert!(!tracker.cull(|| Some(bounds(5.0, 5.0, 15.0, 15.0)), &no_scissor()));
+        assert!(tracker.cull(|| Some(bounds(20.0, 0.0, 30.0, 10.0)), &no_scissor()));
+        // clipped by the scissor before testing
+        assert!(tracker.cull(
+            || Some(bounds(5.0, 5.0, 40.0, 40.0)),
+            &scissor(20.0, 20.0, 10.0, 10.0)
+        ));
+        // skipped calls count towards the frame bounds
+        assert_bounds(tracker.drawn, bounds(5.0, 0.0, 30.0, 30.0));
+
+        // calls without bounds are always drawn
+        assert!(!tracker.cull(|| None, &no_scissor()));
+        tracker.suspended = 1;
+        assert!(!tracker.cull(|| Some(bounds(20.0, 0.0, 30.0, 10.0)), &no_scissor()));
+    }
+}
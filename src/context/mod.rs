@@ -1,7 +1,9 @@
 use crate::{
-    cache::PathCache, renderer::TextureType, LineCap, LineJoin, Path, PathCommands, PathSlice,
+    cache::PathCache, renderer::TextureType, Bounds, LineCap, LineJoin, Path, PathCommands,
+    PathSlice,
 };
 
+mod buffer_pool;
 mod composite;
 mod core;
 mod core_fb;
@@ -10,9 +12,23 @@ mod core_image;
 mod core_path;
 mod core_path_cache;
 mod core_path_commands;
+mod damage;
+mod picture;
+mod stats;
+mod tessellation;
+mod text_mesh;
+#[cfg(feature = "trace")]
+mod trace;
 
+pub use buffer_pool::*;
 pub use composite::*;
 pub use core::*;
+pub use picture::*;
+pub use stats::*;
+pub use tessellation::*;
+pub use text_mesh::*;
+#[cfg(feature = "trace")]
+pub use trace::*;
 use std::ops::{Deref, DerefMut};
 
 pub type ImageId = usize;
@@ -29,6 +45,60 @@ bitflags! {
     }
 }
 
+bitflags! {
+    pub struct TextDecoration: u32 {
+        /// Line below the baseline, using the font's underline metrics.
+        const UNDERLINE = 0x1;
+        /// Line through the text, using the font's strikeout metrics.
+        const STRIKETHROUGH = 0x2;
+        /// Line along the font's ascender.
+        const OVERLINE = 0x4;
+    }
+}
+
+/// Direction in which text advances.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WritingMode {
+    Horizontal,
+    /// Top to bottom. CJK characters are set upright, other characters are
+    /// rotated 90° clockwise.
+    Vertical,
+}
+
+/// Where text that does not fit is replaced with an ellipsis.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TextEllipsis {
+    Start,
+    Middle,
+    End,
+}
+
+/// How glyph coverage is rasterized and blended.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TextAntialias {
+    Grayscale,
+    /// Separate coverage for the red, green and blue subpixels of
+    /// horizontal RGB displays, for solid color paints with the default
+    /// composite operation. Other paints fall back to grayscale; the text
+    /// should be drawn onto an opaque background.
+    Lcd,
+}
+
+/// Where glyphs are placed on the pixel grid.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GlyphPositioning {
+    /// Glyph origins are snapped to whole pixels.
+    #[default]
+    Pixel,
+    /// Glyph origins are snapped to `1 / steps` of a pixel horizontally, and
+    /// each offset is cached as a separate glyph variant.
+    Subpixel { steps: u8 },
+}
+
 bitflags! {
     pub struct ImageFlags: u32 {
         /// Generate mipmaps during creation of the image.
@@ -59,6 +129,55 @@ impl TextMetrics {
     }
 }
 
+/// Position of a character in a line of text, e.g. for hit testing and
+/// caret placement.
+#[derive(Debug, Copy, Clone)]
+pub struct GlyphPosition {
+    /// Byte offset of the character in the text.
+    pub offset: usize,
+    /// Origin of the glyph.
+    pub x: f32,
+    /// Horizontal extent of the glyph's advance and ink.
+    pub min_x: f32,
+    pub max_x: f32,
+}
+
+/// Metrics of a font at a given size. Vertical positions are measured from
+/// the baseline and are positive upwards.
+#[derive(Debug, Copy, Clone)]
+pub struct FontMetrics {
+    /// Design units per em of the font, unscaled.
+    pub units_per_em: u16,
+    pub ascender: f32,
+    pub descender: f32,
+    pub line_gap: f32,
+    pub x_height: f32,
+    pub cap_height: f32,
+    /// Top edge of the underline.
+    pub underline_position: f32,
+    pub underline_thickness: f32,
+    /// Top edge of the strikeout line.
+    pub strikeout_position: f32,
+    pub strikeout_thickness: f32,
+}
+
+impl FontMetrics {
+    pub fn line_height(&self) -> f32 {
+        self.ascender - self.descender + self.line_gap
+    }
+}
+
+/// Metrics of a single glyph at a given size.
+#[derive(Debug, Copy, Clone)]
+pub struct GlyphMetrics {
+    pub advance: f32,
+    pub left_side_bearing: f32,
+    /// Ink bounds relative to the glyph origin on the baseline, with y
+    /// pointing down like the drawing coordinates. Empty for glyphs without
+    /// outline, such as spaces.
+    pub bounds: Bounds,
+}
+
 pub(crate) trait FlattenExpandPath {
     fn path_commands(&self) -> &PathCommands;
 
@@ -0,0 +1,542 @@
+use super::core::State;
+use super::text_mesh::TextMeshSource;
+use super::{
+    Align, CompositeOperation, Context, TextAntialias, TextDecoration, TextEllipsis, WritingMode,
+};
+use crate::fonts::{FontId, TextSpan};
+use crate::{
+    Color, DrawPathStyle, Instance, Instances, LineCap, LineJoin, Paint, PaintPattern, Path,
+    PathCommands, PathDir, PathFillType, Point, Rect, RendererDevice, Transform,
+};
+use std::ops::Range;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct RecordedSpan {
+    pub(super) text: String,
+    pub(super) font_id: FontId,
+    pub(super) size: f32,
+    pub(super) paint: PaintPattern,
+    pub(super) letter_spacing: f32,
+    pub(super) decoration: TextDecoration,
+}
+
+impl RecordedSpan {
+    pub(super) fn from_spans(spans: &[TextSpan]) -> Vec<RecordedSpan> {
+        spans
+            .iter()
+            .map(|span| RecordedSpan {
+                text: span.text.to_string(),
+                font_id: span.font_id,
+                size: span.size,
+                paint: span.paint,
+                letter_spacing: span.letter_spacing,
+                decoration: span.decoration,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) enum DrawCommand {
+    Save,
+    Restore,
+    Reset,
+    ShapeAntialias(bool),
+    StrokeWidth(f32),
+    MiterLimit(f32),
+    LineCap(LineCap),
+    LineJoin(LineJoin),
+    GlobalAlpha(f32),
+    Transform(Transform),
+    ResetTransform,
+    StrokePaint(PaintPattern),
+    FillPaint(PaintPattern),
+    FillType(PathFillType),
+    Scissor(Rect),
+    IntersectScissor(Rect),
+    ResetScissor,
+    CompositeOperation(CompositeOperation),
+    BeginPath,
+    MoveTo(Point),
+    LineTo(Point),
+    BezierTo(Point, Point, Point),
+    QuadTo(Point, Point),
+    ArcTo(Point, Point, f32),
+    Arc(Point, f32, f32, f32, PathDir),
+    Rect(Rect),
+    RoundedRectVarying(Rect, [f32; 4]),
+    Ellipse(Point, f32, f32),
+    PathWinding(PathDir),
+    ClosePath,
+    Fill,
+    Stroke,
+    #[cfg(feature = "wirelines")]
+    Wirelines,
+    FontSize(f32),
+    LetterSpacing(f32),
+    LineHeight(f32),
+    TextAlign(Align),
+    TextDecoration(TextDecoration),
+    TextDecorationSkipInk(bool),
+    WritingMode(WritingMode),
+    TextAntialias(TextAntialias),
+    FontId(FontId),
+    Font(String),
+    Text(Point, String),
+    TextTruncated(Point, String, f32, usize, TextEllipsis),
+    RichText(Point, Option<f32>, Vec<RecordedSpan>),
+    Picture(Picture, Transform, f32),
+    Clear(Color),
+    DrawPath {
+        path: PathCommands,
+        paint: Box<Paint>,
+        style: DrawPathStyle,
+        instances: Option<(Vec<Instance>, Range<u32>)>,
+    },
+    DrawTextMesh {
+        source: TextMeshSource,
+        instances: Option<(Vec<Instance>, Range<u32>)>,
+    },
+}
+
+/// Copies the instances a retained path or text mesh is drawn with
+pub(super) fn recorded_instances<R: RendererDevice>(
+    instances: &Option<(&Instances<R>, Range<u32>)>,
+) -> Option<(Vec<Instance>, Range<u32>)> {
+    instances
+        .as_ref()
+        .map(|(insts, range)| (insts.instances.clone(), range.clone()))
+}
+
+/// A recorded list of drawing calls, made between [`Context::begin_picture`]
+/// and [`Context::end_picture`] and replayed with [`Context::draw_picture`].
+///
+/// A picture holds no renderer resources, so it can be handed to other
+/// threads and replayed on many frames; images and fonts are referenced by id
+/// and must exist when replaying.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Picture {
+    pub(super) commands: Vec<DrawCommand>,
+}
+
+impl Picture {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+}
+
+struct Recording {
+    commands: Vec<DrawCommand>,
+    /// Depth of the state stack when the recording began
+    depth: usize,
+    /// Path of the caller, put back when the recording ends
+    path: PathCommands,
+}
+
+/// Pictures being recorded, the innermost last
+#[derive(Default)]
+pub(super) struct PictureRecorder {
+    recordings: Vec<Recording>,
+    /// Recording is suspended while replaying pictures
+    pub(super) suspended: usize,
+}
+
+impl PictureRecorder {
+    /// Whether calls go into a picture instead of being drawn
+    #[inline]
+    pub(super) fn active(&self) -> bool {
+        self.suspended == 0 && !self.recordings.is_empty()
+    }
+
+    pub(super) fn push(&mut self, cmd: DrawCommand) {
+        if let Some(recording) = self.recordings.last_mut() {
+            recording.commands.push(cmd);
+        }
+    }
+
+    /// States which a restore must not pop while recording
+    pub(super) fn state_depth(&self) -> usize {
+        match self.recordings.last() {
+            Some(recording) if self.suspended == 0 => recording.depth + 1,
+            _ => 1,
+        }
+    }
+}
+
+/// The state a picture is replayed into. Commands which reset state go back
+/// to `outer` rather than to the defaults.
+pub(super) struct ReplayScope {
+    pub(super) depth: usize,
+    pub(super) outer: State,
+    pub(super) base_alpha: f32,
+}
+
+impl<R: RendererDevice> Context<R> {
+    /// Starts recording the calls that follow into a picture, returned by
+    /// [`Context::end_picture`]. Fills, strokes, text, retained paths, text
+    /// meshes, clears and pictures are recorded instead of drawn; retained
+    /// paths and text meshes are copied, so later changes to them are not
+    /// seen by the picture. Recordings can be nested.
+    pub fn begin_picture(&mut self) {
+        let depth = self.states.len();
+        let state = self.states.last().unwrap().clone();
+        self.states.push(state);
+        self.pictures.recordings.push(Recording {
+            commands: Vec::new(),
+            depth,
+            path: std::mem::take(&mut self.path_cache.path),
+        });
+    }
+
+    /// Ends the innermost recording, putting back the state and the path
+    /// from before [`Context::begin_picture`].
+    pub fn end_picture(&mut self) -> Picture {
+        match self.pictures.recordings.pop() {
+            Some(recording) => {
+                self.states.truncate(recording.depth);
+                self.path_cache.path = recording.path;
+                Picture {
+                    commands: recording.commands,
+                }
+            }
+            None => Picture::default(),
+        }
+    }
+
+    /// Replays `picture` on top of the current state, transformed by `xform`
+    /// and with its alpha multiplied by `alpha`. The picture is clipped by the
+    /// current scissor and leaves the state and the path as it found them.
+    pub fn draw_picture(
+        &mut self,
+        picture: &Picture,
+        xform: Transform,
+        alpha: f32,
+    ) -> crate::Result<()> {
+        self.record(|| DrawCommand::Picture(picture.clone(), xform, alpha));
+        if self.pictures.active() {
+            return Ok(());
+        }
+        self.unrecorded(|ctx| {
+            let depth = ctx.states.len();
+            let path = std::mem::take(&mut ctx.path_cache.path);
+            ctx.save();
+            ctx.transform(xform);
+            let outer = ctx.states.last().unwrap().clone();
+            let base_alpha = outer.paint.alpha * alpha;
+            ctx.global_alpha(base_alpha);
+
+            let scope = ReplayScope {
+                depth,
+                outer,
+                base_alpha,
+            };
+            let result = picture
+                .commands
+                .iter()
+                .try_for_each(|cmd| ctx.replay_command(cmd, &scope));
+            ctx.states.truncate(depth);
+            ctx.path_cache.path = path;
+            result
+        })
+    }
+
+    pub(super) fn replay_command(
+        &mut self,
+        cmd: &DrawCommand,
+        scope: &ReplayScope,
+    ) -> crate::Result<()> {
+        let ReplayScope {
+            depth,
+            outer,
+            base_alpha,
+        } = scope;
+        match cmd {
+            DrawCommand::Save => self.save(),
+            DrawCommand::Restore => {
+                // never unwind past the state saved for the replay
+                if self.states.len() > depth + 1 {
+                    self.restore();
+                }
+            }
+            DrawCommand::Reset => {
+                self.reset();
+                let state = self.state_mut();
+                state.xform = outer.xform;
+                state.scissor = outer.scissor;
+                state.paint.alpha = *base_alpha;
+            }
+            DrawCommand::ShapeAntialias(enabled) => self.shape_antialias(*enabled),
+            DrawCommand::StrokeWidth(width) => self.stroke_width(*width),
+            DrawCommand::MiterLimit(limit) => self.miter_limit(*limit),
+            DrawCommand::LineCap(cap) => self.line_cap(*cap),
+            DrawCommand::LineJoin(join) => self.line_join(*join),
+            DrawCommand::GlobalAlpha(alpha) => self.global_alpha(alpha * base_alpha),
+            DrawCommand::Transform(xform) => self.transform(*xform),
+            DrawCommand::ResetTransform => self.state_mut().xform = outer.xform,
+            DrawCommand::StrokePaint(paint) => self.stroke_paint(*paint),
+            DrawCommand::FillPaint(paint) => self.fill_paint(*paint),
+            DrawCommand::FillType(fill_type) => self.fill_type(*fill_type),
+            DrawCommand::Scissor(rect) => {
+                if outer.scissor.extent.width < 0.0 {
+                    self.scissor(*rect);
+                } else {
+                    self.state_mut().scissor = outer.scissor;
+                    self.intersect_scissor(*rect);
+                }
+            }
+            DrawCommand::IntersectScissor(rect) => self.intersect_scissor(*rect),
+            DrawCommand::ResetScissor => self.state_mut().scissor = outer.scissor,
+            DrawCommand::CompositeOperation(op) => self.global_composite_operation(*op),
+            DrawCommand::BeginPath => self.begin_path(),
+            DrawCommand::MoveTo(pt) => self.move_to(*pt),
+            DrawCommand::LineTo(pt) => self.line_to(*pt),
+            DrawCommand::BezierTo(cp1, cp2, pt) => self.bezier_to(*cp1, *cp2, *pt),
+            DrawCommand::QuadTo(cp, pt) => self.quad_to(*cp, *pt),
+            DrawCommand::ArcTo(pt1, pt2, radius) => self.arc_to(*pt1, *pt2, *radius),
+            DrawCommand::Arc(cp, radius, a0, a1, dir) => self.arc(*cp, *radius, *a0, *a1, *dir),
+            DrawCommand::Rect(rect) => self.rect(*rect),
+            DrawCommand::RoundedRectVarying(rect, [lt, rt, rb, lb]) => {
+                self.rounded_rect_varying(*rect, *lt, *rt, *rb, *lb)
+            }
+            DrawCommand::Ellipse(center, rx, ry) => self.ellipse(*center, *rx, *ry),
+            DrawCommand::PathWinding(dir) => self.path_winding(*dir),
+            DrawCommand::ClosePath => self.close_path(),
+            DrawCommand::Fill => self.fill()?,
+            DrawCommand::Stroke => self.stroke()?,
+            #[cfg(feature = "wirelines")]
+            DrawCommand::Wirelines => self.wirelines()?,
+            DrawCommand::FontSize(size) => self.font_size(*size),
+            DrawCommand::LetterSpacing(spacing) => self.text_letter_spacing(*spacing),
+            DrawCommand::LineHeight(line_height) => self.text_line_height(*line_height),
+            DrawCommand::TextAlign(align) => self.text_align(*align),
+            DrawCommand::TextDecoration(decoration) => self.text_decoration(*decoration),
+            DrawCommand::TextDecorationSkipInk(skip_ink) => {
+                self.text_decoration_skip_ink(*skip_ink)
+            }
+            DrawCommand::WritingMode(mode) => self.text_writing_mode(*mode),
+            DrawCommand::TextAntialias(antialias) => self.text_antialias(*antialias),
+            DrawCommand::FontId(id) => self.fontid(*id),
+            DrawCommand::Font(name) => self.font(name),
+            DrawCommand::Text(pt, text) => self.text(*pt, text)?,
+            DrawCommand::TextTruncated(pt, text, max_width, max_lines, ellipsis) => {
+                self.text_truncated(*pt, text, *max_width, *max_lines, *ellipsis)?
+            }
+            DrawCommand::RichText(pt, break_width, spans) => {
+                let spans: Vec<TextSpan> = spans
+                    .iter()
+                    .map(|span| TextSpan {
+                        text: &span.text,
+                        font_id: span.font_id,
+                        size: span.size,
+                        paint: span.paint,
+                        letter_spacing: span.letter_spacing,
+                        decoration: span.decoration,
+                    })
+                    .collect();
+                match break_width {
+                    Some(width) => self.rich_text_box(*pt, *width, &spans)?,
+                    None => self.rich_text(*pt, &spans)?,
+                }
+            }
+            DrawCommand::Picture(picture, xform, alpha) => {
+                self.draw_picture(picture, *xform, *alpha)?
+            }
+            DrawCommand::Clear(color) => self.clear(*color)?,
+            DrawCommand::DrawPath {
+                path,
+                paint,
+                style,
+                instances,
+            } => {
+                let path = Path::<R>::from(path.clone());
+                let instances = self.replay_instances(instances)?;
+                self.draw_path(
+                    &path,
+                    paint,
+                    *style,
+                    instances
+                        .as_ref()
+                        .map(|(instances, range)| (instances, range.clone())),
+                )?;
+            }
+            DrawCommand::DrawTextMesh { source, instances } => {
+                let mesh = self.layout_text_mesh(source.clone())?;
+                let instances = self.replay_instances(instances)?;
+                self.draw_text_mesh(
+                    &mesh,
+                    instances
+                        .as_ref()
+                        .map(|(instances, range)| (instances, range.clone())),
+                )?;
+            }
+        }
+        Ok(())
+    }
+    fn replay_instances(
+        &mut self,
+        instances: &Option<(Vec<Instance>, Range<u32>)>,
+    ) -> crate::Result<Option<(Instances<R>, Range<u32>)>> {
+        match instances {
+            Some((insts, range)) => {
+                let instances = Instances::<R>::new(insts.clone());
+                self.update_instances(&instances)?;
+                Ok(Some((instances, range.clone())))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::tests::{Call, NullRenderer};
+    use crate::Extent;
+
+    fn context() -> Context<NullRenderer> {
+        let mut ctx = Context::create(NullRenderer::default()).unwrap();
+        ctx.begin_frame(Extent::new(100.0, 100.0), 1.0).unwrap();
+        ctx
+    }
+
+    fn fill_rect(ctx: &mut Context<NullRenderer>) {
+        ctx.begin_path();
+        ctx.rect((10.0, 10.0, 20.0, 20.0));
+        ctx.fill().unwrap();
+    }
+
+    fn fills(ctx: &Context<NullRenderer>) -> Vec<PaintPattern> {
+        let calls = &ctx.renderer().calls;
+        calls
+            .iter()
+            .filter_map(|call| match call {
+                Call::Fill(paint) => Some(*paint),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unbalanced_restore() {
+        let mut ctx = context();
+        ctx.save();
+        ctx.global_alpha(0.5);
+        ctx.begin_picture();
+        ctx.restore();
+        ctx.restore();
+        assert_eq!(ctx.states.len(), 3);
+        ctx.global_alpha(0.25);
+        fill_rect(&mut ctx);
+        let picture = ctx.end_picture();
+        assert_eq!(ctx.states.len(), 2);
+        assert_eq!(ctx.states.last().unwrap().paint.alpha, 0.5);
+
+        // the recorded restores can't pop the states of the caller either
+        ctx.draw_picture(&picture, Transform::identity(), 1.0)
+            .unwrap();
+        assert_eq!(ctx.states.len(), 2);
+        assert_eq!(ctx.states.last().unwrap().paint.alpha, 0.5);
+        assert_eq!(fills(&ctx).len(), 1);
+        assert_eq!(fills(&ctx)[0].inner_color.a, 0.125);
+    }
+
+    #[test]
+    fn nested_recordings() {
+        let mut ctx = context();
+        ctx.begin_path();
+        ctx.move_to((1.0, 2.0));
+        ctx.begin_picture();
+        fill_rect(&mut ctx);
+        ctx.begin_picture();
+        ctx.fill_paint(Color::rgb(1.0, 0.0, 0.0));
+        fill_rect(&mut ctx);
+        let inner = ctx.end_picture();
+        ctx.draw_picture(&inner, Transform::translate(5.0, 0.0), 1.0)
+            .unwrap();
+        let outer = ctx.end_picture();
+        assert!(ctx.renderer().calls.is_empty());
+        assert_eq!(inner.commands.len(), 4);
+        assert!(matches!(
+            outer.commands.last(),
+            Some(DrawCommand::Picture(_, _, _))
+        ));
+        // the caller's path survives both recordings
+        assert_eq!(ctx.path_cache.path.commands.len(), 1);
+
+        ctx.draw_picture(&outer, Transform::identity(), 1.0)
+            .unwrap();
+        let fills = fills(&ctx);
+        assert_eq!(fills.len(), 2);
+        assert_eq!(fills[0].inner_color, Color::rgb(1.0, 1.0, 1.0));
+        assert_eq!(fills[1].inner_color, Color::rgb(1.0, 0.0, 0.0));
+        assert_eq!(fills[1].xform.0, Transform::translate(5.0, 0.0).0);
+    }
+
+    #[test]
+    fn replay_transform_and_alpha() {
+        let mut ctx = context();
+        ctx.begin_picture();
+        ctx.global_alpha(0.5);
+        ctx.fill_paint(Color::rgb(0.0, 0.0, 1.0));
+        fill_rect(&mut ctx);
+        ctx.reset_transform();
+        ctx.global_alpha(1.0);
+        ctx.fill_paint(Color::rgb(0.0, 1.0, 0.0));
+        fill_rect(&mut ctx);
+        let picture = ctx.end_picture();
+
+        ctx.translate(1.0, 2.0);
+        ctx.global_alpha(0.8);
+        ctx.draw_picture(&picture, Transform::scale(2.0, 2.0), 0.5)
+            .unwrap();
+        let fills = fills(&ctx);
+        let xform = Transform::scale(2.0, 2.0) * Transform::translate(1.0, 2.0);
+        assert_eq!(fills[0].xform.0, xform.0);
+        assert!((fills[0].inner_color.a - 0.2).abs() < 1e-6);
+        // resets go back to the state the picture is drawn with
+        assert_eq!(fills[1].xform.0, xform.0);
+        assert!((fills[1].inner_color.a - 0.4).abs() < 1e-6);
+        assert_eq!(ctx.states.last().unwrap().paint.alpha, 0.8);
+    }
+
+    #[test]
+    fn retained_draws_keep_their_order() {
+        let mut ctx = context();
+        let mut path = Path::new();
+        path.rect((0.0, 0.0, 10.0, 10.0));
+        let font = ctx
+            .create_font(
+                "roboto",
+                &include_bytes!("../../nvgx-demo/Roboto-Bold.ttf")[..],
+            )
+            .unwrap();
+        ctx.fontid(font);
+        let mesh = ctx.create_text_mesh((10.0, 50.0), "Hi").unwrap();
+
+        ctx.begin_picture();
+        ctx.clear(Color::rgb(0.0, 0.0, 0.0)).unwrap();
+        fill_rect(&mut ctx);
+        ctx.draw_path(&path, &Paint::new(), DrawPathStyle::STROKE, None)
+            .unwrap();
+        ctx.draw_text_mesh(&mesh, None).unwrap();
+        let picture = ctx.end_picture();
+        assert!(ctx.renderer().calls.is_empty());
+
+        ctx.draw_picture(&picture, Transform::identity(), 1.0)
+            .unwrap();
+        let black = Color::rgb(0.0, 0.0, 0.0);
+        let white = Color::rgb(1.0, 1.0, 1.0);
+        match &ctx.renderer().calls[..] {
+            [Call::Clear(clear), Call::Fill(_), Call::Stroke(stroke), Call::Triangles(text)] => {
+                assert_eq!(*clear, black);
+                assert_eq!(stroke.inner_color, black);
+                assert_eq!(text.inner_color, white);
+            }
+            calls => panic!("unexpected calls {:?}", calls),
+        }
+    }
+}
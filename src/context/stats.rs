@@ -0,0 +1,86 @@
+use crate::path::cache::PathSlice;
+use std::time::Duration;
+
+/// Work of a frame, returned by [`super::Context::end_frame`]. Calls are
+/// counted once per call to the renderer, whatever number of paths or glyphs
+/// it draws. Instanced draws count the triangles of every instance.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FrameStats {
+    pub fill_calls: usize,
+    pub stroke_calls: usize,
+    pub line_calls: usize,
+    pub text_calls: usize,
+    /// Draw calls the renderer made for the frame once it merged batchable
+    /// calls, when it reports them
+    pub renderer_calls: Option<usize>,
+    pub fill_triangles: usize,
+    pub stroke_triangles: usize,
+    pub text_triangles: usize,
+    /// Vertices of the frame and of uploaded paths and text meshes
+    pub uploaded_vertices: usize,
+    pub uploaded_instances: usize,
+    /// Uploads to images and to the font texture
+    pub texture_uploads: usize,
+    /// Share of the font texture taken by cached glyphs, from 0 to 1
+    pub atlas_usage: f32,
+    /// CPU time spent tessellating paths
+    pub tessellation_time: Duration,
+    /// GPU time of a recent frame, when the renderer measures it
+    pub gpu_time: Option<Duration>,
+}
+
+impl FrameStats {
+    pub fn draw_calls(&self) -> usize {
+        self.fill_calls + self.stroke_calls + self.line_calls + self.text_calls
+    }
+
+    pub fn triangles(&self) -> usize {
+        self.fill_triangles + self.stroke_triangles + self.text_triangles
+    }
+}
+
+/// Triangles of the fans and fringes of filled paths
+pub(super) fn fill_triangles(paths: &[PathSlice]) -> usize {
+    paths.iter().fold(0, |acc, path| {
+        acc + path.num_fill.saturating_sub(2) + path.num_stroke.saturating_sub(2)
+    })
+}
+
+/// Triangles of the strips of stroked paths
+pub(super) fn stroke_triangles(paths: &[PathSlice]) -> usize {
+    paths
+        .iter()
+        .fold(0, |acc, path| acc + path.num_stroke.saturating_sub(2))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::renderer::tests::NullRenderer;
+    use crate::{Context, DrawPathStyle, Extent, Paint, Path};
+
+    #[test]
+    fn calls_count_renderer_calls() {
+        let mut ctx = Context::create(NullRenderer::default()).unwrap();
+        ctx.begin_frame(Extent::new(100.0, 100.0), 1.0).unwrap();
+        ctx.begin_path();
+        ctx.rect((10.0, 10.0, 20.0, 20.0));
+        ctx.rect((40.0, 10.0, 20.0, 20.0));
+        ctx.rect((70.0, 10.0, 20.0, 20.0));
+        ctx.fill().unwrap();
+        ctx.stroke().unwrap();
+
+        let mut path = Path::new();
+        path.rect((0.0, 50.0, 10.0, 10.0));
+        path.rect((20.0, 50.0, 10.0, 10.0));
+        let style = DrawPathStyle::FILL | DrawPathStyle::STROKE;
+        ctx.draw_path(&path, &Paint::new(), style, None).unwrap();
+
+        let stats = ctx.end_frame().unwrap();
+        assert_eq!(stats.fill_calls, 2);
+        assert_eq!(stats.stroke_calls, 2);
+        assert_eq!(stats.draw_calls(), ctx.renderer().calls.len());
+        // the fans of five rectangles, two triangles each
+        assert!(stats.fill_triangles >= 10);
+        assert_eq!(stats.renderer_calls, None);
+    }
+}
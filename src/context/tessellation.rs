@@ -0,0 +1,116 @@
+use super::core_path_cache::PathRefWithCache;
+use super::damage::paths_bounds;
+use super::Context;
+use crate::{BufferUsage, DrawPathStyle, Paint, Path, RendererDevice};
+
+/// Tolerances and antialiasing of a context, to tessellate paths away from
+/// it with [`Path::tessellate`]
+#[derive(Debug, Copy, Clone)]
+pub struct Tessellation {
+    dist_tol: f32,
+    tess_tol: f32,
+    fringe_width: f32,
+    device_pixel_ratio: f32,
+    edge_antialias: bool,
+}
+
+impl<R: RendererDevice> Path<R> {
+    /// Tessellates the path for drawing with `paint` in `style`, keeping the
+    /// vertices on the CPU until [`Context::upload_paths`] or
+    /// [`Context::draw_path`] uploads them. Does nothing when the path is
+    /// already tessellated for `style`.
+    pub fn tessellate(&self, tessellation: &Tessellation, paint: &Paint, style: DrawPathStyle) {
+        let mut path_cache = PathRefWithCache::new(self);
+        let cached_style = path_cache.path_mut_inner.style;
+        if cached_style.contains(style) {
+            return;
+        }
+        let Tessellation {
+            dist_tol,
+            tess_tol,
+            fringe_width,
+            device_pixel_ratio,
+            edge_antialias,
+        } = *tessellation;
+
+        let new_style = cached_style | style;
+        if new_style.contains(DrawPathStyle::FILL) {
+            path_cache.path_mut_inner.draw_slice.bounds_offset = Context::<R>::expand_fill_path(
+                &mut path_cache,
+                paint,
+                dist_tol,
+                tess_tol,
+                fringe_width,
+            );
+        }
+        if new_style.contains(DrawPathStyle::STROKE) {
+            let antialias = edge_antialias && paint.antialias;
+            let (_, stroke_width) = paint.get_stroke(
+                antialias,
+                fringe_width,
+                self.xform.average_scale(),
+                device_pixel_ratio,
+            );
+            Context::<R>::expand_stroke_path(
+                &mut path_cache,
+                antialias,
+                stroke_width,
+                paint,
+                dist_tol,
+                tess_tol,
+                fringe_width,
+            );
+        }
+        #[cfg(feature = "wirelines")]
+        if new_style.contains(DrawPathStyle::WIRELINES) {
+            Context::<R>::expand_wirelines_path(&mut path_cache, dist_tol, tess_tol);
+        }
+
+        let vertices = std::mem::take(&mut path_cache.cache.vertices);
+        let inner = &mut *path_cache.path_mut_inner;
+        let draw_slice = &inner.draw_slice;
+        inner.draw_slice.bounds = [&draw_slice.fill, &draw_slice.stroke, &draw_slice.lines]
+            .iter()
+            .filter_map(|paths| paths_bounds(&vertices, paths))
+            .reduce(|acc, bounds| acc.union(&bounds));
+        inner.vertices = vertices;
+        inner.style = new_style;
+    }
+}
+
+impl<R: RendererDevice> Context<R> {
+    /// Parameters paths are tessellated with in the current frame
+    pub fn tessellation(&self) -> Tessellation {
+        Tessellation {
+            dist_tol: self.dist_tol,
+            tess_tol: self.tess_tol,
+            fringe_width: self.fringe_width,
+            device_pixel_ratio: self.device_pixel_ratio,
+            edge_antialias: self.renderer.edge_antialias(),
+        }
+    }
+
+    /// Uploads the vertices of paths tessellated by [`Path::tessellate`]
+    pub fn upload_paths<'p, I>(&mut self, paths: I) -> crate::Result<()>
+    where
+        I: IntoIterator<Item = &'p Path<R>>,
+        R: 'p,
+    {
+        for path in paths {
+            self.upload_path(path)?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn upload_path(&mut self, path: &Path<R>) -> crate::Result<()> {
+        let mut inner = path.inner.lock().unwrap();
+        let inner = &mut *inner;
+        if inner.vertices.is_empty() {
+            return Ok(());
+        }
+        let vertex_data = bytemuck::cast_slice(&inner.vertices);
+        self.upload_vertex_buffer(&mut inner.vertex_buffer, vertex_data, BufferUsage::Vertex)?;
+        inner.vertices = Vec::new();
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+use super::core_font::{lcd_paint, push_quad};
+use super::damage::{instanced_bounds, vertex_bounds};
+use super::picture::{recorded_instances, DrawCommand};
+use super::{Align, Context, ImageId, PooledBuffer, TextAntialias, TextDecoration, WritingMode};
+use crate::fonts::{FontId, LayoutChar, LayoutRun};
+use crate::path::cache::Vertex;
+use crate::{
+    Bounds, BufferUsage, Color, Instances, PaintPattern, Point, RendererDevice, Transform,
+    VertexSlice,
+};
+use std::ops::Range;
+use std::sync::Mutex;
+
+/// Text and style a [`TextMesh`] is laid out with
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct TextMeshSource {
+    pub(super) pt: Point,
+    pub(super) text: String,
+    pub(super) xform: Transform,
+    pub(super) device_pixel_ratio: f32,
+    pub(super) font_id: FontId,
+    pub(super) font_size: f32,
+    pub(super) letter_spacing: f32,
+    pub(super) text_align: Align,
+    pub(super) writing_mode: WritingMode,
+    pub(super) text_decoration: TextDecoration,
+    pub(super) text_decoration_skip_ink: bool,
+    pub(super) text_antialias: TextAntialias,
+}
+
+struct TextMeshInner<R: RendererDevice> {
+    /// Laid out characters, before their glyphs are cached
+    chars: Vec<LayoutChar>,
+    /// Line of horizontal text, decorated once its glyphs are cached
+    run: Option<LayoutRun>,
+    /// Horizontal LCD text, drawn as such with paints allowing it
+    allow_lcd: bool,
+    /// The glyphs are cached for LCD text
+    lcd: bool,
+    /// Font texture generation the vertices were built for
+    generation: Option<u64>,
+    vertex_buffer: Option<PooledBuffer<R>>,
+    /// Decorations and glyphs drawn from the font texture
+    text: VertexSlice,
+    /// Runs of custom glyphs of the same image
+    images: Vec<(ImageId, VertexSlice)>,
+    bounds: Option<Bounds>,
+}
+
+/// Text laid out once, created by [`Context::create_text_mesh`] and drawn
+/// at many places at once by [`Context::draw_text_mesh`]. The glyphs stay in
+/// the font texture of the context, the mesh is rebuilt whenever they move.
+pub struct TextMesh<R: RendererDevice> {
+    source: TextMeshSource,
+    inner: Mutex<TextMeshInner<R>>,
+}
+
+impl<R: RendererDevice> Context<R> {
+    /// Lays out `text` at `pt` with the current font, text style and
+    /// transform, like [`Context::text`] would draw it.
+    pub fn create_text_mesh<S: AsRef<str>, P: Into<Point>>(
+        &mut self,
+        pt: P,
+        text: S,
+    ) -> crate::Result<TextMesh<R>> {
+        let state = self.states.last().unwrap();
+        let source = TextMeshSource {
+            pt: pt.into(),
+            text: text.as_ref().to_string(),
+            xform: state.xform,
+            device_pixel_ratio: self.device_pixel_ratio,
+            font_id: state.font_id,
+            font_size: state.font_size,
+            letter_spacing: state.letter_spacing,
+            text_align: state.text_align,
+            writing_mode: state.writing_mode,
+            text_decoration: state.text_decoration,
+            text_decoration_skip_ink: state.text_decoration_skip_ink,
+            text_antialias: state.text_antialias,
+        };
+        self.layout_text_mesh(source)
+    }
+
+    pub(super) fn layout_text_mesh(
+        &mut self,
+        source: TextMeshSource,
+    ) -> crate::Result<TextMesh<R>> {
+        let scale = source.xform.font_scale() * source.device_pixel_ratio;
+        let pt = (source.pt.x * scale, source.pt.y * scale).into();
+        let mut chars = Vec::new();
+        let mut run = None;
+        let lcd = match source.writing_mode {
+            WritingMode::Vertical => {
+                self.fonts.layout_text_vertical(
+                    &mut self.renderer,
+                    &source.text,
+                    source.font_id,
+                    pt,
+                    source.font_size * scale,
+                    source.text_align,
+                    source.letter_spacing * scale,
+                    false,
+                    &mut chars,
+                )?;
+                false
+            }
+            WritingMode::Horizontal => {
+                run = Some(self.fonts.layout_text(
+                    &mut self.renderer,
+                    &source.text,
+                    source.font_id,
+                    pt,
+                    source.font_size * scale,
+                    source.text_align,
+                    source.letter_spacing * scale,
+                    false,
+                    &mut chars,
+                )?);
+                source.text_antialias == TextAntialias::Lcd
+            }
+        };
+        Ok(TextMesh {
+            source,
+            inner: Mutex::new(TextMeshInner {
+                chars,
+                run,
+                allow_lcd: lcd,
+                lcd,
+                generation: None,
+                vertex_buffer: None,
+                text: VertexSlice::default(),
+                images: Vec::new(),
+                bounds: None,
+            }),
+        })
+    }
+
+    /// Draws `mesh` with the current fill paint, once for each of the
+    /// instances in range or once without instances. All glyphs of the font
+    /// texture are drawn by one call, LCD text ignores the instance colors.
+    pub fn draw_text_mesh(
+        &mut self,
+        mesh: &TextMesh<R>,
+        instances: Option<(&Instances<R>, Range<u32>)>,
+    ) -> crate::Result<()> {
+        self.record(|| DrawCommand::DrawTextMesh {
+            source: mesh.source.clone(),
+            instances: recorded_instances(&instances),
+        });
+        if self.pictures.active() {
+            return Ok(());
+        }
+        let mut inner = mesh.inner.lock().unwrap();
+        let state = self.states.last().unwrap();
+        let lcd = inner.allow_lcd && lcd_paint(&state.paint.fill, state.composite_operation);
+        if inner.lcd != lcd {
+            inner.lcd = lcd;
+            inner.generation = None;
+        }
+        if inner.generation != Some(self.fonts.generation) {
+            self.build_text_mesh(&mesh.source, &mut inner)?;
+        }
+
+        let state = self.states.last().unwrap();
+        let bounds = || instanced_bounds(inner.bounds, &instances);
+        if self.damage.cull(bounds, &state.scissor) {
+            return Ok(());
+        }
+        let instances = instances.and_then(|(insts, range)| insts.buffer(range));
+        let count = instances.as_ref().map_or(1, |(_, range)| range.len());
+
+        if inner.text.count > 0 {
+            let mut paint = state.paint.fill;
+            paint.image = Some(self.fonts.img);
+            paint.inner_color.a *= state.paint.alpha;
+            paint.outer_color.a *= state.paint.alpha;
+            if inner.lcd {
+                self.renderer.lcd_triangles(
+                    inner.vertex_buffer.as_ref().map(|b| b.buffer.clone()),
+                    instances.clone(),
+                    &paint,
+                    &state.scissor,
+                    inner.text,
+                )?;
+            } else {
+                self.renderer.triangles(
+                    inner.vertex_buffer.as_ref().map(|b| b.buffer.clone()),
+                    instances.clone(),
+                    &paint,
+                    state.composite_operation,
+                    &state.scissor,
+                    inner.text,
+                )?;
+            }
+            self.stats.text_calls += 1;
+            self.stats.text_triangles += inner.text.count / 3 * count;
+        }
+
+        let mut image_paint = PaintPattern::from(Color::rgba(1.0, 1.0, 1.0, state.paint.alpha));
+        for (image, slice) in &inner.images {
+            image_paint.image = Some(*image);
+            self.renderer.triangles(
+                inner.vertex_buffer.as_ref().map(|b| b.buffer.clone()),
+                instances.clone(),
+                &image_paint,
+                state.composite_operation,
+                &state.scissor,
+                *slice,
+            )?;
+            self.stats.text_calls += 1;
+            self.stats.text_triangles += slice.count / 3 * count;
+        }
+        Ok(())
+    }
+
+    /// Caches the glyphs of a mesh in the font texture and uploads its quads
+    fn build_text_mesh(
+        &mut self,
+        source: &TextMeshSource,
+        inner: &mut TextMeshInner<R>,
+    ) -> crate::Result<()> {
+        let mut chars = inner.chars.clone();
+        self.fonts
+            .cache_glyphs(&mut self.renderer, &mut chars, inner.lcd)?;
+        let scale = source.xform.font_scale() * source.device_pixel_ratio;
+        let decorations = match &inner.run {
+            Some(run) if !source.text_decoration.is_empty() => self.fonts.layout_decorations(
+                source.font_id,
+                source.font_size * scale,
+                source.text_decoration,
+                source.text_decoration_skip_ink,
+                run,
+                &chars,
+            ),
+            _ => Vec::new(),
+        };
+
+        let invscale = 1.0 / scale;
+        let xform = &source.xform;
+        let mut vertices: Vec<Vertex> = Vec::new();
+        let uv = self.fonts.solid_uv();
+        for bounds in &decorations {
+            push_quad(&mut vertices, xform, invscale, bounds, &uv, false);
+        }
+        for lc in chars.iter().filter(|lc| lc.image.is_none()) {
+            push_quad(
+                &mut vertices,
+                xform,
+                invscale,
+                &lc.bounds,
+                &lc.uv,
+                lc.rotated,
+            );
+        }
+        inner.text = VertexSlice {
+            offset: 0,
+            count: vertices.len(),
+        };
+
+        inner.images.clear();
+        let mut images = chars.iter().filter(|lc| lc.image.is_some()).peekable();
+        while let Some(first) = images.next() {
+            let offset = vertices.len();
+            push_quad(
+                &mut vertices,
+                xform,
+                invscale,
+                &first.bounds,
+                &first.uv,
+                false,
+            );
+            while let Some(lc) = images.next_if(|lc| lc.image == first.image) {
+                push_quad(&mut vertices, xform, invscale, &lc.bounds, &lc.uv, false);
+            }
+            let slice = VertexSlice {
+                offset,
+                count: vertices.len() - offset,
+            };
+            inner.images.push((first.image.unwrap(), slice));
+        }
+        inner.bounds = vertex_bounds(&vertices);
+        inner.generation = Some(self.fonts.generation);
+
+        if vertices.is_empty() {
+            return Ok(());
+        }
+        let vertex_data = bytemuck::cast_slice(&vertices);
+        self.upload_vertex_buffer(&mut inner.vertex_buffer, vertex_data, BufferUsage::Vertex)
+    }
+}
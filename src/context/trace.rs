@@ -0,0 +1,535 @@
+use super::core::State;
+use super::picture::{DrawCommand, ReplayScope};
+use super::{Context, FrameStats, GlyphPositioning, ImageFlags, ImageId};
+use crate::fonts::{CustomGlyph, FontId};
+use crate::{Error, Extent, PaintPattern, RendererDevice, TextureType};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+
+/// Leading bytes of a trace file
+const TRACE_MAGIC: &[u8; 8] = b"NVGXTRCE";
+/// Layout version of trace files, traces of other versions are rejected
+const TRACE_VERSION: u32 = 4;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) enum TraceCommand {
+    BeginFrame(Extent, f32),
+    GlyphPositioning(GlyphPositioning),
+    Draw(DrawCommand),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TraceFont {
+    id: FontId,
+    name: String,
+    data: Vec<u8>,
+    fallbacks: Vec<FontId>,
+}
+
+/// Contents of an image as last uploaded, `None` when unknown
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct TraceImage {
+    texture_type: TextureType,
+    width: u32,
+    height: u32,
+    flags: ImageFlags,
+    data: Option<Vec<u8>>,
+}
+
+impl TraceImage {
+    fn bytes_per_pixel(&self) -> usize {
+        match self.texture_type {
+            TextureType::RGBA | TextureType::BGRA => 4,
+            TextureType::Alpha => 1,
+        }
+    }
+
+    /// Opaque magenta, drawn in place of images captured without their data
+    fn placeholder(&self) -> Vec<u8> {
+        let pixel: &[u8] = match self.texture_type {
+            TextureType::RGBA | TextureType::BGRA => &[255, 0, 255, 255],
+            TextureType::Alpha => &[255],
+        };
+        pixel.repeat((self.width * self.height) as usize)
+    }
+}
+
+struct Capture {
+    path: PathBuf,
+    glyph_positioning: GlyphPositioning,
+    commands: Vec<TraceCommand>,
+}
+
+#[derive(Default)]
+pub(super) struct TraceRecorder {
+    /// Copies of the image data, kept once capturing is enabled
+    images: Option<HashMap<ImageId, TraceImage>>,
+    capture: Option<Capture>,
+    /// Recording is suspended while replaying pictures and drawing into
+    /// framebuffers
+    pub(super) suspended: usize,
+}
+
+impl TraceRecorder {
+    pub(super) fn image_created(
+        &mut self,
+        img: ImageId,
+        texture_type: TextureType,
+        width: u32,
+        height: u32,
+        flags: ImageFlags,
+        data: Option<&[u8]>,
+    ) {
+        if let Some(images) = &mut self.images {
+            let image = TraceImage {
+                texture_type,
+                width,
+                height,
+                flags,
+                data: data.map(|data| data.to_vec()),
+            };
+            images.insert(img, image);
+        }
+    }
+
+    pub(super) fn image_updated(&mut self, img: ImageId, area: (u32, u32, u32, u32), data: &[u8]) {
+        let image = match self.images.as_mut().and_then(|images| images.get_mut(&img)) {
+            Some(image) => image,
+            None => return,
+        };
+        let (x, y, w, h) = area;
+        let bpp = image.bytes_per_pixel();
+        let (width, height) = (image.width as usize, image.height as usize);
+        let (x, y, w, h) = (x as usize, y as usize, w as usize, h as usize);
+        if x + w > width || y + h > height || data.len() < w * h * bpp {
+            return;
+        }
+        let pixels = image
+            .data
+            .get_or_insert_with(|| vec![0; width * height * bpp]);
+        for row in 0..h {
+            let dst = ((y + row) * width + x) * bpp;
+            let src = row * w * bpp;
+            pixels[dst..dst + w * bpp].copy_from_slice(&data[src..src + w * bpp]);
+        }
+    }
+
+    pub(super) fn image_deleted(&mut self, img: ImageId) {
+        if let Some(images) = &mut self.images {
+            images.remove(&img);
+        }
+    }
+}
+
+fn remap_paint<I: FnMut(ImageId) -> ImageId>(paint: &mut PaintPattern, image: &mut I) {
+    if let Some(img) = &mut paint.image {
+        *img = image(*img);
+    }
+}
+
+impl DrawCommand {
+    /// Rewrites the ids of the images and fonts the command uses
+    fn remap<I, F>(&mut self, image: &mut I, font: &mut F)
+    where
+        I: FnMut(ImageId) -> ImageId,
+        F: FnMut(FontId) -> FontId,
+    {
+        match self {
+            DrawCommand::StrokePaint(paint) | DrawCommand::FillPaint(paint) => {
+                remap_paint(paint, image)
+            }
+            DrawCommand::FontId(id) => *id = font(*id),
+            DrawCommand::RichText(_, _, spans) => {
+                for span in spans {
+                    span.font_id = font(span.font_id);
+                    remap_paint(&mut span.paint, image);
+                }
+            }
+            DrawCommand::Picture(picture, _, _) => {
+                for cmd in &mut picture.commands {
+                    cmd.remap(image, font);
+                }
+            }
+            DrawCommand::DrawPath { paint, .. } => {
+                remap_paint(&mut paint.fill, image);
+                remap_paint(&mut paint.stroke, image);
+            }
+            DrawCommand::DrawTextMesh { source, .. } => source.font_id = font(source.font_id),
+            _ => {}
+        }
+    }
+}
+
+impl TraceCommand {
+    fn remap<I, F>(&mut self, image: &mut I, font: &mut F)
+    where
+        I: FnMut(ImageId) -> ImageId,
+        F: FnMut(FontId) -> FontId,
+    {
+        if let TraceCommand::Draw(cmd) = self {
+            cmd.remap(image, font);
+        }
+    }
+}
+
+/// The drawing calls of one frame along with the fonts and images they use,
+/// written by [`Context::capture_frame`] to reproduce rendering elsewhere.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trace {
+    glyph_positioning: GlyphPositioning,
+    fonts: Vec<TraceFont>,
+    custom_glyphs: Vec<(char, CustomGlyph)>,
+    images: Vec<(ImageId, TraceImage)>,
+    commands: Vec<TraceCommand>,
+}
+
+impl Trace {
+    pub fn read<T: Read>(mut reader: T) -> crate::Result<Trace> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != TRACE_MAGIC {
+            return Err(Error::InvalidTrace("not a trace file".into()));
+        }
+        let version: u32 = bincode::deserialize_from(&mut reader)?;
+        if version != TRACE_VERSION {
+            return Err(Error::InvalidTrace(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+        Ok(bincode::deserialize_from(reader)?)
+    }
+
+    pub fn write<T: Write>(&self, mut writer: T) -> crate::Result<()> {
+        writer.write_all(TRACE_MAGIC)?;
+        bincode::serialize_into(&mut writer, &TRACE_VERSION)?;
+        bincode::serialize_into(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> crate::Result<Trace> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> crate::Result<()> {
+        self.write(BufWriter::new(File::create(path)?))
+    }
+
+    /// Window extent and device pixel ratio the frame was begun with
+    pub fn viewport(&self) -> Option<(Extent, f32)> {
+        self.commands.iter().find_map(|cmd| match cmd {
+            TraceCommand::BeginFrame(extent, ratio) => Some((*extent, *ratio)),
+            _ => None,
+        })
+    }
+
+    /// Creates the fonts and images of the trace in `ctx` and draws the frame,
+    /// ending with [`Context::end_frame`]. Images captured without their data
+    /// are drawn as opaque magenta.
+    pub fn replay<R: RendererDevice>(&self, ctx: &mut Context<R>) -> crate::Result<FrameStats> {
+        let mut fonts = HashMap::new();
+        for font in &self.fonts {
+            fonts.insert(
+                font.id,
+                ctx.create_font(font.name.clone(), font.data.clone())?,
+            );
+        }
+        for font in &self.fonts {
+            for fallback in &font.fallbacks {
+                if let Some(fallback) = fonts.get(fallback) {
+                    ctx.add_fallback_fontid(fonts[&font.id], *fallback);
+                }
+            }
+        }
+
+        let mut images = HashMap::new();
+        for (id, image) in &self.images {
+            let placeholder;
+            let data = match &image.data {
+                Some(data) => data,
+                None => {
+                    placeholder = image.placeholder();
+                    &placeholder
+                }
+            };
+            let img = ctx.create_image(
+                image.width,
+                image.height,
+                image.texture_type,
+                image.flags,
+                Some(data),
+            )?;
+            images.insert(*id, img);
+        }
+
+        let mut image = |id: ImageId| images.get(&id).copied().unwrap_or(id);
+        let mut font = |id: FontId| fonts.get(&id).copied().unwrap_or(id);
+        for (c, glyph) in &self.custom_glyphs {
+            let glyph = CustomGlyph {
+                image: image(glyph.image),
+                ..*glyph
+            };
+            ctx.add_custom_glyph(*c, glyph)?;
+        }
+        ctx.text_glyph_positioning(self.glyph_positioning);
+
+        let scope = ReplayScope {
+            depth: 0,
+            outer: State::default(),
+            base_alpha: 1.0,
+        };
+        for cmd in &self.commands {
+            let mut cmd = cmd.clone();
+            cmd.remap(&mut image, &mut font);
+            match cmd {
+                TraceCommand::BeginFrame(extent, ratio) => ctx.begin_frame(extent, ratio)?,
+                TraceCommand::GlyphPositioning(positioning) => {
+                    ctx.text_glyph_positioning(positioning)
+                }
+                TraceCommand::Draw(cmd) => ctx.replay_command(&cmd, &scope)?,
+            }
+        }
+        ctx.end_frame()
+    }
+}
+
+impl<R: RendererDevice> Context<R> {
+    /// Keeps a copy of the image data uploaded from now on, so that captured
+    /// frames include it. Images created before are captured as placeholders
+    /// of the same size.
+    pub fn enable_capture(&mut self) {
+        if self.trace.images.is_none() {
+            self.trace.images = Some(HashMap::new());
+        }
+    }
+
+    /// Records the calls from now until the end of the frame, and writes them
+    /// along with the fonts and images they use to a [`Trace`] file at `path`
+    /// in [`Context::end_frame`]. Call it before [`Context::begin_frame`].
+    /// Drawing into framebuffers is not recorded.
+    pub fn capture_frame<P: Into<PathBuf>>(&mut self, path: P) {
+        self.trace.capture = Some(Capture {
+            path: path.into(),
+            glyph_positioning: self.fonts.glyph_positioning(),
+            commands: Vec::new(),
+        });
+    }
+
+    pub(super) fn record_trace<F: FnOnce() -> TraceCommand>(&mut self, cmd: F) {
+        if self.trace.suspended == 0 {
+            if let Some(capture) = &mut self.trace.capture {
+                capture.commands.push(cmd());
+            }
+        }
+    }
+
+    /// Drops the calls of a canceled frame, the capture goes on with the next
+    pub(super) fn cancel_capture(&mut self) {
+        if let Some(capture) = &mut self.trace.capture {
+            capture.commands.clear();
+        }
+    }
+
+    /// Writes the trace of the captured frame
+    pub(super) fn finish_capture(&mut self) -> crate::Result<()> {
+        let Capture {
+            path,
+            glyph_positioning,
+            mut commands,
+        } = match self.trace.capture.take() {
+            Some(capture) => capture,
+            None => return Ok(()),
+        };
+
+        let custom_glyphs: Vec<(char, CustomGlyph)> = self.fonts.custom_glyphs().collect();
+        let mut used = HashSet::new();
+        for cmd in &mut commands {
+            cmd.remap(
+                &mut |img| {
+                    used.insert(img);
+                    img
+                },
+                &mut |font| font,
+            );
+        }
+        used.extend(custom_glyphs.iter().map(|(_, glyph)| glyph.image));
+
+        let mut images = Vec::new();
+        for img in used {
+            let known = self
+                .trace
+                .images
+                .as_ref()
+                .and_then(|images| images.get(&img))
+                .cloned();
+            let image = match known {
+                Some(image) => image,
+                None => match self.renderer.texture_size(img) {
+                    Ok((width, height)) => TraceImage {
+                        texture_type: TextureType::RGBA,
+                        width,
+                        height,
+                        flags: ImageFlags::empty(),
+                        data: None,
+                    },
+                    Err(_) => continue,
+                },
+            };
+            images.push((img, image));
+        }
+        images.sort_by_key(|(img, _)| *img);
+
+        let fonts = self
+            .fonts
+            .sources()
+            .into_iter()
+            .map(|(id, name, data, fallbacks)| TraceFont {
+                id,
+                name,
+                data,
+                fallbacks,
+            })
+            .collect();
+
+        let trace = Trace {
+            glyph_positioning,
+            fonts,
+            custom_glyphs,
+            images,
+            commands,
+        };
+        trace.save(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::picture::Picture;
+    use crate::{Color, ImagePattern, Transform};
+
+    fn image_paint(img: ImageId) -> PaintPattern {
+        ImagePattern {
+            img,
+            ..Default::default()
+        }
+        .into()
+    }
+
+    fn trace() -> Trace {
+        let picture = Picture {
+            commands: vec![DrawCommand::FillPaint(image_paint(2)), DrawCommand::Fill],
+        };
+        Trace {
+            glyph_positioning: GlyphPositioning::Subpixel { steps: 3 },
+            fonts: vec![TraceFont {
+                id: 0,
+                name: "sans".into(),
+                data: vec![1, 2, 3],
+                fallbacks: Vec::new(),
+            }],
+            custom_glyphs: Vec::new(),
+            images: vec![(
+                1,
+                TraceImage {
+                    texture_type: TextureType::Alpha,
+                    width: 2,
+                    height: 1,
+                    flags: ImageFlags::NEAREST,
+                    data: Some(vec![0, 255]),
+                },
+            )],
+            commands: vec![
+                TraceCommand::BeginFrame(Extent::new(320.0, 240.0), 2.0),
+                TraceCommand::Draw(DrawCommand::Clear(Color::rgb(0.1, 0.2, 0.3))),
+                TraceCommand::Draw(DrawCommand::FillPaint(image_paint(1))),
+                TraceCommand::Draw(DrawCommand::Picture(
+                    picture,
+                    Transform::translate(10.0, 20.0),
+                    0.5,
+                )),
+            ],
+        }
+    }
+
+    fn write(trace: &Trace) -> Vec<u8> {
+        let mut data = Vec::new();
+        trace.write(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn round_trip() {
+        let trace = trace();
+        let read = Trace::read(&write(&trace)[..]).unwrap();
+        assert_eq!(format!("{:?}", read), format!("{:?}", trace));
+        let (extent, ratio) = read.viewport().unwrap();
+        assert_eq!((extent.width, extent.height, ratio), (320.0, 240.0, 2.0));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let mut data = write(&trace());
+
+        let mut magic = data.clone();
+        magic[0] = b'X';
+        assert!(matches!(
+            Trace::read(&magic[..]),
+            Err(Error::InvalidTrace(_))
+        ));
+
+        let version = TRACE_MAGIC.len();
+        data[version] = data[version].wrapping_add(1);
+        assert!(matches!(
+            Trace::read(&data[..]),
+            Err(Error::InvalidTrace(_))
+        ));
+
+        let truncated = write(&trace());
+        assert!(Trace::read(&truncated[..truncated.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn remap_nested_pictures() {
+        let mut trace = trace();
+        let mut used = Vec::new();
+        for cmd in &mut trace.commands {
+            cmd.remap(
+                &mut |img| {
+                    used.push(img);
+                    img + 10
+                },
+                &mut |font| font,
+            );
+        }
+        assert_eq!(used, vec![1, 2]);
+        match &trace.commands[3] {
+            TraceCommand::Draw(DrawCommand::Picture(picture, _, _)) => match &picture.commands[0] {
+                DrawCommand::FillPaint(paint) => assert_eq!(paint.image, Some(12)),
+                cmd => panic!("unexpected {:?}", cmd),
+            },
+            cmd => panic!("unexpected {:?}", cmd),
+        }
+    }
+
+    #[test]
+    fn image_updates() {
+        let mut recorder = TraceRecorder {
+            images: Some(HashMap::new()),
+            ..Default::default()
+        };
+        recorder.image_created(1, TextureType::Alpha, 3, 2, ImageFlags::empty(), None);
+        recorder.image_updated(1, (1, 1, 2, 1), &[7, 8]);
+        // outside of the image, ignored
+        recorder.image_updated(1, (2, 0, 2, 1), &[9, 9]);
+        let image = &recorder.images.as_ref().unwrap()[&1];
+        assert_eq!(image.data, Some(vec![0, 0, 0, 0, 7, 8]));
+
+        recorder.image_deleted(1);
+        assert!(recorder.images.as_ref().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+use crate::ImageId;
+use std::fmt;
+
+/// Errors of contexts and renderers
+#[derive(Debug)]
+pub enum Error {
+    /// No image with this id in the context, or it was deleted
+    InvalidImage(ImageId),
+    /// Larger than the device can create textures
+    TextureTooLarge {
+        width: u32,
+        height: u32,
+        max: u32,
+    },
+    /// Font data which could not be parsed, with the name it was added as
+    FontParse(String),
+    /// Glyphs which do not fit into the font texture at once
+    AtlasFull,
+    /// The surface has to be configured again before the next frame
+    SurfaceLost,
+    /// Trace file which could not be read
+    InvalidTrace(String),
+    /// Serialized path or paint written by a newer version of the format
+    UnsupportedVersion(u32),
+    /// Write of `len` bytes at `offset` past the end of a vertex buffer of
+    /// `size` bytes
+    BufferRange {
+        offset: usize,
+        len: usize,
+        size: usize,
+    },
+    ImageDecode(image::ImageError),
+    Io(std::io::Error),
+    /// Other failures of the renderer, e.g. shaders which do not compile
+    Backend(Box<dyn std::error::Error + Send + Sync>),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub fn backend<E: Into<Box<dyn std::error::Error + Send + Sync>>>(err: E) -> Self {
+        Error::Backend(err.into())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidImage(img) => write!(f, "image '{}' not found", img),
+            Error::TextureTooLarge { width, height, max } => write!(
+                f,
+                "texture of {}x{} exceeds the maximum size {}",
+                width, height, max
+            ),
+            Error::FontParse(name) => write!(f, "open font `{}` failed", name),
+            Error::AtlasFull => write!(f, "glyphs do not fit into the font texture"),
+            Error::SurfaceLost => write!(f, "surface lost"),
+            Error::InvalidTrace(msg) => write!(f, "invalid trace: {}", msg),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {}", version)
+            }
+            Error::BufferRange { offset, len, size } => write!(
+                f,
+                "write of {} bytes at offset {} exceeds the vertex buffer of {} bytes",
+                len, offset, size
+            ),
+            Error::ImageDecode(err) => write!(f, "decode image failed: {}", err),
+            Error::Io(err) => err.fmt(f),
+            Error::Backend(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::ImageDecode(err) => Some(err),
+            Error::Io(err) => Some(err),
+            Error::Backend(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(err: image::ImageError) -> Self {
+        Error::ImageDecode(err)
+    }
+}
+
+#[cfg(feature = "trace")]
+impl From<bincode::Error> for Error {
+    fn from(err: bincode::Error) -> Self {
+        match *err {
+            bincode::ErrorKind::Io(err) => Error::Io(err),
+            err => Error::InvalidTrace(err.to_string()),
+        }
+    }
+}
@@ -1,29 +1,216 @@
-use crate::context::{ImageId, TextMetrics};
+use crate::context::{
+    FontMetrics, GlyphMetrics, GlyphPosition, GlyphPositioning, ImageId, TextDecoration,
+    TextEllipsis, TextMetrics,
+};
 use crate::renderer::TextureType;
-use crate::{Align, Bounds, Extent, ImageFlags, RendererDevice};
-use anyhow::anyhow;
+use crate::{Align, Bounds, Color, Error, Extent, ImageFlags, PaintPattern, Rect, RendererDevice};
 use bitflags::_core::borrow::Borrow;
-use rusttype::gpu_cache::Cache;
-use rusttype::{Font, Glyph, Point, PositionedGlyph, Scale};
+use owned_ttf_parser::{AsFaceRef, Face};
+use rusttype::gpu_cache::{Cache, CachedBy};
+use rusttype::{Font, Glyph, GlyphId, Point, PositionedGlyph, Scale, ScaledGlyph};
 use slab::Slab;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
 
 const TEX_WIDTH: u32 = 1024;
 const TEX_HEIGHT: u32 = 1024;
+/// Rows at the bottom of the font texture kept opaque, so that solid quads
+/// (e.g. text decorations) can be drawn in the same batch as the glyphs.
+const SOLID_ROWS: u32 = 2;
+/// Minimum glyph coverage treated as ink when skipping decorations.
+const INK_THRESHOLD: f32 = 0.25;
+/// Horizontal oversampling of glyphs rasterized for LCD text, one texel per
+/// subpixel.
+const LCD_SUBPIXELS: f32 = 3.0;
 
 pub type FontId = usize;
 
 #[allow(unused)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LayoutChar {
     id: FontId,
     pub x: f32,
     pub next_x: f32,
     pub c: char,
     pub idx: usize,
-    glyph: PositionedGlyph<'static>,
+    glyph: Option<PositionedGlyph<'static>>,
+    /// The image of a custom glyph, drawn at `uv` instead of the font
+    /// texture.
+    pub image: Option<ImageId>,
     pub uv: Bounds,
     pub bounds: Bounds,
+    /// The glyph is drawn rotated 90° clockwise, like Latin text in vertical
+    /// layout.
+    pub rotated: bool,
+}
+
+/// An image drawn in place of a character in horizontal text, e.g. an icon
+/// or emote. Sizes are in ems, i.e. multiples of the font size.
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CustomGlyph {
+    pub image: ImageId,
+    /// The part of the image to draw, in pixels. `None` draws the whole
+    /// image.
+    pub source: Option<Rect>,
+    pub width: f32,
+    pub height: f32,
+    pub advance: f32,
+    /// Distance of the bottom edge of the image below the baseline.
+    pub baseline_offset: f32,
+}
+
+impl CustomGlyph {
+    pub fn new(image: ImageId, width: f32, height: f32) -> Self {
+        CustomGlyph {
+            image,
+            source: None,
+            width,
+            height,
+            advance: width,
+            baseline_offset: 0.0,
+        }
+    }
+
+    /// Ascent above and descent below the baseline, in pixels.
+    fn extent(&self, size: f32) -> (f32, f32) {
+        (
+            (self.height - self.baseline_offset) * size,
+            -self.baseline_offset * size,
+        )
+    }
+
+    fn bounds(&self, x: f32, baseline: f32, size: f32) -> Bounds {
+        let (ascent, descent) = self.extent(size);
+        Bounds {
+            min: crate::Point::new(x, baseline - ascent),
+            max: crate::Point::new(x + self.width * size, baseline - descent),
+        }
+    }
+}
+
+/// Horizontal extent and baseline of a laid out line of text.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LayoutRun {
+    pub x: f32,
+    pub next_x: f32,
+    pub baseline: f32,
+}
+
+/// A piece of attributed text with its own font, size, paint and decoration.
+#[derive(Debug, Clone)]
+pub struct TextSpan<'a> {
+    pub text: &'a str,
+    pub font_id: FontId,
+    pub size: f32,
+    pub paint: PaintPattern,
+    pub letter_spacing: f32,
+    pub decoration: TextDecoration,
+}
+
+impl<'a> TextSpan<'a> {
+    pub fn new<P: Into<PaintPattern>>(text: &'a str, font_id: FontId, size: f32, paint: P) -> Self {
+        TextSpan {
+            text,
+            font_id,
+            size,
+            paint: paint.into(),
+            letter_spacing: 0.0,
+            decoration: TextDecoration::empty(),
+        }
+    }
+}
+
+/// The glyphs of one span laid out on one line.
+#[derive(Debug, Clone)]
+pub(crate) struct SpanRun {
+    pub span: usize,
+    pub run: LayoutRun,
+    pub chars: Range<usize>,
+}
+
+struct ShapedChar {
+    span: usize,
+    idx: usize,
+    c: char,
+    glyph: Option<(FontId, ScaledGlyph<'static>)>,
+    custom: Option<CustomGlyph>,
+    kerning: f32,
+    advance: f32,
+    spacing: f32,
+}
+
+struct VerticalChar {
+    id: FontId,
+    idx: usize,
+    c: char,
+    glyph: ScaledGlyph<'static>,
+    upright: bool,
+    advance: f32,
+}
+
+struct SpanLine {
+    chars: Range<usize>,
+    width: f32,
+    ascent: f32,
+    descent: f32,
+    line_gap: f32,
+}
+
+/// Returns true for characters that can be broken before and after without
+/// whitespace, like CJK ideographs and kana.
+pub(crate) fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x1100..=0x11FF
+        | 0x2E80..=0x2FDF
+        | 0x3000..=0x30FF
+        | 0x3130..=0x318F
+        | 0x31F0..=0x31FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xAC00..=0xD7AF
+        | 0xF900..=0xFAFF
+        | 0xFF00..=0xFFEF
+        | 0x20000..=0x2FA1F)
+}
+
+/// Returns true for characters set upright in vertical text.
+fn is_upright(c: char) -> bool {
+    is_cjk(c) && !matches!(c, '\u{30FC}' | '\u{FF5E}' | '\u{301C}')
+}
+
+/// Maps punctuation to its vertical presentation form.
+fn vertical_form(c: char) -> char {
+    match c {
+        '\u{FF0C}' => '\u{FE10}',
+        '\u{3001}' => '\u{FE11}',
+        '\u{3002}' => '\u{FE12}',
+        '\u{FF1A}' => '\u{FE13}',
+        '\u{FF1B}' => '\u{FE14}',
+        '\u{FF01}' => '\u{FE15}',
+        '\u{FF1F}' => '\u{FE16}',
+        '\u{3016}' => '\u{FE17}',
+        '\u{3017}' => '\u{FE18}',
+        '\u{2026}' => '\u{FE19}',
+        '\u{2014}' => '\u{FE31}',
+        '\u{FF08}' => '\u{FE35}',
+        '\u{FF09}' => '\u{FE36}',
+        '\u{FF5B}' => '\u{FE37}',
+        '\u{FF5D}' => '\u{FE38}',
+        '\u{3014}' => '\u{FE39}',
+        '\u{3015}' => '\u{FE3A}',
+        '\u{3010}' => '\u{FE3B}',
+        '\u{3011}' => '\u{FE3C}',
+        '\u{300A}' => '\u{FE3D}',
+        '\u{300B}' => '\u{FE3E}',
+        '\u{3008}' => '\u{FE3F}',
+        '\u{3009}' => '\u{FE40}',
+        '\u{300C}' => '\u{FE41}',
+        '\u{300D}' => '\u{FE42}',
+        '\u{300E}' => '\u{FE43}',
+        '\u{300F}' => '\u{FE44}',
+        _ => c,
+    }
 }
 
 struct FontData {
@@ -31,40 +218,214 @@ struct FontData {
     fallback_fonts: Vec<FontId>,
 }
 
+impl FontData {
+    fn face(&self) -> &Face<'_> {
+        match &self.font {
+            Font::Ref(face) => face,
+            Font::Owned(face) => face.as_face_ref(),
+        }
+    }
+
+    /// Vertical advance of an upright glyph, one em for fonts without
+    /// vertical metrics
+    fn ver_advance(&self, glyph: GlyphId, size: f32) -> f32 {
+        let face = self.face();
+        let advance = face
+            .glyph_ver_advance(owned_ttf_parser::GlyphId(glyph.0))
+            .unwrap_or_else(|| face.units_per_em());
+        advance as f32 * self.font.scale_for_pixel_height(size)
+    }
+
+    #[cfg(feature = "trace")]
+    fn data(&self) -> &[u8] {
+        match &self.font {
+            Font::Ref(_) => &[],
+            Font::Owned(face) => face.as_slice(),
+        }
+    }
+}
+
+/// Ink of a glyph rasterized at some scale and subpixel offset, kept while
+/// the glyph cache keeps the glyphs
+struct GlyphInk {
+    /// Width of the glyph in pixels
+    columns: usize,
+    /// Runs of rows with ink as `(column, top, bottom)`, relative to the
+    /// pixel bounds of the glyph
+    runs: Vec<(usize, u32, u32)>,
+}
+
+impl GlyphInk {
+    fn new(glyph: &PositionedGlyph) -> GlyphInk {
+        let (width, height) = match glyph.pixel_bounding_box() {
+            Some(bb) => (bb.width() as usize, bb.height() as usize),
+            None => (0, 0),
+        };
+        // LCD glyphs are rasterized at three columns per pixel
+        let scale = glyph.scale();
+        let h_scale = (scale.x / scale.y).round().max(1.0) as usize;
+        let columns = width.div_ceil(h_scale);
+        let mut ink = vec![false; columns * height];
+        glyph.draw(|x, y, v| {
+            if v > INK_THRESHOLD {
+                ink[y as usize * columns + x as usize / h_scale] = true;
+            }
+        });
+
+        let mut runs = Vec::new();
+        for column in 0..columns {
+            let mut top = None;
+            for row in 0..=height {
+                match (top, row < height && ink[row * columns + column]) {
+                    (None, true) => top = Some(row as u32),
+                    (Some(t), false) => {
+                        runs.push((column, t, row as u32));
+                        top = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        GlyphInk { columns, runs }
+    }
+}
+
+/// A glyph rasterized at some scale, by font, glyph id, scale and subpixel
+/// offset
+type GlyphKey = (FontId, u16, [u32; 4]);
+
+fn glyph_key(id: FontId, glyph: &PositionedGlyph) -> GlyphKey {
+    let scale = glyph.scale();
+    let position = glyph.position();
+    let offset = |x: f32| (x - x.floor()).to_bits();
+    (
+        id,
+        glyph.id().0,
+        [
+            scale.x.to_bits(),
+            scale.y.to_bits(),
+            offset(position.x),
+            offset(position.y),
+        ],
+    )
+}
+
 pub struct Fonts {
     fonts: Slab<FontData>,
     fonts_by_name: HashMap<String, FontId>,
     cache: Cache<'static>,
+    positioning: GlyphPositioning,
+    custom_glyphs: HashMap<char, (CustomGlyph, Bounds)>,
     pub(crate) img: ImageId,
+    /// Changes whenever glyphs cached before may have moved or been
+    /// overwritten in the font texture
+    pub(crate) generation: u64,
+    /// Glyphs uploaded since the context reset the count
+    pub(crate) uploads: usize,
+    /// Height and width of the glyph rows of the font texture by their top
+    atlas_rows: BTreeMap<u32, (u32, u32)>,
+    /// Ink of the glyphs of decorated text, dropped with the glyphs cached
+    /// before whenever the generation changes
+    ink: HashMap<GlyphKey, GlyphInk>,
+}
+
+/// Records the glyph uploaded to `rect`. The glyph cache starts each row
+/// with its tallest glyph at the left edge, in the space of evicted rows.
+/// Returns whether rows holding glyphs were overwritten.
+fn track_atlas_row(rows: &mut BTreeMap<u32, (u32, u32)>, rect: &rusttype::Rect<u32>) -> bool {
+    let (top, bottom) = (rect.min.y, rect.max.y);
+    if rect.min.x == 0 {
+        let evicted: Vec<u32> = rows
+            .range(..bottom)
+            .filter(|(row_top, (height, _))| **row_top + *height > top)
+            .map(|(row_top, _)| *row_top)
+            .collect();
+        for row_top in &evicted {
+            rows.remove(row_top);
+        }
+        rows.insert(top, (bottom - top, rect.max.x));
+        !evicted.is_empty()
+    } else {
+        if let Some((_, width)) = rows.get_mut(&top) {
+            *width = (*width).max(rect.max.x);
+        }
+        false
+    }
+}
+
+/// Cache tolerance which keeps each position of `positioning` a separate
+/// glyph variant.
+fn position_tolerance(positioning: GlyphPositioning) -> f32 {
+    match positioning {
+        GlyphPositioning::Pixel => 1.0,
+        GlyphPositioning::Subpixel { steps } => 1.0 / steps.max(1) as f32,
+    }
 }
 
 impl Fonts {
-    pub fn new<R: RendererDevice>(renderer: &mut R) -> anyhow::Result<Fonts> {
+    pub fn new<R: RendererDevice>(renderer: &mut R) -> crate::Result<Fonts> {
+        let img = renderer.create_texture(
+            TextureType::Alpha,
+            TEX_WIDTH,
+            TEX_HEIGHT,
+            ImageFlags::empty(),
+            None,
+        )?;
+        renderer.update_texture(
+            img,
+            0,
+            TEX_HEIGHT - SOLID_ROWS,
+            TEX_WIDTH,
+            SOLID_ROWS,
+            &vec![255; (TEX_WIDTH * SOLID_ROWS) as usize],
+        )?;
+        let positioning = GlyphPositioning::default();
         Ok(Fonts {
             fonts: Default::default(),
             fonts_by_name: Default::default(),
-            img: renderer.create_texture(
-                TextureType::Alpha,
-                TEX_WIDTH,
-                TEX_HEIGHT,
-                ImageFlags::empty(),
-                None,
-            )?,
+            img,
             cache: Cache::builder()
                 .multithread(true)
-                .dimensions(TEX_WIDTH as u32, TEX_HEIGHT as u32)
+                .dimensions(TEX_WIDTH, TEX_HEIGHT - SOLID_ROWS)
+                .position_tolerance(position_tolerance(positioning))
                 .build(),
+            positioning,
+            custom_glyphs: Default::default(),
+            generation: 0,
+            uploads: 0,
+            atlas_rows: Default::default(),
+            ink: Default::default(),
         })
     }
 
+    pub fn glyph_positioning(&self) -> GlyphPositioning {
+        self.positioning
+    }
+
+    /// Sets where glyphs are placed on the pixel grid. Finer positioning
+    /// spaces small text more evenly at the cost of more cached glyphs.
+    /// Changing it empties the glyph cache.
+    pub fn set_glyph_positioning(&mut self, positioning: GlyphPositioning) {
+        if positioning != self.positioning {
+            self.positioning = positioning;
+            self.cache
+                .to_builder()
+                .position_tolerance(position_tolerance(positioning))
+                .rebuild(&mut self.cache);
+            self.atlas_rows.clear();
+            self.ink.clear();
+            self.generation += 1;
+        }
+    }
+
     pub fn add_font<N: Into<String>, D: Into<Vec<u8>>>(
         &mut self,
         name: N,
         data: D,
-    ) -> anyhow::Result<FontId> {
+    ) -> crate::Result<FontId> {
         let name: String = name.into();
         let font = Font::<'static>::try_from_vec(data.into())
-            .ok_or_else(|| anyhow!("Open font `{}` failed", name))?;
+            .ok_or_else(|| Error::FontParse(name.clone()))?;
         let fd = FontData {
             font,
             fallback_fonts: Default::default(),
@@ -74,6 +435,30 @@ impl Fonts {
         Ok(id)
     }
 
+    /// Name, file data and fallbacks of every font
+    #[cfg(feature = "trace")]
+    pub(crate) fn sources(&self) -> Vec<(FontId, String, Vec<u8>, Vec<FontId>)> {
+        self.fonts
+            .iter()
+            .map(|(id, fd)| {
+                let name = self
+                    .fonts_by_name
+                    .iter()
+                    .find(|(_, font)| **font == id)
+                    .map(|(name, _)| name.clone())
+                    .unwrap_or_default();
+                (id, name, fd.data().to_vec(), fd.fallback_fonts.clone())
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "trace")]
+    pub(crate) fn custom_glyphs(&self) -> impl Iterator<Item = (char, CustomGlyph)> + '_ {
+        self.custom_glyphs
+            .iter()
+            .map(|(c, (glyph, _))| (*c, *glyph))
+    }
+
     pub fn find<N: Borrow<str>>(&self, name: N) -> Option<FontId> {
         self.fonts_by_name.get(name.borrow()).map(ToOwned::to_owned)
     }
@@ -84,6 +469,61 @@ impl Fonts {
         }
     }
 
+    /// Registers an image drawn in place of `c` in all fonts.
+    pub fn add_custom_glyph<R: RendererDevice>(
+        &mut self,
+        renderer: &R,
+        c: char,
+        glyph: CustomGlyph,
+    ) -> crate::Result<()> {
+        let (width, height) = renderer.texture_size(glyph.image)?;
+        let source = glyph.source.unwrap_or(Rect::new(
+            crate::Point::new(0.0, 0.0),
+            Extent::new(width as f32, height as f32),
+        ));
+        let uv = Bounds {
+            min: crate::Point::new(source.xy.x / width as f32, source.xy.y / height as f32),
+            max: crate::Point::new(
+                (source.xy.x + source.size.width) / width as f32,
+                (source.xy.y + source.size.height) / height as f32,
+            ),
+        };
+        self.custom_glyphs.insert(c, (glyph, uv));
+        Ok(())
+    }
+
+    pub fn remove_custom_glyph(&mut self, c: char) -> Option<CustomGlyph> {
+        self.custom_glyphs.remove(&c).map(|(glyph, _)| glyph)
+    }
+
+    fn custom_glyph(&self, c: char) -> Option<CustomGlyph> {
+        self.custom_glyphs.get(&c).map(|(glyph, _)| *glyph)
+    }
+
+    /// Lays out a custom glyph with its origin on the baseline.
+    fn layout_custom(
+        &self,
+        glyph: &CustomGlyph,
+        id: FontId,
+        idx: usize,
+        c: char,
+        origin: crate::Point,
+        size: f32,
+    ) -> LayoutChar {
+        LayoutChar {
+            id,
+            idx,
+            c,
+            x: origin.x,
+            next_x: origin.x + glyph.advance * size,
+            glyph: None,
+            image: Some(glyph.image),
+            uv: self.custom_glyphs[&c].1,
+            bounds: glyph.bounds(origin.x, origin.y, size),
+            rotated: false,
+        }
+    }
+
     fn glyph(&self, id: FontId, c: char) -> Option<(FontId, Glyph<'static>)> {
         if let Some(fd) = self.fonts.get(id) {
             let glyph = fd.font.glyph(c);
@@ -105,21 +545,46 @@ impl Fonts {
         }
     }
 
-    fn render_texture<R: RendererDevice>(&mut self, renderer: &mut R) -> anyhow::Result<()> {
+    fn render_texture<R: RendererDevice>(&mut self, renderer: &mut R) -> crate::Result<()> {
         let img = self.img.clone();
-        self.cache.cache_queued(move |rect, data| {
-            renderer
-                .update_texture(
-                    img.clone(),
-                    rect.min.x,
-                    rect.min.y,
-                    rect.max.x - rect.min.x,
-                    rect.max.y - rect.min.y,
-                    data,
-                )
-                .unwrap();
-        })?;
-        Ok(())
+        let uploads = &mut self.uploads;
+        let atlas_rows = &mut self.atlas_rows;
+        let mut evicted = false;
+        let mut result = Ok(());
+        let cached_by = self
+            .cache
+            .cache_queued(|rect, data| {
+                *uploads += 1;
+                evicted |= track_atlas_row(atlas_rows, &rect);
+                if result.is_ok() {
+                    result = renderer.update_texture(
+                        img.clone(),
+                        rect.min.x,
+                        rect.min.y,
+                        rect.max.x - rect.min.x,
+                        rect.max.y - rect.min.y,
+                        data,
+                    );
+                }
+            })
+            .map_err(|_| Error::AtlasFull)?;
+        // glyphs only added keep the coordinates of the ones cached before
+        if evicted || cached_by == CachedBy::Reordering {
+            self.ink.clear();
+            self.generation += 1;
+        }
+        result
+    }
+
+    /// Share of the font texture taken by glyph rows, approximately as rows
+    /// evicted by the glyph cache are only forgotten once overwritten
+    pub(crate) fn atlas_usage(&self) -> f32 {
+        let used: u32 = self
+            .atlas_rows
+            .values()
+            .map(|(height, width)| height * width)
+            .sum();
+        used as f32 / (TEX_WIDTH * (TEX_HEIGHT - SOLID_ROWS)) as f32
     }
 
     pub fn text_metrics(&self, id: FontId, size: f32) -> TextMetrics {
@@ -127,7 +592,7 @@ impl Fonts {
             let scale = Scale::uniform(size);
             let v_metrics = fd.font.v_metrics(scale);
             TextMetrics {
-                ascender: v_metrics.descent,
+                ascender: v_metrics.ascent,
                 descender: v_metrics.descent,
                 line_gap: v_metrics.line_gap,
             }
@@ -140,6 +605,79 @@ impl Fonts {
         }
     }
 
+    /// Metrics of the font at `size`, in the same units as `size`
+    pub fn font_metrics(&self, id: FontId, size: f32) -> Option<FontMetrics> {
+        let fd = self.fonts.get(id)?;
+        let face = fd.face();
+        let scale = fd.font.scale_for_pixel_height(size);
+        let units_per_em = face.units_per_em() as f32;
+        let ascender = face.ascender() as f32;
+        // Fonts without the OS/2 values get them from the outlines of
+        // reference glyphs.
+        let glyph_top = |c: char| {
+            face.glyph_index(c)
+                .and_then(|glyph| face.glyph_bounding_box(glyph))
+                .map(|rect| rect.y_max as f32)
+        };
+        let x_height = face
+            .x_height()
+            .map(|h| h as f32)
+            .or_else(|| glyph_top('x'))
+            .unwrap_or(ascender * 0.5);
+        let cap_height = face
+            .capital_height()
+            .map(|h| h as f32)
+            .or_else(|| glyph_top('H'))
+            .unwrap_or(ascender * 0.7);
+        let (underline_position, underline_thickness) = face
+            .underline_metrics()
+            .map(|m| (m.position as f32, m.thickness as f32))
+            .unwrap_or((-units_per_em / 10.0, units_per_em / 14.0));
+        let (strikeout_position, strikeout_thickness) = face
+            .strikeout_metrics()
+            .map(|m| (m.position as f32, m.thickness as f32))
+            .unwrap_or(((x_height + underline_thickness) * 0.5, underline_thickness));
+
+        Some(FontMetrics {
+            units_per_em: face.units_per_em(),
+            ascender: ascender * scale,
+            descender: face.descender() as f32 * scale,
+            line_gap: face.line_gap() as f32 * scale,
+            x_height: x_height * scale,
+            cap_height: cap_height * scale,
+            underline_position: underline_position * scale,
+            underline_thickness: underline_thickness * scale,
+            strikeout_position: strikeout_position * scale,
+            strikeout_thickness: strikeout_thickness * scale,
+        })
+    }
+
+    /// Metrics of the glyph for `c` at `size`, in the same units as `size`
+    pub fn glyph_metrics(&self, id: FontId, c: char, size: f32) -> Option<GlyphMetrics> {
+        if let Some(custom) = self.custom_glyph(c) {
+            return Some(GlyphMetrics {
+                advance: custom.advance * size,
+                left_side_bearing: 0.0,
+                bounds: custom.bounds(0.0, 0.0, size),
+            });
+        }
+        let (_, glyph) = self.glyph(id, c)?;
+        let glyph = glyph.scaled(Scale::uniform(size));
+        let h_metrics = glyph.h_metrics();
+        let bounds = glyph
+            .exact_bounding_box()
+            .map(|rect| Bounds {
+                min: crate::Point::new(rect.min.x, rect.min.y),
+                max: crate::Point::new(rect.max.x, rect.max.y),
+            })
+            .unwrap_or_default();
+        Some(GlyphMetrics {
+            advance: h_metrics.advance_width,
+            left_side_bearing: h_metrics.left_side_bearing,
+            bounds,
+        })
+    }
+
     pub fn text_size(&self, text: &str, id: FontId, size: f32, spacing: f32) -> Extent {
         if let Some(fd) = self.fonts.get(id) {
             let scale = Scale::uniform(size);
@@ -152,7 +690,11 @@ impl Fonts {
             let mut char_count = 0;
 
             for c in text.chars() {
-                if let Some((_, glyph)) = self.glyph(id, c) {
+                if let Some(custom) = self.custom_glyph(c) {
+                    extent.width += custom.advance * size;
+                    last_glyph = None;
+                    char_count += 1;
+                } else if let Some((_, glyph)) = self.glyph(id, c) {
                     let glyph = glyph.scaled(scale);
                     let h_metrics = glyph.h_metrics();
                     extent.width += h_metrics.advance_width;
@@ -187,8 +729,9 @@ impl Fonts {
         spacing: f32,
         cache: bool,
         result: &mut Vec<LayoutChar>,
-    ) -> anyhow::Result<()> {
+    ) -> crate::Result<LayoutRun> {
         result.clear();
+        let mut run = LayoutRun::default();
 
         if let Some(fd) = self.fonts.get(id) {
             let mut offset = Point { x: 0.0, y: 0.0 };
@@ -223,9 +766,25 @@ impl Fonts {
                 y: position.y + offset.y,
             };
             let mut last_glyph = None;
+            run.x = position.x;
+            run.next_x = position.x;
+            run.baseline = position.y;
 
             for (idx, c) in text.chars().enumerate() {
-                if let Some((id, glyph)) = self.glyph(id, c) {
+                if let Some(custom) = self.custom_glyph(c) {
+                    let lc = self.layout_custom(
+                        &custom,
+                        id,
+                        idx,
+                        c,
+                        (position.x, position.y).into(),
+                        size,
+                    );
+                    run.next_x = lc.next_x;
+                    position.x = lc.next_x + spacing;
+                    last_glyph = None;
+                    result.push(lc);
+                } else if let Some((id, glyph)) = self.glyph(id, c) {
                     let g = glyph.scaled(scale);
                     let h_metrics = g.h_metrics();
 
@@ -240,48 +799,1253 @@ impl Fonts {
                     }
 
                     if let Some(bb) = glyph.pixel_bounding_box() {
-                        self.cache.queue_glyph(id, glyph.clone());
-
                         result.push(LayoutChar {
                             id,
                             idx,
                             c,
                             x: position.x,
                             next_x,
-                            glyph: glyph.clone(),
+                            glyph: Some(glyph.clone()),
+                            image: None,
                             uv: Default::default(),
                             bounds: Bounds {
                                 min: (bb.min.x, bb.min.y).into(),
                                 max: (bb.max.x, bb.max.y).into(),
                             },
+                            rotated: false,
                         });
                     }
 
-                    position.x = next_x;
+                    run.next_x = next_x;
+                    position.x = next_x + spacing;
                     last_glyph = Some(glyph.id());
                 }
             }
 
             if cache {
-                self.render_texture(renderer)?;
-
-                for lc in result {
-                    if let Ok(Some((uv, _))) = self.cache.rect_for(lc.id, &lc.glyph) {
-                        lc.uv = Bounds {
-                            min: crate::Point {
-                                x: uv.min.x,
-                                y: uv.min.y,
-                            },
-                            max: crate::Point {
-                                x: uv.max.x,
-                                y: uv.max.y,
+                self.cache_glyphs(renderer, result, false)?;
+            }
+        }
+
+        Ok(run)
+    }
+
+    /// Returns the positions of the characters of a line of text laid out
+    /// like [`Fonts::layout_text`]. Characters missing from the font are
+    /// skipped.
+    pub fn text_glyph_positions(
+        &self,
+        text: &str,
+        id: FontId,
+        position: crate::Point,
+        size: f32,
+        align: Align,
+        spacing: f32,
+    ) -> Vec<GlyphPosition> {
+        let mut positions = Vec::new();
+        let fd = match self.fonts.get(id) {
+            Some(fd) => fd,
+            None => return positions,
+        };
+        let scale = Scale::uniform(size);
+
+        let mut x = position.x;
+        if align.contains(Align::CENTER) {
+            x -= self.text_size(text, id, size, spacing).width / 2.0;
+        } else if align.contains(Align::RIGHT) {
+            x -= self.text_size(text, id, size, spacing).width;
+        }
+
+        let mut last_glyph = None;
+        for (offset, c) in text.char_indices() {
+            let (next_x, ink) = if let Some(custom) = self.custom_glyph(c) {
+                last_glyph = None;
+                (
+                    x + custom.advance * size,
+                    Some((x, x + custom.width * size)),
+                )
+            } else if let Some((_, glyph)) = self.glyph(id, c) {
+                let glyph = glyph.scaled(scale);
+                let mut next_x = x + glyph.h_metrics().advance_width;
+                if let Some(last_glyph) = last_glyph {
+                    next_x += fd.font.pair_kerning(scale, last_glyph, glyph.id());
+                }
+                last_glyph = Some(glyph.id());
+                let ink = glyph
+                    .exact_bounding_box()
+                    .map(|rect| (x + rect.min.x, x + rect.max.x));
+                (next_x, ink)
+            } else {
+                continue;
+            };
+
+            let (min_x, max_x) = ink.map_or((x, next_x), |(min_x, max_x)| {
+                (min_x.min(x), max_x.max(next_x))
+            });
+            positions.push(GlyphPosition {
+                offset,
+                x,
+                min_x,
+                max_x,
+            });
+            x = next_x + spacing;
+        }
+        positions
+    }
+
+    fn shape_vertical(&self, text: &str, id: FontId, size: f32) -> Vec<VerticalChar> {
+        let scale = Scale::uniform(size);
+        let mut shaped: Vec<VerticalChar> = Vec::new();
+
+        for (idx, c) in text.chars().enumerate() {
+            let upright = is_upright(c);
+            let glyph = if upright {
+                let form = vertical_form(c);
+                self.glyph(id, form)
+                    .map(|glyph| (glyph, form))
+                    .or_else(|| self.glyph(id, c).map(|glyph| (glyph, c)))
+            } else {
+                self.glyph(id, c).map(|glyph| (glyph, c))
+            };
+            let ((glyph_id, glyph), c) = match glyph {
+                Some(glyph) => glyph,
+                None => continue,
+            };
+            let fd = &self.fonts[glyph_id];
+            let glyph = glyph.scaled(scale);
+
+            let advance = if upright {
+                fd.ver_advance(glyph.id(), size)
+            } else {
+                glyph.h_metrics().advance_width
+            };
+            if let Some(last) = shaped.last_mut() {
+                if !upright && !last.upright && last.id == glyph_id {
+                    last.advance += fd.font.pair_kerning(scale, last.glyph.id(), glyph.id());
+                }
+            }
+
+            shaped.push(VerticalChar {
+                id: glyph_id,
+                idx,
+                c,
+                glyph,
+                upright,
+                advance,
+            });
+        }
+        shaped
+    }
+
+    /// Measures text in vertical writing mode. The width is the em box of
+    /// the column.
+    pub fn text_size_vertical(&self, text: &str, id: FontId, size: f32, spacing: f32) -> Extent {
+        let shaped = self.shape_vertical(text, id, size);
+        let height = shaped.iter().map(|ch| ch.advance).sum::<f32>()
+            + spacing * shaped.len().saturating_sub(1) as f32;
+        Extent::new(size, height)
+    }
+
+    /// Lays out text in a column advancing downwards. CJK characters are
+    /// set upright using the vertical metrics of the font, other characters
+    /// are rotated 90° clockwise. The horizontal part of `align` positions
+    /// the column around `position.x`, the vertical part positions the
+    /// column's extent around `position.y`.
+    pub fn layout_text_vertical<R: RendererDevice>(
+        &mut self,
+        renderer: &mut R,
+        text: &str,
+        id: FontId,
+        position: crate::Point,
+        size: f32,
+        align: Align,
+        spacing: f32,
+        cache: bool,
+        result: &mut Vec<LayoutChar>,
+    ) -> crate::Result<()> {
+        result.clear();
+
+        let shaped = self.shape_vertical(text, id, size);
+        let height = shaped.iter().map(|ch| ch.advance).sum::<f32>()
+            + spacing * shaped.len().saturating_sub(1) as f32;
+
+        let center_x = if align.contains(Align::CENTER) {
+            position.x
+        } else if align.contains(Align::RIGHT) {
+            position.x - size / 2.0
+        } else {
+            position.x + size / 2.0
+        };
+        let mut y = if align.contains(Align::MIDDLE) {
+            position.y - height / 2.0
+        } else if align.contains(Align::BOTTOM) {
+            position.y - height
+        } else {
+            position.y
+        };
+
+        for ch in shaped {
+            let fd = &self.fonts[ch.id];
+            let v_metrics = fd.font.v_metrics(Scale::uniform(size));
+
+            let (glyph, bounds) = if ch.upright {
+                let face = fd.face();
+                let glyph_id = owned_ttf_parser::GlyphId(ch.glyph.id().0);
+                let origin_y = face
+                    .glyph_y_origin(glyph_id)
+                    .map(|y| y as f32)
+                    .or_else(|| {
+                        let bearing = face.glyph_ver_side_bearing(glyph_id)?;
+                        let bbox = face.glyph_bounding_box(glyph_id)?;
+                        Some(bearing as f32 + bbox.y_max as f32)
+                    })
+                    .map(|y| y * fd.font.scale_for_pixel_height(size))
+                    .unwrap_or(v_metrics.ascent);
+                let advance_width = ch.glyph.h_metrics().advance_width;
+                let glyph = ch.glyph.positioned(Point {
+                    x: center_x - advance_width / 2.0,
+                    y: y + origin_y,
+                });
+                let bounds = glyph.pixel_bounding_box().map(|bb| Bounds {
+                    min: (bb.min.x, bb.min.y).into(),
+                    max: (bb.max.x, bb.max.y).into(),
+                });
+                (glyph, bounds)
+            } else {
+                // Rasterized upright at the origin, then placed rotated with
+                // the em box centered on the column.
+                let glyph = ch.glyph.positioned(Point { x: 0.0, y: 0.0 });
+                let baseline_x = (center_x - (v_metrics.ascent + v_metrics.descent) / 2.0).round();
+                let top = y.round();
+                let bounds = glyph.pixel_bounding_box().map(|bb| Bounds {
+                    min: (baseline_x - bb.max.y as f32, top + bb.min.x as f32).into(),
+                    max: (baseline_x - bb.min.y as f32, top + bb.max.x as f32).into(),
+                });
+                (glyph, bounds)
+            };
+
+            if let Some(bounds) = bounds {
+                result.push(LayoutChar {
+                    id: ch.id,
+                    idx: ch.idx,
+                    c: ch.c,
+                    x: bounds.min.x,
+                    next_x: bounds.max.x,
+                    glyph: Some(glyph),
+                    image: None,
+                    uv: Default::default(),
+                    bounds,
+                    rotated: !ch.upright,
+                });
+            }
+            y += ch.advance + spacing;
+        }
+
+        if cache {
+            self.cache_glyphs(renderer, result, false)?;
+        }
+        Ok(())
+    }
+
+    /// Snaps the glyphs to the configured positions, rasterizes them into
+    /// the font texture and assigns their texture coordinates. LCD glyphs are
+    /// rasterized at three texels per pixel horizontally.
+    pub(crate) fn cache_glyphs<R: RendererDevice>(
+        &mut self,
+        renderer: &mut R,
+        chars: &mut [LayoutChar],
+        lcd: bool,
+    ) -> crate::Result<()> {
+        let steps = match self.positioning {
+            GlyphPositioning::Pixel => 1.0,
+            GlyphPositioning::Subpixel { steps } => steps.max(1) as f32,
+        };
+        for lc in chars.iter_mut() {
+            let glyph = match &mut lc.glyph {
+                Some(glyph) => glyph,
+                None => continue,
+            };
+            // Rotated glyphs are rasterized at the origin and placed by their
+            // bounds alone.
+            if !lc.rotated {
+                let position = glyph.position();
+                let scaled = glyph.unpositioned().clone();
+                *glyph = if lcd {
+                    let scale = scaled.scale();
+                    scaled
+                        .unscaled()
+                        .clone()
+                        .scaled(Scale {
+                            x: scale.x * LCD_SUBPIXELS,
+                            y: scale.y,
+                        })
+                        .positioned(Point {
+                            x: (position.x * LCD_SUBPIXELS).round(),
+                            y: position.y.round(),
+                        })
+                } else {
+                    scaled.positioned(Point {
+                        x: (position.x * steps).round() / steps,
+                        y: position.y.round(),
+                    })
+                };
+            }
+            self.cache.queue_glyph(lc.id, glyph.clone());
+        }
+
+        self.render_texture(renderer)?;
+
+        let v_scale = (TEX_HEIGHT - SOLID_ROWS) as f32 / TEX_HEIGHT as f32;
+        let h_scale = if lcd { LCD_SUBPIXELS } else { 1.0 };
+        for lc in chars {
+            let glyph = match &lc.glyph {
+                Some(glyph) => glyph,
+                None => continue,
+            };
+            if let Ok(Some((uv, bb))) = self.cache.rect_for(lc.id, glyph) {
+                lc.uv = Bounds {
+                    min: crate::Point {
+                        x: uv.min.x,
+                        y: uv.min.y * v_scale,
+                    },
+                    max: crate::Point {
+                        x: uv.max.x,
+                        y: uv.max.y * v_scale,
+                    },
+                };
+                if !lc.rotated {
+                    lc.bounds = Bounds {
+                        min: (bb.min.x as f32 / h_scale, bb.min.y as f32).into(),
+                        max: (bb.max.x as f32 / h_scale, bb.max.y as f32).into(),
+                    };
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn shape_spans(&self, spans: &[TextSpan], scale: f32) -> Vec<ShapedChar> {
+        let mut shaped = Vec::new();
+        let mut last_glyph: Option<(FontId, GlyphId)> = None;
+
+        for (span_idx, span) in spans.iter().enumerate() {
+            let glyph_scale = Scale::uniform(span.size * scale);
+            for (idx, c) in span.text.chars().enumerate() {
+                let mut ch = ShapedChar {
+                    span: span_idx,
+                    idx,
+                    c,
+                    glyph: None,
+                    custom: None,
+                    kerning: 0.0,
+                    advance: 0.0,
+                    spacing: span.letter_spacing * scale,
+                };
+                let glyph = if c == '\n' || c == '\r' {
+                    None
+                } else {
+                    self.glyph(span.font_id, c)
+                };
+                if let Some(custom) = self.custom_glyph(c) {
+                    ch.advance = custom.advance * span.size * scale;
+                    ch.custom = Some(custom);
+                    last_glyph = None;
+                } else if let Some((id, glyph)) = glyph {
+                    let glyph = glyph.scaled(glyph_scale);
+                    if let Some((last_id, last)) = last_glyph {
+                        if last_id == id {
+                            ch.kerning =
+                                self.fonts[id]
+                                    .font
+                                    .pair_kerning(glyph_scale, last, glyph.id());
+                        }
+                    }
+                    ch.advance = glyph.h_metrics().advance_width;
+                    last_glyph = Some((id, glyph.id()));
+                    ch.glyph = Some((id, glyph));
+                } else {
+                    last_glyph = None;
+                }
+                shaped.push(ch);
+            }
+        }
+        shaped
+    }
+
+    fn can_break_before(shaped: &[ShapedChar], i: usize) -> bool {
+        let (prev, c) = (shaped[i - 1].c, shaped[i].c);
+        (prev.is_whitespace() && !c.is_whitespace()) || is_cjk(prev) || is_cjk(c)
+    }
+
+    fn span_line(
+        &self,
+        spans: &[TextSpan],
+        scale: f32,
+        shaped: &[ShapedChar],
+        chars: Range<usize>,
+    ) -> SpanLine {
+        let mut end = chars.end;
+        while end > chars.start && shaped[end - 1].c.is_whitespace() {
+            end -= 1;
+        }
+        let mut line = SpanLine {
+            chars: chars.start..end,
+            width: 0.0,
+            ascent: 0.0,
+            descent: 0.0,
+            line_gap: 0.0,
+        };
+
+        let mut x = 0.0;
+        for (n, ch) in shaped[chars.start..end].iter().enumerate() {
+            if n > 0 {
+                x += ch.kerning;
+            }
+            line.width = x + ch.advance;
+            x = line.width + ch.spacing;
+        }
+
+        let mut line_spans: Vec<usize> =
+            shaped[chars.start..end].iter().map(|ch| ch.span).collect();
+        if line_spans.is_empty() {
+            // Empty lines take the metrics of the span holding the line break.
+            line_spans.extend(
+                shaped
+                    .get(chars.start)
+                    .or_else(|| shaped.last())
+                    .map(|ch| ch.span)
+                    .or(if spans.is_empty() { None } else { Some(0) }),
+            );
+        }
+        line_spans.dedup();
+        for span in line_spans {
+            let span = &spans[span];
+            if let Some(fd) = self.fonts.get(span.font_id) {
+                let v_metrics = fd.font.v_metrics(Scale::uniform(span.size * scale));
+                line.ascent = line.ascent.max(v_metrics.ascent);
+                line.descent = line.descent.min(v_metrics.descent);
+                line.line_gap = line.line_gap.max(v_metrics.line_gap);
+            }
+        }
+        // Custom glyphs taller than the fonts push the neighbouring lines
+        // away.
+        for ch in &shaped[line.chars.clone()] {
+            if let Some(custom) = &ch.custom {
+                let (ascent, descent) = custom.extent(spans[ch.span].size * scale);
+                line.ascent = line.ascent.max(ascent);
+                line.descent = line.descent.min(descent);
+            }
+        }
+        line
+    }
+
+    fn break_lines(
+        &self,
+        spans: &[TextSpan],
+        scale: f32,
+        shaped: &[ShapedChar],
+        break_width: Option<f32>,
+    ) -> Vec<SpanLine> {
+        let mut lines = Vec::new();
+        let mut start = 0;
+        let mut x = 0.0;
+        let mut last_break = None;
+        let mut i = 0;
+
+        while i < shaped.len() {
+            let ch = &shaped[i];
+            if ch.c == '\n' {
+                lines.push(self.span_line(spans, scale, shaped, start..i));
+                start = i + 1;
+                x = 0.0;
+                last_break = None;
+                i += 1;
+                continue;
+            }
+            if i > start && Self::can_break_before(shaped, i) {
+                last_break = Some(i);
+            }
+
+            let advance = if i > start { ch.kerning } else { 0.0 } + ch.advance;
+            if let Some(width) = break_width {
+                if i > start && !ch.c.is_whitespace() && x + advance > width {
+                    let end = last_break.unwrap_or(i);
+                    lines.push(self.span_line(spans, scale, shaped, start..end));
+                    start = end;
+                    while start < shaped.len()
+                        && shaped[start].c != '\n'
+                        && shaped[start].c.is_whitespace()
+                    {
+                        start += 1;
+                    }
+                    i = start;
+                    x = 0.0;
+                    last_break = None;
+                    continue;
+                }
+            }
+            x += advance + ch.spacing;
+            i += 1;
+        }
+        lines.push(self.span_line(spans, scale, shaped, start..shaped.len()));
+        lines
+    }
+
+    /// Measures attributed text, wrapping it at `break_width` if given.
+    pub(crate) fn spans_size(
+        &self,
+        spans: &[TextSpan],
+        scale: f32,
+        line_height: f32,
+        break_width: Option<f32>,
+    ) -> Extent {
+        let shaped = self.shape_spans(spans, scale);
+        self.break_lines(spans, scale, &shaped, break_width)
+            .iter()
+            .fold(Extent::new(0.0, 0.0), |extent, line| {
+                Extent::new(
+                    extent.width.max(line.width),
+                    extent.height + (line.ascent - line.descent + line.line_gap) * line_height,
+                )
+            })
+    }
+
+    /// Splits text into the byte ranges of its lines when wrapped at
+    /// `break_width`, without trailing whitespace.
+    pub fn break_text(
+        &self,
+        text: &str,
+        id: FontId,
+        size: f32,
+        spacing: f32,
+        break_width: f32,
+    ) -> Vec<Range<usize>> {
+        let span = TextSpan {
+            letter_spacing: spacing,
+            ..TextSpan::new(text, id, size, Color::rgba(0.0, 0.0, 0.0, 0.0))
+        };
+        let spans = [span];
+        let shaped = self.shape_spans(&spans, 1.0);
+        let offsets: Vec<usize> = text
+            .char_indices()
+            .map(|(offset, _)| offset)
+            .chain(std::iter::once(text.len()))
+            .collect();
+        self.break_lines(&spans, 1.0, &shaped, Some(break_width))
+            .iter()
+            .map(|line| offsets[line.chars.start]..offsets[line.chars.end])
+            .collect()
+    }
+
+    /// Fits text into `max_lines` lines of `max_width`, replacing the
+    /// overflowing part with an ellipsis. Widths are measured with
+    /// [`Fonts::text_size`], so the lines fit exactly as they are drawn.
+    pub fn truncate_text(
+        &self,
+        text: &str,
+        id: FontId,
+        size: f32,
+        spacing: f32,
+        max_width: f32,
+        max_lines: usize,
+        ellipsis: TextEllipsis,
+    ) -> Vec<String> {
+        if max_lines == 0 {
+            return Vec::new();
+        }
+        let lines = self.break_text(text, id, size, spacing, max_width);
+        if lines.len() <= max_lines {
+            return lines
+                .into_iter()
+                .map(|line| text[line].to_string())
+                .collect();
+        }
+
+        let (head, tail) = match ellipsis {
+            TextEllipsis::Start => (0, max_lines - 1),
+            TextEllipsis::Middle => (max_lines / 2, (max_lines - 1) / 2),
+            TextEllipsis::End => (max_lines - 1, 0),
+        };
+        let elided = lines[head].start..lines[lines.len() - tail - 1].end;
+        let elided = text[elided].replace(['\n', '\r'], " ");
+        let elided = self.elide_line(&elided, id, size, spacing, max_width, ellipsis);
+
+        lines[..head]
+            .iter()
+            .map(|line| text[line.clone()].to_string())
+            .chain(std::iter::once(elided))
+            .chain(
+                lines[lines.len() - tail..]
+                    .iter()
+                    .map(|line| text[line.clone()].to_string()),
+            )
+            .collect()
+    }
+
+    fn elide_line(
+        &self,
+        line: &str,
+        id: FontId,
+        size: f32,
+        spacing: f32,
+        max_width: f32,
+        ellipsis: TextEllipsis,
+    ) -> String {
+        let line = line.trim();
+        if self.text_size(line, id, size, spacing).width <= max_width {
+            return line.to_string();
+        }
+        let mark = if self.glyph(id, '\u{2026}').is_some() {
+            "\u{2026}"
+        } else {
+            "..."
+        };
+        let chars: Vec<(usize, char)> = line.char_indices().collect();
+        let candidate = |keep: usize| {
+            let (head, tail) = match ellipsis {
+                TextEllipsis::Start => (0, keep),
+                TextEllipsis::Middle => (keep.div_ceil(2), keep / 2),
+                TextEllipsis::End => (keep, 0),
+            };
+            let head_end = chars
+                .get(head)
+                .map(|(offset, _)| *offset)
+                .unwrap_or(line.len());
+            let tail_start = chars[chars.len() - tail..]
+                .first()
+                .map(|(offset, _)| *offset)
+                .unwrap_or(line.len());
+            format!(
+                "{}{}{}",
+                line[..head_end].trim_end(),
+                mark,
+                line[tail_start..].trim_start()
+            )
+        };
+
+        // The width grows with the number of kept characters, so search for
+        // the longest candidate that still fits.
+        let (mut lo, mut hi) = (0, chars.len());
+        while lo < hi {
+            let mid = (lo + hi).div_ceil(2);
+            if self.text_size(&candidate(mid), id, size, spacing).width <= max_width {
+                lo = mid;
+            } else {
+                hi = mid - 1;
+            }
+        }
+        let result = candidate(lo);
+        if lo == 0 && self.text_size(&result, id, size, spacing).width > max_width {
+            String::new()
+        } else {
+            result
+        }
+    }
+
+    /// Lays out attributed text on common baselines, wrapping it at
+    /// `break_width` if given. `scale` converts span sizes and spacings into
+    /// the space of `position`.
+    pub(crate) fn layout_spans(
+        &self,
+        spans: &[TextSpan],
+        scale: f32,
+        position: crate::Point,
+        align: Align,
+        line_height: f32,
+        break_width: Option<f32>,
+        result: &mut Vec<LayoutChar>,
+        runs: &mut Vec<SpanRun>,
+    ) {
+        result.clear();
+        runs.clear();
+
+        let shaped = self.shape_spans(spans, scale);
+        let lines = self.break_lines(spans, scale, &shaped, break_width);
+
+        let mut baseline = position.y;
+        if let Some(first) = lines.first() {
+            if align.contains(Align::MIDDLE) {
+                baseline += (first.ascent + first.descent + first.line_gap) / 2.0;
+            } else if align.contains(Align::BOTTOM) {
+                baseline += first.descent;
+            } else if align.contains(Align::TOP) {
+                baseline += first.ascent;
+            }
+        }
+
+        let mut prev_line: Option<&SpanLine> = None;
+        for line in &lines {
+            if let Some(prev) = prev_line {
+                baseline += (prev.line_gap - prev.descent + line.ascent) * line_height;
+            }
+            prev_line = Some(line);
+
+            let mut x = position.x;
+            if align.contains(Align::CENTER) {
+                x += match break_width {
+                    Some(width) => (width - line.width) / 2.0,
+                    None => -line.width / 2.0,
+                };
+            } else if align.contains(Align::RIGHT) {
+                x += match break_width {
+                    Some(width) => width - line.width,
+                    None => -line.width,
+                };
+            }
+
+            for (n, ch) in shaped[line.chars.clone()].iter().enumerate() {
+                if n > 0 {
+                    x += ch.kerning;
+                }
+                let next_x = x + ch.advance;
+
+                match runs.last_mut() {
+                    Some(span_run) if span_run.span == ch.span && n > 0 => {
+                        span_run.run.next_x = next_x;
+                    }
+                    _ => runs.push(SpanRun {
+                        span: ch.span,
+                        run: LayoutRun {
+                            x,
+                            next_x,
+                            baseline,
+                        },
+                        chars: result.len()..result.len(),
+                    }),
+                }
+
+                if let Some(custom) = &ch.custom {
+                    let size = spans[ch.span].size * scale;
+                    let font_id = spans[ch.span].font_id;
+                    result.push(self.layout_custom(
+                        custom,
+                        font_id,
+                        ch.idx,
+                        ch.c,
+                        (x, baseline).into(),
+                        size,
+                    ));
+                } else if let Some((id, glyph)) = &ch.glyph {
+                    let glyph = glyph.clone().positioned(Point { x, y: baseline });
+                    if let Some(bb) = glyph.pixel_bounding_box() {
+                        result.push(LayoutChar {
+                            id: *id,
+                            idx: ch.idx,
+                            c: ch.c,
+                            x,
+                            next_x,
+                            glyph: Some(glyph),
+                            image: None,
+                            uv: Default::default(),
+                            bounds: Bounds {
+                                min: (bb.min.x, bb.min.y).into(),
+                                max: (bb.max.x, bb.max.y).into(),
                             },
-                        };
+                            rotated: false,
+                        });
                     }
                 }
+                if let Some(span_run) = runs.last_mut() {
+                    span_run.chars.end = result.len();
+                }
+                x = next_x + ch.spacing;
             }
         }
+    }
 
-        Ok(())
+    /// Texture coordinates of the opaque area of the font texture.
+    pub(crate) fn solid_uv(&self) -> Bounds {
+        let uv = crate::Point::new(
+            0.5,
+            (TEX_HEIGHT - SOLID_ROWS / 2) as f32 / TEX_HEIGHT as f32,
+        );
+        Bounds { min: uv, max: uv }
+    }
+
+    /// Computes the rectangles of the text decorations for a laid out run,
+    /// optionally leaving gaps where the glyphs' ink crosses the underline
+    /// or overline. The glyphs must be cached, see [`Fonts::cache_glyphs`].
+    pub(crate) fn layout_decorations(
+        &mut self,
+        id: FontId,
+        size: f32,
+        decoration: TextDecoration,
+        skip_ink: bool,
+        run: &LayoutRun,
+        chars: &[LayoutChar],
+    ) -> Vec<Bounds> {
+        let mut result = Vec::new();
+        let metrics = match self.font_metrics(id, size) {
+            Some(metrics) => metrics,
+            None => return result,
+        };
+
+        for line in [
+            TextDecoration::UNDERLINE,
+            TextDecoration::STRIKETHROUGH,
+            TextDecoration::OVERLINE,
+        ]
+        .iter()
+        {
+            if !decoration.contains(*line) {
+                continue;
+            }
+            let (position, thickness) = if *line == TextDecoration::STRIKETHROUGH {
+                (metrics.strikeout_position, metrics.strikeout_thickness)
+            } else if *line == TextDecoration::OVERLINE {
+                (metrics.ascender, metrics.underline_thickness)
+            } else {
+                (metrics.underline_position, metrics.underline_thickness)
+            };
+            let thickness = thickness.round().max(1.0);
+            let top = (run.baseline - position).round();
+            let bounds = Bounds {
+                min: crate::Point::new(run.x, top),
+                max: crate::Point::new(run.next_x, top + thickness),
+            };
+            if skip_ink && *line != TextDecoration::STRIKETHROUGH {
+                self.skip_ink(bounds, chars, &mut result);
+            } else {
+                result.push(bounds);
+            }
+        }
+        result
+    }
+
+    fn skip_ink(&mut self, line: Bounds, chars: &[LayoutChar], result: &mut Vec<Bounds>) {
+        let pad = line.height().max(1.0);
+        let band = (line.min.y - pad, line.max.y + pad);
+        let mut gaps = Vec::new();
+
+        for lc in chars {
+            let glyph = match &lc.glyph {
+                Some(glyph) => glyph,
+                None => continue,
+            };
+            if lc.bounds.max.y <= band.0 || lc.bounds.min.y >= band.1 {
+                continue;
+            }
+            let glyph_ink = self
+                .ink
+                .entry(glyph_key(lc.id, glyph))
+                .or_insert_with(|| GlyphInk::new(glyph));
+            let mut ink = vec![false; glyph_ink.columns + 1];
+            for &(column, top, bottom) in &glyph_ink.runs {
+                // rows count as ink when their centers are within the band
+                let top = lc.bounds.min.y + top as f32 + 0.5;
+                let bottom = lc.bounds.min.y + bottom as f32 - 0.5;
+                if top <= band.1 && bottom >= band.0 {
+                    ink[column] = true;
+                }
+            }
+
+            let mut start = None;
+            for (x, covered) in ink.iter().enumerate() {
+                match (start, covered) {
+                    (None, true) => start = Some(x),
+                    (Some(s), false) => {
+                        gaps.push((
+                            lc.bounds.min.x + s as f32 - pad,
+                            lc.bounds.min.x + x as f32 + pad,
+                        ));
+                        start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        gaps.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut x = line.min.x;
+        for (gap_start, gap_end) in gaps {
+            if gap_start > x {
+                result.push(Bounds {
+                    min: crate::Point::new(x, line.min.y),
+                    max: crate::Point::new(gap_start.min(line.max.x), line.max.y),
+                });
+            }
+            x = x.max(gap_end);
+            if x >= line.max.x {
+                break;
+            }
+        }
+        if x < line.max.x {
+            result.push(Bounds {
+                min: crate::Point::new(x, line.min.y),
+                max: line.max,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::tests::NullRenderer;
+
+    fn fonts() -> (Fonts, FontId) {
+        let mut fonts = Fonts::new(&mut NullRenderer::default()).unwrap();
+        let id = fonts
+            .add_font(
+                "roboto",
+                &include_bytes!("../nvgx-demo/Roboto-Bold.ttf")[..],
+            )
+            .unwrap();
+        (fonts, id)
+    }
+
+    fn assert_near(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} instead of {}",
+            actual,
+            expected
+        );
+    }
+
+    fn rect(x0: u32, y0: u32, x1: u32, y1: u32) -> rusttype::Rect<u32> {
+        rusttype::Rect {
+            min: rusttype::point(x0, y0),
+            max: rusttype::point(x1, y1),
+        }
+    }
+
+    #[test]
+    fn atlas_rows_track_evictions() {
+        let mut rows = BTreeMap::new();
+        // rows and glyphs added to free space keep the glyphs before
+        assert!(!track_atlas_row(&mut rows, &rect(0, 0, 10, 12)));
+        assert!(!track_atlas_row(&mut rows, &rect(10, 0, 18, 8)));
+        assert!(!track_atlas_row(&mut rows, &rect(0, 12, 20, 30)));
+        assert_eq!(rows.get(&0), Some(&(12, 18)));
+        assert_eq!(rows.get(&12), Some(&(18, 20)));
+
+        // a taller row in the space of the first one overwrites both
+        assert!(track_atlas_row(&mut rows, &rect(0, 0, 6, 14)));
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows.get(&0), Some(&(14, 6)));
+    }
+
+    #[test]
+    fn break_at_whitespace() {
+        let (fonts, id) = fonts();
+        let text = "hello world again";
+        let width = fonts.text_size("hello world", id, 20.0, 0.0).width;
+
+        let lines = fonts.break_text(text, id, 20.0, 0.0, width);
+        let lines: Vec<&str> = lines.into_iter().map(|line| &text[line]).collect();
+        assert_eq!(lines, vec!["hello world", "again"]);
+
+        // letter spacing widens the line past the width
+        let lines = fonts.break_text(text, id, 20.0, 2.0, width);
+        let lines: Vec<&str> = lines.into_iter().map(|line| &text[line]).collect();
+        assert_eq!(lines, vec!["hello", "world", "again"]);
+
+        // words longer than the width are broken between characters
+        let lines = fonts.break_text("abcdef", id, 20.0, 0.0, width / 4.0);
+        assert!(lines.len() > 1);
+        assert_eq!(lines.last().unwrap().end, 6);
+    }
+
+    #[test]
+    fn break_at_newlines() {
+        let (fonts, id) = fonts();
+        let text = "one\n\ntwo  \nthree";
+        let lines = fonts.break_text(text, id, 20.0, 0.0, 1000.0);
+        let lines: Vec<&str> = lines.into_iter().map(|line| &text[line]).collect();
+        assert_eq!(lines, vec!["one", "", "two", "three"]);
+    }
+
+    #[test]
+    fn mixed_span_size() {
+        let (fonts, id) = fonts();
+        let spans = [
+            TextSpan::new("small ", id, 10.0, Color::rgb(1.0, 0.0, 0.0)),
+            TextSpan::new("large", id, 30.0, Color::rgb(0.0, 0.0, 1.0)),
+        ];
+        let size = fonts.spans_size(&spans, 1.0, 1.0, None);
+        let small = fonts.text_size("small ", id, 10.0, 0.0);
+        let large = fonts.text_size("large", id, 30.0, 0.0);
+        assert_near(size.width, small.width + large.width, 0.5);
+        // the line is as tall as its largest span
+        assert_near(size.height, large.height, 1e-3);
+
+        // scaling applies to every span
+        let scaled = fonts.spans_size(&spans, 2.0, 1.0, None);
+        assert_near(scaled.width, size.width * 2.0, 1.0);
+
+        // wrapped, each span gets its own line
+        let wrapped = fonts.spans_size(&spans, 1.0, 1.0, Some(large.width));
+        assert_near(wrapped.width, large.width, 1e-3);
+        assert_near(wrapped.height, small.height + large.height, 1e-3);
+    }
+
+    #[test]
+    fn truncate() {
+        let (fonts, id) = fonts();
+        let text = "the quick brown fox jumps over the lazy dog";
+        let width = fonts.text_size("the quick brown", id, 20.0, 0.0).width;
+        let mark = if fonts.glyph(id, '\u{2026}').is_some() {
+            "\u{2026}"
+        } else {
+            "..."
+        };
+        let fits = |line: &String| fonts.text_size(line, id, 20.0, 0.0).width <= width;
+
+        let end = fonts.truncate_text(text, id, 20.0, 0.0, width, 1, TextEllipsis::End);
+        assert_eq!(end.len(), 1);
+        assert!(end[0].starts_with("the quick") && end[0].ends_with(mark));
+        assert!(fits(&end[0]));
+
+        let start = fonts.truncate_text(text, id, 20.0, 0.0, width, 1, TextEllipsis::Start);
+        assert!(start[0].starts_with(mark) && start[0].ends_with("lazy dog"));
+        assert!(fits(&start[0]));
+
+        let middle = fonts.truncate_text(text, id, 20.0, 0.0, width, 1, TextEllipsis::Middle);
+        assert!(middle[0].starts_with("the") && middle[0].ends_with("dog"));
+        assert!(middle[0].contains(mark));
+        assert!(fits(&middle[0]));
+    }
+
+    #[test]
+    fn truncate_lines() {
+        let (fonts, id) = fonts();
+        let text = "the quick brown fox jumps over the lazy dog";
+        let width = fonts.text_size("the quick brown", id, 20.0, 0.0).width;
+
+        let lines = fonts.truncate_text(text, id, 20.0, 0.0, width, 2, TextEllipsis::End);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "the quick brown");
+        assert!(lines[1].starts_with("fox jumps"));
+
+        // text fitting into the lines is only broken
+        let lines = fonts.truncate_text(text, id, 20.0, 0.0, width, 10, TextEllipsis::End);
+        assert_eq!(lines.join(" "), text);
+
+        assert!(fonts
+            .truncate_text(text, id, 20.0, 0.0, width, 0, TextEllipsis::End)
+            .is_empty());
+        // not even the ellipsis fits
+        let lines = fonts.truncate_text(text, id, 20.0, 0.0, 1.0, 1, TextEllipsis::End);
+        assert_eq!(lines, vec![String::new()]);
+    }
+
+    #[test]
+    fn upright_characters() {
+        assert!(is_upright('\u{6F22}'));
+        assert!(is_upright('\u{3042}'));
+        assert!(!is_upright('A'));
+        // the prolonged sound mark is rotated like a dash
+        assert!(!is_upright('\u{30FC}'));
+        assert_eq!(vertical_form('\u{3002}'), '\u{FE12}');
+        assert_eq!(vertical_form('\u{300C}'), '\u{FE41}');
+        assert_eq!(vertical_form('a'), 'a');
+    }
+
+    #[test]
+    fn vertical_column() {
+        let (mut fonts, id) = fonts();
+        let size = fonts.text_size_vertical("Hello", id, 20.0, 2.0);
+        assert_eq!(size.width, 20.0);
+        // rotated glyphs advance by their horizontal advance
+        let advances = "Hello"
+            .chars()
+            .map(|c| fonts.glyph_metrics(id, c, 20.0).unwrap().advance)
+            .sum::<f32>();
+        assert_near(size.height, advances + 4.0 * 2.0, 1e-3);
+
+        let mut chars = Vec::new();
+        fonts
+            .layout_text_vertical(
+                &mut NullRenderer::default(),
+                "Hello",
+                id,
+                (100.0, 50.0).into(),
+                20.0,
+                Align::CENTER | Align::MIDDLE,
+                2.0,
+                false,
+                &mut chars,
+            )
+            .unwrap();
+        assert_eq!(chars.len(), 5);
+        assert!(chars.iter().all(|lc| lc.rotated));
+        for pair in chars.windows(2) {
+            assert!(pair[0].bounds.min.y < pair[1].bounds.min.y);
+        }
+        for lc in &chars {
+            assert!(lc.bounds.min.x >= 90.0 && lc.bounds.max.x <= 110.0);
+            assert!(lc.bounds.min.y >= 50.0 - size.height / 2.0 - 1.0);
+            assert!(lc.bounds.max.y <= 50.0 + size.height / 2.0 + 1.0);
+        }
+    }
+
+    #[test]
+    fn vertical_advance_without_vmtx() {
+        let (fonts, id) = fonts();
+        let fd = &fonts.fonts[id];
+        let face = fd.face();
+        assert!(face.tables().vmtx.is_none());
+
+        // one em, scaled like the glyphs rather than by the font size
+        let glyph = fd.font.glyph('A').id();
+        let height = (face.ascender() - face.descender()) as f32;
+        let em = 20.0 * face.units_per_em() as f32 / height;
+        assert_near(fd.ver_advance(glyph, 20.0), em, 1e-3);
+        assert!((em - 20.0).abs() > 1.0);
+    }
+
+    #[test]
+    fn font_metrics() {
+        let (fonts, id) = fonts();
+        let face = fonts.fonts[id].face();
+        let metrics = fonts.font_metrics(id, 20.0).unwrap();
+        let height = (face.ascender() - face.descender()) as f32;
+        let scale = 20.0 / height;
+
+        assert_eq!(metrics.units_per_em, 2048);
+        assert_near(metrics.ascender - metrics.descender, 20.0, 1e-3);
+        let x_height = face.x_height().unwrap() as f32 * scale;
+        let cap_height = face.capital_height().unwrap() as f32 * scale;
+        assert_near(metrics.x_height, x_height, 1e-3);
+        assert_near(metrics.cap_height, cap_height, 1e-3);
+        assert!(0.0 < metrics.x_height && metrics.x_height < metrics.cap_height);
+        assert!(metrics.cap_height < metrics.ascender);
+        assert!(metrics.underline_position < 0.0);
+
+        let unscaled = fonts.font_metrics(id, 40.0).unwrap();
+        assert_eq!(unscaled.units_per_em, 2048);
+        assert_near(unscaled.cap_height, cap_height * 2.0, 1e-3);
+    }
+
+    #[test]
+    fn glyph_metrics() {
+        let (fonts, id) = fonts();
+        let face = fonts.fonts[id].face();
+        let scale = 20.0 / (face.ascender() - face.descender()) as f32;
+        let glyph = face.glyph_index('H').unwrap();
+        let rect = face.glyph_bounding_box(glyph).unwrap();
+        let metrics = fonts.glyph_metrics(id, 'H', 20.0).unwrap();
+
+        let advance = face.glyph_hor_advance(glyph).unwrap() as f32 * scale;
+        assert_near(metrics.advance, advance, 1e-3);
+        // y points down, so the ink of 'H' sits above the baseline
+        assert_near(metrics.bounds.min.x, rect.x_min as f32 * scale, 1e-2);
+        assert_near(metrics.bounds.max.x, rect.x_max as f32 * scale, 1e-2);
+        assert_near(metrics.bounds.min.y, -rect.y_max as f32 * scale, 1e-2);
+        assert_near(metrics.bounds.max.y, -rect.y_min as f32 * scale, 1e-2);
+        assert_near(
+            -metrics.bounds.min.y,
+            fonts.font_metrics(id, 20.0).unwrap().cap_height,
+            1e-2,
+        );
+
+        let space = fonts.glyph_metrics(id, ' ', 20.0).unwrap();
+        assert!(space.advance > 0.0);
+        assert_eq!(space.bounds.min.x, space.bounds.max.x);
+    }
+
+    #[test]
+    fn decorations() {
+        let (mut fonts, id) = fonts();
+        let mut renderer = NullRenderer::default();
+        let mut chars = Vec::new();
+        let run = fonts
+            .layout_text(
+                &mut renderer,
+                "gypsy",
+                id,
+                (10.3, 40.0).into(),
+                32.0,
+                Align::LEFT | Align::BASELINE,
+                0.0,
+                false,
+                &mut chars,
+            )
+            .unwrap();
+        fonts
+            .cache_glyphs(&mut renderer, &mut chars, false)
+            .unwrap();
+        let all = TextDecoration::UNDERLINE | TextDecoration::STRIKETHROUGH;
+
+        let lines = fonts.layout_decorations(id, 32.0, all, false, &run, &chars);
+        assert_eq!(lines.len(), 2);
+        let (underline, strikethrough) = (lines[0], lines[1]);
+        assert!(underline.min.y > run.baseline);
+        assert!(strikethrough.max.y < run.baseline);
+        for line in &lines {
+            assert_eq!((line.min.x, line.max.x), (run.x, run.next_x));
+            assert!(line.height() >= 1.0);
+        }
+
+        // descenders cut the underline into pieces, the strikethrough stays
+        let lines = fonts.layout_decorations(id, 32.0, all, true, &run, &chars);
+        let pieces: Vec<&Bounds> = lines
+            .iter()
+            .filter(|line| line.min.y == underline.min.y)
+            .collect();
+        assert!(pieces.len() > 1);
+        let covered = pieces.iter().map(|line| line.width()).sum::<f32>();
+        assert!(covered < underline.width());
+        assert!(
+            lines
+                .iter()
+                .any(|line| line.min.y == strikethrough.min.y
+                    && line.width() == strikethrough.width())
+        );
+        // gaps are around the descenders of the glyphs as drawn
+        for pair in pieces.windows(2) {
+            let (gap_start, gap_end) = (pair[0].max.x, pair[1].min.x);
+            assert!(chars.iter().any(|lc| {
+                "gyp".contains(lc.c) && lc.bounds.min.x < gap_end && lc.bounds.max.x > gap_start
+            }));
+        }
+
+        // the ink of the glyphs is kept until the cached glyphs change
+        let cached = fonts.ink.len();
+        assert!(cached > 0);
+        let again = fonts.layout_decorations(id, 32.0, all, true, &run, &chars);
+        assert_eq!(again.len(), lines.len());
+        assert_eq!(fonts.ink.len(), cached);
+        fonts.set_glyph_positioning(GlyphPositioning::Subpixel { steps: 3 });
+        assert!(fonts.ink.is_empty());
+    }
+
+    #[test]
+    fn lcd_decorations() {
+        let (mut fonts, id) = fonts();
+        let mut renderer = NullRenderer::default();
+        let mut chars = Vec::new();
+        let run = fonts
+            .layout_text(
+                &mut renderer,
+                "gypsy",
+                id,
+                (10.0, 40.0).into(),
+                32.0,
+                Align::LEFT | Align::BASELINE,
+                0.0,
+                false,
+                &mut chars,
+            )
+            .unwrap();
+        let mut lcd_chars = chars.clone();
+        fonts
+            .cache_glyphs(&mut renderer, &mut chars, false)
+            .unwrap();
+        fonts
+            .cache_glyphs(&mut renderer, &mut lcd_chars, true)
+            .unwrap();
+
+        let underline = TextDecoration::UNDERLINE;
+        let gray = fonts.layout_decorations(id, 32.0, underline, true, &run, &chars);
+        let lcd = fonts.layout_decorations(id, 32.0, underline, true, &run, &lcd_chars);
+        // glyphs rasterized at three columns per pixel skip the same ink, to
+        // the pixel
+        for piece in &lcd {
+            assert!(gray.iter().any(|gray| {
+                (piece.min.x - gray.min.x).abs() <= 1.0 && (piece.max.x - gray.max.x).abs() <= 1.0
+            }));
+        }
     }
 }
@@ -0,0 +1,233 @@
+//! Serialized form of the types persisted with the `serde` feature.
+//!
+//! Paths and paints carry a format version, documents of newer versions are
+//! rejected. Fields added later get defaults, so older documents keep loading,
+//! and readers ignore fields they don't know. The same holds for the paint and
+//! image patterns nested in them; gradients are stored with all their fields.
+
+use crate::{
+    Align, Command, DrawPathStyle, Error, ImageFlags, LineCap, LineJoin, Paint, PaintPattern,
+    PathCommands, PathFillType, TextDecoration, Transform,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryFrom;
+
+/// Version written into every serialized path and paint
+pub(crate) const FORMAT_VERSION: u32 = 1;
+
+/// Accepts the versions this build can read. Version 1 is the first, there is
+/// nothing to migrate yet.
+fn check_version(version: u32) -> Result<(), Error> {
+    if version > FORMAT_VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct PathCommandsRepr {
+    version: u32,
+    commands: Vec<Command>,
+    fill_type: PathFillType,
+    xform: Transform,
+    xforms: Vec<Transform>,
+}
+
+impl Default for PathCommandsRepr {
+    fn default() -> Self {
+        PathCommands::default().into()
+    }
+}
+
+impl From<PathCommands> for PathCommandsRepr {
+    fn from(path: PathCommands) -> Self {
+        PathCommandsRepr {
+            version: FORMAT_VERSION,
+            commands: path.commands,
+            fill_type: path.fill_type,
+            xform: path.xform,
+            xforms: path.xforms,
+        }
+    }
+}
+
+impl TryFrom<PathCommandsRepr> for PathCommands {
+    type Error = Error;
+
+    fn try_from(repr: PathCommandsRepr) -> Result<Self, Error> {
+        check_version(repr.version)?;
+        // commands are stored transformed, the pen position is not
+        let last_position = repr
+            .commands
+            .iter()
+            .rev()
+            .find_map(|cmd| match cmd {
+                Command::MoveTo(pt) | Command::LineTo(pt) | Command::BezierTo(_, _, pt) => {
+                    Some(repr.xform.inverse().transform_point(*pt))
+                }
+                _ => None,
+            })
+            .unwrap_or_default();
+        Ok(PathCommands {
+            last_position,
+            commands: repr.commands,
+            fill_type: repr.fill_type,
+            xform: repr.xform,
+            xforms: repr.xforms,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct PaintRepr {
+    version: u32,
+    antialias: bool,
+    alpha: f32,
+    stroke: PaintPattern,
+    fill: PaintPattern,
+    stroke_width: f32,
+    line_join: LineJoin,
+    line_cap: LineCap,
+    miter_limit: f32,
+}
+
+impl Default for PaintRepr {
+    fn default() -> Self {
+        Paint::default().into()
+    }
+}
+
+impl From<Paint> for PaintRepr {
+    fn from(paint: Paint) -> Self {
+        PaintRepr {
+            version: FORMAT_VERSION,
+            antialias: paint.antialias,
+            alpha: paint.alpha,
+            stroke: paint.stroke,
+            fill: paint.fill,
+            stroke_width: paint.stroke_width,
+            line_join: paint.line_join,
+            line_cap: paint.line_cap,
+            miter_limit: paint.miter_limit,
+        }
+    }
+}
+
+impl TryFrom<PaintRepr> for Paint {
+    type Error = Error;
+
+    fn try_from(repr: PaintRepr) -> Result<Self, Error> {
+        check_version(repr.version)?;
+        Ok(Paint {
+            antialias: repr.antialias,
+            alpha: repr.alpha,
+            stroke: repr.stroke,
+            fill: repr.fill,
+            stroke_width: repr.stroke_width,
+            line_join: repr.line_join,
+            line_cap: repr.line_cap,
+            miter_limit: repr.miter_limit,
+        })
+    }
+}
+
+/// Flags are stored as their bits, unknown bits are dropped when reading
+macro_rules! serde_bitflags {
+    ($($flags:ty),*) => {
+        $(
+            impl Serialize for $flags {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    self.bits().serialize(serializer)
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $flags {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    Ok(Self::from_bits_truncate(u32::deserialize(deserializer)?))
+                }
+            }
+        )*
+    };
+}
+
+serde_bitflags!(Align, TextDecoration, ImageFlags, DrawPathStyle);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Color, Gradient, Point};
+
+    fn sample_path() -> PathCommands {
+        let mut path = PathCommands::default();
+        path.translate(10.0, 20.0);
+        path.move_to((0.0, 0.0));
+        path.line_to((5.0, 0.0));
+        path.bezier_to((5.0, 5.0), (0.0, 5.0), (0.0, 10.0));
+        path.fill_type(PathFillType::EvenOdd);
+        path
+    }
+
+    #[test]
+    fn path_round_trip() {
+        let path = sample_path();
+        let json = serde_json::to_string(&path).unwrap();
+        let mut read: PathCommands = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&read).unwrap(), json);
+        // the pen position is recovered in path coordinates
+        assert!(read.last_position.equals(Point::new(0.0, 10.0), 1e-5));
+
+        let mut path = path;
+        path.line_to((3.0, 3.0));
+        read.line_to((3.0, 3.0));
+        assert_eq!(
+            serde_json::to_string(&read).unwrap(),
+            serde_json::to_string(&path).unwrap()
+        );
+    }
+
+    #[test]
+    fn paint_round_trip() {
+        let paint = Paint {
+            alpha: 0.5,
+            fill: Gradient::Linear {
+                start: Point::new(0.0, 0.0),
+                end: Point::new(0.0, 100.0),
+                start_color: Color::rgb(1.0, 0.0, 0.0),
+                end_color: Color::rgb(0.0, 0.0, 1.0),
+            }
+            .into(),
+            line_cap: LineCap::Round,
+            ..Paint::default()
+        };
+        let json = serde_json::to_string(&paint).unwrap();
+        let read: Paint = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&read).unwrap(), json);
+        assert_eq!(read.fill.outer_color, Color::rgb(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn missing_and_unknown_fields() {
+        let paint: Paint =
+            serde_json::from_str(r#"{"alpha":0.5,"fill":{"feather":2.0},"added_later":1}"#)
+                .unwrap();
+        assert_eq!(paint.alpha, 0.5);
+        assert_eq!(paint.stroke_width, Paint::default().stroke_width);
+        assert_eq!(paint.fill.feather, 2.0);
+        assert_eq!(paint.fill.inner_color, PaintPattern::default().inner_color);
+        assert!(paint.fill.image.is_none());
+
+        let path: PathCommands = serde_json::from_str(r#"{"version":1}"#).unwrap();
+        assert!(path.commands.is_empty());
+    }
+
+    #[test]
+    fn newer_version_rejected() {
+        let version = FORMAT_VERSION + 1;
+        let json = format!(r#"{{"version":{}}}"#, version);
+        let err = serde_json::from_str::<Paint>(&json).unwrap_err();
+        assert!(err.to_string().contains("format version"), "{}", err);
+        assert!(serde_json::from_str::<PathCommands>(&json).is_err());
+    }
+}
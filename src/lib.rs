@@ -3,7 +3,10 @@ extern crate bitflags;
 
 mod color;
 mod context;
+mod error;
 mod fonts;
+#[cfg(feature = "serde")]
+mod format;
 mod math;
 mod paint;
 mod path;
@@ -11,6 +14,7 @@ mod renderer;
 
 pub use color::*;
 pub use context::*;
+pub use error::*;
 pub use fonts::*;
 pub use math::*;
 pub use paint::*;
@@ -48,4 +52,64 @@ pub mod utils {
             a: color.a,
         }
     }
+
+    /// Appends the triangles of a fan as a list of vertex indices
+    pub fn triangle_fan_indices(offset: u32, count: u32, indices: &mut Vec<u32>) {
+        for i in 1..count.saturating_sub(1) {
+            indices.extend([offset, offset + i, offset + i + 1]);
+        }
+    }
+
+    /// Appends the triangles of a strip as a list of vertex indices, with the
+    /// winding of the strip
+    pub fn triangle_strip_indices(offset: u32, count: u32, indices: &mut Vec<u32>) {
+        for i in 0..count.saturating_sub(2) {
+            let v = offset + i;
+            if i % 2 == 0 {
+                indices.extend([v, v + 1, v + 2]);
+            } else {
+                indices.extend([v + 1, v, v + 2]);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn fan_indices() {
+            let mut indices = vec![7];
+            triangle_fan_indices(10, 5, &mut indices);
+            assert_eq!(indices, vec![7, 10, 11, 12, 10, 12, 13, 10, 13, 14]);
+
+            indices.clear();
+            triangle_fan_indices(0, 2, &mut indices);
+            triangle_fan_indices(0, 0, &mut indices);
+            assert!(indices.is_empty());
+        }
+
+        #[test]
+        fn strip_indices_keep_winding() {
+            // a zigzag strip along the x axis, all triangles counterclockwise
+            // when drawn as a strip
+            let points: Vec<(f32, f32)> = (0..6)
+                .map(|i| (i as f32, if i % 2 == 0 { 0.0 } else { 1.0 }))
+                .collect();
+            let mut indices = Vec::new();
+            triangle_strip_indices(0, points.len() as u32, &mut indices);
+            assert_eq!(indices.len(), 4 * 3);
+
+            let winding = |tri: &[u32]| {
+                let [a, b, c] = [0, 1, 2].map(|i| points[tri[i] as usize]);
+                ((b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)).signum()
+            };
+            let first = winding(&indices[..3]);
+            assert!(indices.chunks(3).all(|tri| winding(tri) == first));
+
+            indices.clear();
+            triangle_strip_indices(3, 2, &mut indices);
+            assert!(indices.is_empty());
+        }
+    }
 }
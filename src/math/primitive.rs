@@ -3,6 +3,7 @@ use num_traits::AsPrimitive;
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Extent {
     pub width: f32,
     pub height: f32,
@@ -21,6 +22,7 @@ impl<T: AsPrimitive<f32>> From<(T, T)> for Extent {
 }
 
 #[derive(Debug, Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Rect {
     pub xy: Point,
     pub size: Extent,
@@ -79,6 +81,7 @@ impl<T: AsPrimitive<f32>> From<(T, T, T, T)> for Rect {
 }
 
 #[derive(Debug, Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Bounds {
     pub min: Point,
     pub max: Point,
@@ -108,4 +111,29 @@ impl Bounds {
     pub fn right_bottom(&self) -> Point {
         self.max
     }
+
+    /// Smallest bounds containing both
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        Bounds {
+            min: Point::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y)),
+            max: Point::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y)),
+        }
+    }
+
+    /// Whether both bounds share an area
+    pub fn intersects(&self, other: &Bounds) -> bool {
+        self.min.x < other.max.x
+            && other.min.x < self.max.x
+            && self.min.y < other.max.y
+            && other.min.y < self.max.y
+    }
+}
+
+impl From<Rect> for Bounds {
+    fn from(rect: Rect) -> Self {
+        Bounds {
+            min: rect.xy,
+            max: rect.xy.offset(rect.size.width, rect.size.height),
+        }
+    }
 }
@@ -4,6 +4,7 @@ use std::ops::{Mul, MulAssign};
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Transform(pub [f32; 6]);
 
 impl Transform {
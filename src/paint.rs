@@ -4,6 +4,11 @@ use super::ImageId;
 use crate::{Color, Extent, Point, Rect, Transform};
 
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct PaintPattern {
     pub xform: Transform,
     pub extent: Extent,
@@ -15,6 +20,7 @@ pub struct PaintPattern {
 }
 
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Gradient {
     Linear {
         start: Point,
@@ -39,6 +45,11 @@ pub enum Gradient {
 }
 
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct ImagePattern {
     pub center: Point,
     pub size: Extent,
@@ -47,6 +58,18 @@ pub struct ImagePattern {
     pub alpha: f32,
 }
 
+impl Default for ImagePattern {
+    fn default() -> Self {
+        ImagePattern {
+            center: Point::default(),
+            size: Extent::default(),
+            angle: 0.0,
+            img: 0,
+            alpha: 1.0,
+        }
+    }
+}
+
 impl From<Gradient> for PaintPattern {
     fn from(grad: Gradient) -> Self {
         match grad {
@@ -151,6 +174,13 @@ impl From<ImagePattern> for PaintPattern {
     }
 }
 
+/// Transparent solid color
+impl Default for PaintPattern {
+    fn default() -> Self {
+        Color::rgba(0.0, 0.0, 0.0, 0.0).into()
+    }
+}
+
 impl<T: Into<Color> + Clone> From<T> for PaintPattern {
     fn from(color: T) -> Self {
         PaintPattern {
@@ -166,6 +196,7 @@ impl<T: Into<Color> + Clone> From<T> for PaintPattern {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LineJoin {
     Miter,
     Round,
@@ -173,6 +204,7 @@ pub enum LineJoin {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LineCap {
     Butt,
     Round,
@@ -180,6 +212,14 @@ pub enum LineCap {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        into = "crate::format::PaintRepr",
+        try_from = "crate::format::PaintRepr"
+    )
+)]
 pub struct Paint {
     pub antialias: bool,
     pub alpha: f32,
@@ -0,0 +1,684 @@
+use super::cache::PathCache;
+use super::{PathCommands, PathFillType, WindingSolidity, DIST_TOL, TESS_TOL};
+use crate::{Point, Vector2D};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+/// Set operation combining the filled areas of two paths
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum BooleanOp {
+    Union,
+    Intersection,
+    /// Area of the first path not covered by the second
+    Difference,
+    Xor,
+}
+
+impl BooleanOp {
+    #[inline]
+    fn apply(self, a: bool, b: bool) -> bool {
+        match self {
+            BooleanOp::Union => a || b,
+            BooleanOp::Intersection => a && b,
+            BooleanOp::Difference => a && !b,
+            BooleanOp::Xor => a != b,
+        }
+    }
+}
+
+impl PathCommands {
+    /// Combine the filled area of this path with `other`, honouring the fill type
+    /// and winding of both. Curves are flattened, so the result contains only
+    /// straight segments, one closed sub-path per outline or hole.
+    pub fn boolean(&self, other: &PathCommands, op: BooleanOp) -> PathCommands {
+        let operands = [Operand::new(self, TESS_TOL), Operand::new(other, TESS_TOL)];
+        boolean_polygons(&operands, op, DIST_TOL)
+    }
+
+    pub fn union(&self, other: &PathCommands) -> PathCommands {
+        self.boolean(other, BooleanOp::Union)
+    }
+
+    pub fn intersection(&self, other: &PathCommands) -> PathCommands {
+        self.boolean(other, BooleanOp::Intersection)
+    }
+
+    pub fn difference(&self, other: &PathCommands) -> PathCommands {
+        self.boolean(other, BooleanOp::Difference)
+    }
+
+    pub fn xor(&self, other: &PathCommands) -> PathCommands {
+        self.boolean(other, BooleanOp::Xor)
+    }
+}
+
+/// Flattened rings of one input path, every sub-path treated as closed like a fill
+pub(crate) struct Operand {
+    pub rings: Vec<Vec<Point>>,
+    pub fill_type: PathFillType,
+}
+
+impl Operand {
+    pub fn new(path: &PathCommands, tess_tol: f32) -> Operand {
+        let mut cache = PathCache::default();
+        cache.flatten_paths(&path.commands, DIST_TOL, tess_tol);
+        Operand {
+            rings: cache
+                .paths
+                .iter()
+                .filter(|info| info.count > 2)
+                .map(|info| cache.path_points(info).collect())
+                .collect(),
+            fill_type: path.fill_type,
+        }
+    }
+
+    #[inline]
+    fn inside(&self, winding: i32) -> bool {
+        match self.fill_type {
+            PathFillType::Winding => winding != 0,
+            PathFillType::EvenOdd => winding % 2 != 0,
+        }
+    }
+}
+
+struct Segment {
+    a: Point,
+    b: Point,
+    operand: usize,
+}
+
+impl Segment {
+    #[inline]
+    fn min_x(&self) -> f32 {
+        self.a.x.min(self.b.x)
+    }
+}
+
+struct Edge {
+    from: usize,
+    to: usize,
+    operand: usize,
+}
+
+/// Split every segment where another one crosses or touches it, weld the pieces
+/// into a shared vertex set, keep the edges separating result inside from outside
+/// and link them back into rings.
+pub(crate) fn boolean_polygons(operands: &[Operand; 2], op: BooleanOp, eps: f32) -> PathCommands {
+    let mut segments = Vec::new();
+    for (operand, input) in operands.iter().enumerate() {
+        for ring in &input.rings {
+            for i in 0..ring.len() {
+                let a = ring[i];
+                let b = ring[(i + 1) % ring.len()];
+                if !a.equals(b, eps) {
+                    segments.push(Segment { a, b, operand });
+                }
+            }
+        }
+    }
+
+    // sweep along x, only segments with overlapping x extents are paired
+    let mut order: Vec<usize> = (0..segments.len()).collect();
+    order.sort_by(|&i, &j| {
+        let (i, j) = (&segments[i], &segments[j]);
+        i.min_x().partial_cmp(&j.min_x()).unwrap()
+    });
+    let mut splits: Vec<Vec<(f32, Point)>> = segments.iter().map(|_| Vec::new()).collect();
+    for (n, &i) in order.iter().enumerate() {
+        let max_x = segments[i].a.x.max(segments[i].b.x) + eps;
+        for &j in order[n + 1..]
+            .iter()
+            .take_while(|&&j| segments[j].min_x() <= max_x)
+        {
+            let (i, j) = (i.min(j), i.max(j));
+            let (head, tail) = splits.split_at_mut(j);
+            split_pair(&segments[i], &segments[j], eps, &mut head[i], &mut tail[0]);
+        }
+    }
+
+    let mut vertices = Vertices::new(eps);
+    let mut edges = Vec::new();
+    for (seg, cuts) in segments.iter().zip(splits.iter_mut()) {
+        cuts.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let mut from = vertices.insert(seg.a);
+        for pt in cuts.iter().map(|cut| cut.1).chain(Some(seg.b)) {
+            let to = vertices.insert(pt);
+            if to != from {
+                edges.push(Edge {
+                    from,
+                    to,
+                    operand: seg.operand,
+                });
+                from = to;
+            }
+        }
+    }
+
+    // coincident edges from either operand are classified once, as a group
+    let mut groups: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    for (idx, edge) in edges.iter().enumerate() {
+        let key = (edge.from.min(edge.to), edge.from.max(edge.to));
+        groups.entry(key).or_default().push(idx);
+    }
+
+    let pts = &vertices.points;
+    let mut classes: Vec<Group> = groups
+        .iter()
+        .map(|(&(u, v), group)| {
+            // orient the group upwards, or rightwards when horizontal, so that
+            // the side reached by a +x ray from the midpoint is the right one
+            let (u, v) = if pts[u].y < pts[v].y || (pts[u].y == pts[v].y && pts[u].x < pts[v].x) {
+                (u, v)
+            } else {
+                (v, u)
+            };
+            let mut delta = [0; 2];
+            for edge in group.iter().map(|&idx| &edges[idx]) {
+                delta[edge.operand] += if edge.from == u { 1 } else { -1 };
+            }
+            Group { u, v, delta }
+        })
+        .collect();
+    classes.sort_unstable_by_key(|group| (group.u, group.v));
+
+    let mut kept = Vec::new();
+    for (group, base) in classes.iter().zip(sweep_windings(&classes, pts)) {
+        let horizontal = group.horizontal(pts);
+        let mut left = [0; 2];
+        let mut right = [0; 2];
+        for operand in 0..2 {
+            if horizontal {
+                left[operand] = base[operand];
+                right[operand] = base[operand] - group.delta[operand];
+            } else {
+                left[operand] = base[operand] + group.delta[operand];
+                right[operand] = base[operand];
+            }
+        }
+
+        let in_left = op.apply(operands[0].inside(left[0]), operands[1].inside(left[1]));
+        let in_right = op.apply(operands[0].inside(right[0]), operands[1].inside(right[1]));
+        if in_left != in_right {
+            // result inside kept on the left of each edge
+            kept.push(if in_left {
+                (group.u, group.v)
+            } else {
+                (group.v, group.u)
+            });
+        }
+    }
+
+    let mut result = PathCommands::default();
+    for ring in link_rings(&kept, pts) {
+        let ring = simplify_ring(ring, eps);
+        if ring.len() < 3 {
+            continue;
+        }
+        let area = ring_area(&ring);
+        if area.abs() <= eps * eps {
+            continue;
+        }
+        // emitted in the order flatten_paths expects for its solidity
+        result.move_to(ring[ring.len() - 1]);
+        for pt in ring.iter().rev().skip(1) {
+            result.line_to(*pt);
+        }
+        result.close_path();
+        result.path_winding(if area > 0.0 {
+            WindingSolidity::Solid
+        } else {
+            WindingSolidity::Hole
+        });
+    }
+    result
+}
+
+#[inline]
+pub(super) fn cross(a: Point, b: Point) -> f32 {
+    a.x * b.y - a.y * b.x
+}
+
+/// Parameter of `p` along `a`-`b` when it lies on the segment away from its ends
+fn interior_param(p: Point, a: Point, b: Point, eps: f32) -> Option<f32> {
+    if p.equals(a, eps) || p.equals(b, eps) || p.dist_pt_seg(a, b) >= eps * eps {
+        return None;
+    }
+    let d = &b - &a;
+    Some((&p - &a).dot(&d) / d.dot(&d))
+}
+
+fn split_pair(
+    s0: &Segment,
+    s1: &Segment,
+    eps: f32,
+    out0: &mut Vec<(f32, Point)>,
+    out1: &mut Vec<(f32, Point)>,
+) {
+    if s0.a.x.max(s0.b.x) + eps < s1.a.x.min(s1.b.x)
+        || s1.a.x.max(s1.b.x) + eps < s0.a.x.min(s0.b.x)
+        || s0.a.y.max(s0.b.y) + eps < s1.a.y.min(s1.b.y)
+        || s1.a.y.max(s1.b.y) + eps < s0.a.y.min(s0.b.y)
+    {
+        return;
+    }
+
+    // end points touching the other segment, this also covers collinear overlaps
+    let mut touching = false;
+    for &p in &[s1.a, s1.b] {
+        if let Some(t) = interior_param(p, s0.a, s0.b, eps) {
+            out0.push((t, p));
+            touching = true;
+        }
+    }
+    for &p in &[s0.a, s0.b] {
+        if let Some(t) = interior_param(p, s1.a, s1.b, eps) {
+            out1.push((t, p));
+            touching = true;
+        }
+    }
+    let shared = [s1.a, s1.b]
+        .iter()
+        .any(|p| p.equals(s0.a, eps) || p.equals(s0.b, eps));
+    if touching || shared {
+        return;
+    }
+
+    let r = &s0.b - &s0.a;
+    let s = &s1.b - &s1.a;
+    let denom = cross(r, s);
+    if denom.abs() <= f32::EPSILON * r.dot(&r).max(s.dot(&s)) {
+        return;
+    }
+    let qp = &s1.a - &s0.a;
+    let t = cross(qp, s) / denom;
+    let u = cross(qp, r) / denom;
+    if t > 0.0 && t < 1.0 && u > 0.0 && u < 1.0 {
+        let p = Point::new(s0.a.x + r.x * t, s0.a.y + r.y * t);
+        out0.push((t, p));
+        out1.push((u, p));
+    }
+}
+
+/// Vertex set welding points closer than `eps` together
+struct Vertices {
+    eps: f32,
+    points: Vec<Point>,
+    grid: HashMap<(i64, i64), Vec<usize>>,
+}
+
+impl Vertices {
+    fn new(eps: f32) -> Vertices {
+        Vertices {
+            eps,
+            points: Vec::new(),
+            grid: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, pt: Point) -> usize {
+        let cx = (pt.x / self.eps).floor() as i64;
+        let cy = (pt.y / self.eps).floor() as i64;
+        for x in cx - 1..=cx + 1 {
+            for y in cy - 1..=cy + 1 {
+                if let Some(cell) = self.grid.get(&(x, y)) {
+                    if let Some(&idx) = cell
+                        .iter()
+                        .find(|&&idx| self.points[idx].equals(pt, self.eps))
+                    {
+                        return idx;
+                    }
+                }
+            }
+        }
+        let idx = self.points.len();
+        self.points.push(pt);
+        self.grid.entry((cx, cy)).or_default().push(idx);
+        idx
+    }
+}
+
+/// Coincident edges between two welded vertices, `u` below `v` or left of
+/// it when horizontal
+struct Group {
+    u: usize,
+    v: usize,
+    /// Winding change of each operand when crossing the group from right to left
+    delta: [i32; 2],
+}
+
+impl Group {
+    #[inline]
+    fn horizontal(&self, pts: &[Point]) -> bool {
+        pts[self.u].y == pts[self.v].y
+    }
+
+    /// Position of a non-horizontal group at height `y`
+    #[inline]
+    fn x_at(&self, pts: &[Point], y: f32) -> f32 {
+        let (a, b) = (pts[self.u], pts[self.v]);
+        a.x + (b.x - a.x) * (y - a.y) / (b.y - a.y)
+    }
+}
+
+/// Winding numbers of both operands next to every group, on its right or, for
+/// horizontal groups, just above it towards +y
+///
+/// Groups don't cross, so between two consecutive vertex heights the ones
+/// spanning the band keep their order along x and each winding is a suffix sum
+/// over that order. This counts edges with the same half-open rule as a +x ray.
+fn sweep_windings(groups: &[Group], pts: &[Point]) -> Vec<[i32; 2]> {
+    let mut ys: Vec<f32> = groups
+        .iter()
+        .flat_map(|group| [pts[group.u].y, pts[group.v].y])
+        .collect();
+    ys.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    ys.dedup();
+
+    let mut starting = vec![Vec::new(); ys.len()];
+    let mut horizontal = vec![Vec::new(); ys.len()];
+    for (idx, group) in groups.iter().enumerate() {
+        let band = ys.partition_point(|&y| y < pts[group.u].y);
+        if group.horizontal(pts) {
+            horizontal[band].push(idx);
+        } else {
+            starting[band].push(idx);
+        }
+    }
+
+    let mut windings = vec![[0; 2]; groups.len()];
+    let mut active: Vec<(usize, f32)> = Vec::new();
+    let mut suffix = Vec::new();
+    for band in 0..ys.len().saturating_sub(1) {
+        let (y0, y1) = (ys[band], ys[band + 1]);
+        active.retain(|&(idx, _)| pts[groups[idx].v].y > y0);
+        active.extend(starting[band].iter().map(|&idx| (idx, 0.0)));
+        let mid = (y0 + y1) * 0.5;
+        for (idx, x) in &mut active {
+            *x = groups[*idx].x_at(pts, mid);
+        }
+        active.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+        suffix.clear();
+        suffix.resize(active.len() + 1, [0; 2]);
+        for (n, &(idx, _)) in active.iter().enumerate().rev() {
+            windings[idx] = suffix[n + 1];
+            let delta = groups[idx].delta;
+            suffix[n] = [suffix[n + 1][0] + delta[0], suffix[n + 1][1] + delta[1]];
+        }
+
+        for &idx in &horizontal[band] {
+            let group = &groups[idx];
+            let x = (pts[group.u].x + pts[group.v].x) * 0.5;
+            let first = active.partition_point(|&(other, _)| groups[other].x_at(pts, y0) < x);
+            windings[idx] = suffix[first];
+        }
+    }
+    windings
+}
+
+/// Chain directed edges into closed rings. At shared vertices the edge turning
+/// most to the left is taken, which keeps each ring around a single face.
+fn link_rings(edges: &[(usize, usize)], pts: &[Point]) -> Vec<Vec<Point>> {
+    let mut outgoing: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (idx, &(from, _)) in edges.iter().enumerate() {
+        outgoing.entry(from).or_default().push(idx);
+    }
+    let angle = |from: usize, to: usize| {
+        let d = &pts[to] - &pts[from];
+        d.y.atan2(d.x)
+    };
+
+    let mut used = vec![false; edges.len()];
+    let mut rings = Vec::new();
+    for first in 0..edges.len() {
+        if used[first] {
+            continue;
+        }
+        used[first] = true;
+        let start = edges[first].0;
+        let mut ring = vec![pts[start]];
+        let mut current = first;
+        loop {
+            let (from, to) = edges[current];
+            if to == start {
+                break;
+            }
+            ring.push(pts[to]);
+            let back = angle(to, from);
+            let next = outgoing.get(&to).and_then(|candidates| {
+                candidates
+                    .iter()
+                    .copied()
+                    .filter(|&idx| !used[idx])
+                    .map(|idx| {
+                        // clockwise sweep from the way we came in
+                        let mut sweep = back - angle(to, edges[idx].1);
+                        while sweep <= 0.0 {
+                            sweep += 2.0 * PI;
+                        }
+                        (idx, sweep)
+                    })
+                    .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+                    .map(|(idx, _)| idx)
+            });
+            match next {
+                Some(idx) => {
+                    used[idx] = true;
+                    current = idx;
+                }
+                None => break,
+            }
+        }
+        rings.push(ring);
+    }
+    rings
+}
+
+/// Drop points splitting a straight run, left over from intersections
+fn simplify_ring(mut ring: Vec<Point>, eps: f32) -> Vec<Point> {
+    let mut i = 0;
+    while ring.len() > 2 && i < ring.len() {
+        let prev = ring[(i + ring.len() - 1) % ring.len()];
+        let next = ring[(i + 1) % ring.len()];
+        let pt = ring[i];
+        let d0 = &pt - &prev;
+        let d1 = &next - &pt;
+        let straight = d0.dot(&d1) > 0.0 && pt.dist_pt_seg(prev, next) < eps * eps;
+        if straight {
+            ring.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+    ring
+}
+
+/// Signed area, positive when the interior is on the left of the edges
+pub(crate) fn ring_area(ring: &[Point]) -> f32 {
+    let mut area = 0.0;
+    for i in 0..ring.len() {
+        area += cross(ring[i], ring[(i + 1) % ring.len()]);
+    }
+    area * 0.5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PathDir;
+
+    fn rect(x: f32, y: f32, w: f32, h: f32) -> PathCommands {
+        let mut path = PathCommands::default();
+        path.rect((x, y, w, h));
+        path
+    }
+
+    /// Filled area of a boolean result, holes subtracted
+    fn area(path: &PathCommands) -> f32 {
+        let operand = Operand::new(path, TESS_TOL);
+        operand
+            .rings
+            .iter()
+            .map(|ring| ring_area(ring))
+            .sum::<f32>()
+            .abs()
+    }
+
+    fn contains(path: &PathCommands, p: Point) -> bool {
+        let operand = Operand::new(path, TESS_TOL);
+        let mut w = 0;
+        for ring in &operand.rings {
+            for i in 0..ring.len() {
+                let a = ring[i];
+                let b = ring[(i + 1) % ring.len()];
+                if a.y <= p.y {
+                    if b.y > p.y && cross(&b - &a, &p - &a) > 0.0 {
+                        w += 1;
+                    }
+                } else if b.y <= p.y && cross(&b - &a, &p - &a) < 0.0 {
+                    w -= 1;
+                }
+            }
+        }
+        operand.inside(w)
+    }
+
+    fn assert_area(path: &PathCommands, expected: f32) {
+        let actual = area(path);
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "area {} instead of {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn overlapping_rects() {
+        let a = rect(0.0, 0.0, 10.0, 10.0);
+        let b = rect(5.0, 5.0, 10.0, 10.0);
+        assert_area(&a.union(&b), 175.0);
+        assert_area(&a.intersection(&b), 25.0);
+        assert_area(&a.difference(&b), 75.0);
+        assert_area(&a.xor(&b), 150.0);
+        assert_area(&b.union(&a), 175.0);
+    }
+
+    #[test]
+    fn shared_edge() {
+        let a = rect(0.0, 0.0, 10.0, 10.0);
+        let b = rect(10.0, 0.0, 10.0, 10.0);
+        let union = a.union(&b);
+        assert_area(&union, 200.0);
+        // the shared edge is dropped, leaving a single outline
+        assert_eq!(Operand::new(&union, TESS_TOL).rings.len(), 1);
+        assert!(contains(&union, Point::new(10.0, 5.0)));
+        assert_area(&a.intersection(&b), 0.0);
+        assert_area(&a.difference(&b), 100.0);
+    }
+
+    #[test]
+    fn collinear_overlap() {
+        let a = rect(0.0, 0.0, 10.0, 10.0);
+        let b = rect(10.0, 5.0, 10.0, 10.0);
+        let union = a.union(&b);
+        assert_area(&union, 200.0);
+        assert_eq!(Operand::new(&union, TESS_TOL).rings.len(), 1);
+        assert!(contains(&union, Point::new(10.0, 7.5)));
+        assert!(!contains(&union, Point::new(15.0, 2.5)));
+        assert!(!contains(&union, Point::new(5.0, 12.5)));
+    }
+
+    #[test]
+    fn hole() {
+        let a = rect(0.0, 0.0, 30.0, 30.0);
+        let b = rect(10.0, 10.0, 10.0, 10.0);
+        let difference = a.difference(&b);
+        assert_area(&difference, 800.0);
+        assert!(!contains(&difference, Point::new(15.0, 15.0)));
+        assert!(contains(&difference, Point::new(5.0, 15.0)));
+        assert_area(&b.difference(&a), 0.0);
+        // filling the hole back in gives the square
+        assert_area(&difference.union(&b), 900.0);
+    }
+
+    #[test]
+    fn even_odd_vs_winding() {
+        // two nested squares with the same orientation
+        let mut nested = rect(0.0, 0.0, 30.0, 30.0);
+        nested.rect((10.0, 10.0, 10.0, 10.0));
+        let other = rect(100.0, 100.0, 1.0, 1.0);
+
+        nested.fill_type(PathFillType::Winding);
+        let winding = nested.union(&other);
+        assert_area(&winding, 901.0);
+        assert!(contains(&winding, Point::new(15.0, 15.0)));
+
+        nested.fill_type(PathFillType::EvenOdd);
+        let even_odd = nested.union(&other);
+        assert_area(&even_odd, 801.0);
+        assert!(!contains(&even_odd, Point::new(15.0, 15.0)));
+    }
+
+    fn circle(x: f32, y: f32, r: f32) -> PathCommands {
+        let mut path = PathCommands::default();
+        path.circle((x, y), r);
+        path
+    }
+
+    fn assert_near(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "{} instead of {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn circle_minus_circle() {
+        let outer = circle(0.0, 0.0, 40.0);
+        let inner = circle(0.0, 0.0, 25.0);
+        let ring = outer.difference(&inner);
+        // exactly the flattened circles, close to the true ones
+        assert_area(&ring, area(&outer) - area(&inner));
+        assert_near(
+            area(&ring),
+            PI * (40.0 * 40.0 - 25.0 * 25.0),
+            0.01 * PI * 975.0,
+        );
+        assert_eq!(Operand::new(&ring, TESS_TOL).rings.len(), 2);
+        assert!(contains(&ring, Point::new(32.0, 0.0)));
+        assert!(!contains(&ring, Point::new(0.0, 0.0)));
+        assert!(!contains(&ring, Point::new(0.0, 45.0)));
+    }
+
+    #[test]
+    fn overlapping_circles() {
+        let a = circle(0.0, 0.0, 20.0);
+        let b = circle(20.0, 0.0, 20.0);
+        let union = area(&a.union(&b));
+        let intersection = area(&a.intersection(&b));
+        assert_near(union + intersection, area(&a) + area(&b), 1e-2);
+        assert_near(area(&a.xor(&b)), union - intersection, 1e-2);
+        // the lens of two circles a radius apart, made smaller by the flat
+        // chords of both arcs
+        let lens = 2.0 * 400.0 * (PI / 3.0) - 400.0 * 3f32.sqrt() / 2.0;
+        assert_near(intersection, lens, 0.02 * lens);
+    }
+
+    #[test]
+    fn arc_and_rect() {
+        // a half disc on top of a square, sharing its top edge
+        let mut dome = PathCommands::default();
+        dome.arc((10.0, 0.0), 10.0, PI, 0.0, PathDir::CW);
+        dome.close_path();
+        let square = rect(0.0, 0.0, 20.0, 20.0);
+        let union = dome.union(&square);
+        assert_area(&union, area(&dome) + 400.0);
+        assert_eq!(Operand::new(&union, TESS_TOL).rings.len(), 1);
+        assert!(contains(&union, Point::new(10.0, -5.0)));
+        assert_area(&dome.intersection(&square), 0.0);
+    }
+}
@@ -270,8 +270,8 @@ pub(super) unsafe fn butt_cap_end(
     u0: f32,
     u1: f32,
 ) -> *mut Vertex {
-    let px = p.xy.x - dx * d;
-    let py = p.xy.y - dy * d;
+    let px = p.xy.x + dx * d;
+    let py = p.xy.y + dy * d;
     let dlx = dy;
     let dly = -dx;
 
@@ -146,32 +146,7 @@ impl PathCache {
         if self.paths.len() != 0 {
             return;
         }
-        for cmd in commands {
-            match cmd {
-                Command::MoveTo(pt) => {
-                    self.add_path();
-                    self.add_point(*pt, PointFlags::PT_CORNER, dist_tol);
-                }
-                Command::LineTo(pt) => {
-                    self.add_point(*pt, PointFlags::PT_CORNER, dist_tol);
-                }
-                Command::BezierTo(cp1, cp2, pt) => {
-                    if let Some(last) = self.points.last().map(|pt| *pt) {
-                        self.tesselate_bezier(
-                            last.xy,
-                            *cp1,
-                            *cp2,
-                            *pt,
-                            0,
-                            PointFlags::PT_CORNER,
-                            tess_tol,
-                        );
-                    }
-                }
-                Command::Close => self.close_path(),
-                Command::Winding(solidity) => self.path_solidity(*solidity),
-            }
-        }
+        self.flatten_commands(commands, dist_tol, tess_tol);
 
         self.bounds.min = Point::new(std::f32::MAX, std::f32::MAX);
         self.bounds.max = Point::new(std::f32::MIN, std::f32::MIN);
@@ -218,6 +193,37 @@ impl PathCache {
         }
     }
 
+    /// Flatten commands into points in the order they were given, before the
+    /// winding of each sub-path is fixed up
+    pub(crate) fn flatten_commands(&mut self, commands: &[Command], dist_tol: f32, tess_tol: f32) {
+        for cmd in commands {
+            match cmd {
+                Command::MoveTo(pt) => {
+                    self.add_path();
+                    self.add_point(*pt, PointFlags::PT_CORNER, dist_tol);
+                }
+                Command::LineTo(pt) => {
+                    self.add_point(*pt, PointFlags::PT_CORNER, dist_tol);
+                }
+                Command::BezierTo(cp1, cp2, pt) => {
+                    if let Some(last) = self.points.last().map(|pt| *pt) {
+                        self.tesselate_bezier(
+                            last.xy,
+                            *cp1,
+                            *cp2,
+                            *pt,
+                            0,
+                            PointFlags::PT_CORNER,
+                            tess_tol,
+                        );
+                    }
+                }
+                Command::Close => self.close_path(),
+                Command::Winding(solidity) => self.path_solidity(*solidity),
+            }
+        }
+    }
+
     fn calculate_joins(&mut self, w: f32, line_join: LineJoin, miter_limit: f32) {
         let mut iw = 0.0;
         if w > 0.0 {
@@ -734,7 +740,7 @@ fn poly_reverse(pts: &mut [VPoint]) {
     }
 }
 
-fn curve_divs(r: f32, arc: f32, tess_tol: f32) -> usize {
+pub(crate) fn curve_divs(r: f32, arc: f32, tess_tol: f32) -> usize {
     let da = (r / (r + tess_tol)).acos() * 2.0;
     ((arc / da).ceil() as i32).max(2) as usize
 }
@@ -5,6 +5,8 @@ use crate::{Bounds, PathDir, Point};
 mod cap_join;
 mod draw_path;
 
+pub(crate) use draw_path::curve_divs;
+
 bitflags! {
     #[derive(Default)]
     struct PointFlags: u32 {
@@ -32,6 +34,15 @@ pub(crate) struct PathCache {
     pub(crate) bounds: Bounds,
 }
 
+impl PathCache {
+    /// Flattened points of one sub-path, in the winding order used for rendering
+    pub(crate) fn path_points<'a>(&'a self, path: &PathInfo) -> impl Iterator<Item = Point> + 'a {
+        self.points[path.first..path.first + path.count]
+            .iter()
+            .map(|pt| pt.xy)
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Default, bytemuck::Zeroable, bytemuck::Pod)]
 pub struct Vertex {
@@ -95,3 +106,35 @@ impl PathSlice {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Command, LineCap, LineJoin};
+
+    /// Horizontal extent of the stroke vertices of a line from 0 to 100
+    fn stroke_extent(cap: LineCap, fringe: f32) -> (f32, f32) {
+        let mut cache = PathCache::default();
+        let commands = [
+            Command::MoveTo(Point::new(0.0, 0.0)),
+            Command::LineTo(Point::new(100.0, 0.0)),
+        ];
+        cache.flatten_paths(&commands, 0.01, 0.25);
+        let mut slices = Vec::new();
+        cache.expand_stroke(5.0, fringe, cap, LineJoin::Miter, 10.0, 0.25, &mut slices);
+        let stroke = slices[0].get_stroke();
+        cache.vertices[stroke.offset..stroke.offset + stroke.count]
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), v| {
+                (min.min(v.x), max.max(v.x))
+            })
+    }
+
+    #[test]
+    fn square_caps_extend_both_ends() {
+        assert_eq!(stroke_extent(LineCap::Butt, 0.0), (0.0, 100.0));
+        assert_eq!(stroke_extent(LineCap::Square, 0.0), (-5.0, 105.0));
+        // the fringe reaches half a fringe past the cap
+        assert_eq!(stroke_extent(LineCap::Square, 1.0), (-5.5, 105.5));
+    }
+}
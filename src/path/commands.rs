@@ -8,6 +8,7 @@ pub const KAPPA90: f32 = 0.5522847493;
 pub const PI: f32 = std::f32::consts::PI;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PathDir {
     #[default]
     CCW,
@@ -21,6 +22,7 @@ pub enum WindingSolidity {
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PathFillType {
     Winding,
     EvenOdd,
@@ -36,6 +38,7 @@ impl Into<PathDir> for WindingSolidity {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) enum Command {
     MoveTo(Point),
     LineTo(Point),
@@ -45,6 +48,14 @@ pub(crate) enum Command {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        into = "crate::format::PathCommandsRepr",
+        try_from = "crate::format::PathCommandsRepr"
+    )
+)]
 pub struct PathCommands {
     pub(crate) last_position: Point,
     pub(crate) commands: Vec<Command>,
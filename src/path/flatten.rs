@@ -0,0 +1,97 @@
+use super::cache::PathCache;
+use super::{PathCommands, PathDir};
+use crate::Point;
+
+/// One flattened sub-path
+#[derive(Debug, Clone)]
+pub struct FlattenedPath {
+    pub points: Vec<Point>,
+    pub closed: bool,
+    /// Solid sub-paths are CCW, holes CW, as they are filled
+    pub winding: PathDir,
+}
+
+/// Iterator over the sub-paths of [`PathCommands::flatten`]
+pub struct Flatten {
+    cache: PathCache,
+    next: usize,
+}
+
+impl Iterator for Flatten {
+    type Item = FlattenedPath;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let info = *self.cache.paths.get(self.next)?;
+        self.next += 1;
+        Some(FlattenedPath {
+            points: self.cache.path_points(&info).collect(),
+            closed: info.closed,
+            winding: info.windding,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.cache.paths.len() - self.next;
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for Flatten {}
+
+impl PathCommands {
+    /// The polylines this path is rendered with. A context flattens with a
+    /// `tolerance` of `0.25 / device_pixel_ratio`, in path units.
+    pub fn flatten(&self, tolerance: f32) -> Flatten {
+        let mut cache = PathCache::default();
+        // same ratio between the two tolerances as the context keeps
+        cache.flatten_paths(&self.commands, tolerance * 0.04, tolerance);
+        Flatten { cache, next: 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sub_paths() {
+        let mut path = PathCommands::default();
+        path.rect((0.0, 0.0, 10.0, 10.0));
+        path.move_to((20.0, 0.0));
+        path.line_to((30.0, 5.0));
+
+        let mut flatten = path.flatten(0.25);
+        assert_eq!(flatten.len(), 2);
+        let rect = flatten.next().unwrap();
+        assert!(rect.closed);
+        assert_eq!(rect.points.len(), 4);
+        let line = flatten.next().unwrap();
+        assert!(!line.closed);
+        assert_eq!(line.points.len(), 2);
+        assert!(flatten.next().is_none());
+    }
+
+    #[test]
+    fn tolerance() {
+        let mut path = PathCommands::default();
+        path.circle((0.0, 0.0), 50.0);
+        let coarse = path.flatten(1.0).next().unwrap();
+        let fine = path.flatten(0.05).next().unwrap();
+        assert!(fine.points.len() > coarse.points.len());
+        for pt in coarse.points.iter().chain(&fine.points) {
+            let radius = (pt.x * pt.x + pt.y * pt.y).sqrt();
+            // the bezier circle itself is off by up to 0.03%
+            assert!((radius - 50.0).abs() < 0.02, "{}", radius);
+        }
+    }
+
+    #[test]
+    fn holes_wind_clockwise() {
+        let mut path = PathCommands::default();
+        path.rect((0.0, 0.0, 30.0, 30.0));
+        path.rect((10.0, 10.0, 10.0, 10.0));
+        path.path_winding(PathDir::CW);
+        let windings: Vec<PathDir> = path.flatten(0.25).map(|path| path.winding).collect();
+        assert_eq!(windings, vec![PathDir::CCW, PathDir::CW]);
+    }
+}
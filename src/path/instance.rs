@@ -1,39 +1,124 @@
 use std::{
-    cell::RefCell,
-    ops::{Deref, DerefMut},
+    ops::{Deref, DerefMut, Range},
+    sync::Mutex,
 };
 
-use crate::{RendererDevice, Transform};
+use crate::{Color, Point, PooledBuffer, RendererDevice, Transform};
+
+/// One instance of a path drawn with [`crate::Context::draw_path`]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Instance {
+    pub xform: Transform,
+    /// Multiplies the color of the paint, including its alpha
+    pub color: Color,
+    /// Moves the gradient or image pattern of the paint, in window coordinates
+    pub paint_offset: Point,
+    /// Passed along to the fragment stage, unused by the built-in shaders
+    pub data: [f32; 2],
+}
+
+impl Instance {
+    pub fn new(xform: Transform) -> Self {
+        Self {
+            xform,
+            color: Color::rgb(1.0, 1.0, 1.0),
+            paint_offset: Point::default(),
+            data: [0.0; 2],
+        }
+    }
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self::new(Transform::identity())
+    }
+}
+
+impl From<Transform> for Instance {
+    fn from(xform: Transform) -> Self {
+        Self::new(xform)
+    }
+}
 
 pub(crate) struct InstancesInner<R: RendererDevice> {
-    pub(crate) vertex_buffer: Option<R::VertexBuffer>,
+    pub(crate) vertex_buffer: Option<PooledBuffer<R>>,
+    /// Number of instances the vertex buffer has room for
+    pub(crate) capacity: usize,
+    /// Instances changed since the last upload
+    pub(crate) dirty: Option<Range<usize>>,
 }
 
+/// Instances of a path, uploaded by [`crate::Context::update_instances`].
+/// Changes made through [`Instances::set`], [`Instances::push`] and
+/// [`Instances::range_mut`] upload only the changed range, any other mutable
+/// access to the vector uploads all of it.
 pub struct Instances<R: RendererDevice> {
-    pub(crate) transforms: Vec<Transform>,
-    pub(crate) inner: RefCell<InstancesInner<R>>,
+    pub(crate) instances: Vec<Instance>,
+    pub(crate) inner: Mutex<InstancesInner<R>>,
 }
 
 impl<R: RendererDevice> Instances<R> {
-    pub fn new(transforms: Vec<Transform>) -> Self {
+    pub fn new(instances: Vec<Instance>) -> Self {
         return Self {
-            transforms,
-            inner: RefCell::new(InstancesInner {
+            instances,
+            inner: Mutex::new(InstancesInner {
                 vertex_buffer: None,
+                capacity: 0,
+                dirty: Some(0..usize::MAX),
             }),
         };
     }
+
+    pub fn set(&mut self, index: usize, instance: Instance) {
+        self.instances[index] = instance;
+        self.mark_dirty(index..index + 1);
+    }
+
+    pub fn push(&mut self, instance: Instance) {
+        self.instances.push(instance);
+        let len = self.instances.len();
+        self.mark_dirty(len - 1..len);
+    }
+
+    pub fn range_mut(&mut self, range: Range<usize>) -> &mut [Instance] {
+        self.mark_dirty(range.clone());
+        &mut self.instances[range]
+    }
+
+    /// Uploaded buffer to draw the instances in `range` from, `None` when
+    /// there are no instances
+    pub(crate) fn buffer(&self, range: Range<u32>) -> Option<(R::VertexBuffer, Range<u32>)> {
+        if self.instances.is_empty() {
+            return None;
+        }
+        let inner = self.inner.lock().unwrap();
+        inner
+            .vertex_buffer
+            .as_ref()
+            .map(|pooled| (pooled.buffer.clone(), range))
+    }
+
+    fn mark_dirty(&mut self, range: Range<usize>) {
+        let dirty = &mut self.inner.get_mut().unwrap().dirty;
+        *dirty = Some(match dirty.take() {
+            Some(dirty) => dirty.start.min(range.start)..dirty.end.max(range.end),
+            None => range,
+        });
+    }
 }
 
 impl<R: RendererDevice> Deref for Instances<R> {
-    type Target = Vec<Transform>;
+    type Target = Vec<Instance>;
     fn deref(&self) -> &Self::Target {
-        return &self.transforms;
+        return &self.instances;
     }
 }
 
 impl<R: RendererDevice> DerefMut for Instances<R> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        return &mut self.transforms;
+        self.mark_dirty(0..usize::MAX);
+        return &mut self.instances;
     }
 }
@@ -0,0 +1,310 @@
+use super::cache::PathCache;
+use super::{PathCommands, DIST_TOL, TESS_TOL};
+use crate::{Point, Vector2D};
+
+/// Position and unit direction at some distance along a path
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PathSample {
+    pub position: Point,
+    pub tangent: Point,
+}
+
+#[derive(Debug, Clone)]
+struct Contour {
+    points: Vec<Point>,
+    /// Distance from the contour start to each point, closing point included
+    distances: Vec<f32>,
+    closed: bool,
+}
+
+impl Contour {
+    #[inline]
+    fn length(&self) -> f32 {
+        *self.distances.last().unwrap()
+    }
+
+    #[inline]
+    fn point(&self, idx: usize) -> Point {
+        self.points[idx % self.points.len()]
+    }
+
+    /// Segment containing `distance` and the position on it
+    fn locate(&self, distance: f32) -> (usize, Point) {
+        let seg = match self.distances[1..].iter().position(|&d| d > distance) {
+            Some(seg) => seg,
+            None => self.distances.len() - 2,
+        };
+        let p0 = self.point(seg);
+        let p1 = self.point(seg + 1);
+        let len = self.distances[seg + 1] - self.distances[seg];
+        let t = if len > 0.0 {
+            ((distance - self.distances[seg]) / len).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        (
+            seg,
+            Point::new(p0.x + (p1.x - p0.x) * t, p0.y + (p1.y - p0.y) * t),
+        )
+    }
+}
+
+/// Arc length parameterization of a path, from the same flattened polyline
+/// used for rendering. Sub-paths follow each other in drawing order and a
+/// closed sub-path includes its closing segment.
+#[derive(Debug, Clone)]
+pub struct PathMeasure {
+    contours: Vec<Contour>,
+    length: f32,
+}
+
+impl PathMeasure {
+    pub fn new(path: &PathCommands) -> PathMeasure {
+        let mut cache = PathCache::default();
+        cache.flatten_commands(&path.commands, DIST_TOL, TESS_TOL);
+
+        let mut contours = Vec::new();
+        let mut length = 0.0;
+        for info in &cache.paths {
+            let mut points: Vec<Point> = cache.path_points(info).collect();
+            let mut closed = info.closed;
+            if points.len() > 2 && points[0].equals(points[points.len() - 1], DIST_TOL) {
+                points.pop();
+                closed = true;
+            }
+            if points.len() < 2 {
+                continue;
+            }
+
+            let segments = if closed {
+                points.len()
+            } else {
+                points.len() - 1
+            };
+            let mut distances = Vec::with_capacity(segments + 1);
+            distances.push(0.0);
+            let mut total = 0.0;
+            for i in 0..segments {
+                let p0 = points[i];
+                let p1 = points[(i + 1) % points.len()];
+                total += (&p1 - &p0).length();
+                distances.push(total);
+            }
+            length += total;
+            contours.push(Contour {
+                points,
+                distances,
+                closed,
+            });
+        }
+        PathMeasure { contours, length }
+    }
+
+    #[inline]
+    pub fn length(&self) -> f32 {
+        self.length
+    }
+
+    /// Contour holding `distance`, along with the distance inside it
+    fn contour_at(&self, mut distance: f32) -> Option<(&Contour, f32)> {
+        let last = self.contours.len().checked_sub(1)?;
+        for (idx, contour) in self.contours.iter().enumerate() {
+            if distance <= contour.length() || idx == last {
+                return Some((contour, distance.min(contour.length())));
+            }
+            distance -= contour.length();
+        }
+        None
+    }
+
+    /// Sample the path at `distance`, clamped to the path length
+    pub fn sample(&self, distance: f32) -> Option<PathSample> {
+        let (contour, distance) = self.contour_at(distance.max(0.0))?;
+        let (seg, position) = contour.locate(distance);
+        let p0 = contour.point(seg);
+        let p1 = contour.point(seg + 1);
+        let mut tangent = &p1 - &p0;
+        tangent.normalize();
+        Some(PathSample { position, tangent })
+    }
+
+    /// Portion of the path between two distances as a polyline, one sub-path
+    /// per contour touched. A closed sub-path stays closed when fully covered.
+    pub fn trim(&self, start: f32, end: f32) -> PathCommands {
+        let mut result = PathCommands::default();
+        let mut offset = 0.0;
+        for contour in &self.contours {
+            let length = contour.length();
+            let from = (start - offset).max(0.0);
+            let to = (end - offset).min(length);
+            offset += length;
+            if from >= to {
+                continue;
+            }
+
+            if contour.closed && from <= 0.0 && to >= length {
+                result.move_to(contour.points[0]);
+                for pt in &contour.points[1..] {
+                    result.line_to(*pt);
+                }
+                result.close_path();
+                continue;
+            }
+
+            let (first, pt) = contour.locate(from);
+            let (last, end_pt) = contour.locate(to);
+            result.move_to(pt);
+            for idx in first + 1..=last {
+                result.line_to(contour.point(idx));
+            }
+            if to > contour.distances[last] {
+                result.line_to(end_pt);
+            }
+        }
+        result
+    }
+}
+
+impl PathCommands {
+    pub fn measure(&self) -> PathMeasure {
+        PathMeasure::new(self)
+    }
+
+    /// Total length of all sub-paths
+    pub fn length(&self) -> f32 {
+        self.measure().length()
+    }
+
+    /// Sub-path between two fractions of the total length
+    pub fn trim(&self, start: f32, end: f32) -> PathCommands {
+        let measure = self.measure();
+        measure.trim(start * measure.length(), end * measure.length())
+    }
+
+    /// Sub-path between two distances from the start
+    pub fn trim_length(&self, start: f32, end: f32) -> PathCommands {
+        self.measure().trim(start, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::{Command, PI};
+
+    fn line(x0: f32, y0: f32, x1: f32, y1: f32) -> PathCommands {
+        let mut path = PathCommands::default();
+        path.move_to((x0, y0));
+        path.line_to((x1, y1));
+        path
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{} instead of {}",
+            actual,
+            expected
+        );
+    }
+
+    fn assert_point(actual: Point, x: f32, y: f32) {
+        assert_near(actual.x, x);
+        assert_near(actual.y, y);
+    }
+
+    fn sub_paths(path: &PathCommands) -> usize {
+        path.commands
+            .iter()
+            .filter(|cmd| matches!(cmd, Command::MoveTo(_)))
+            .count()
+    }
+
+    fn closed(path: &PathCommands) -> bool {
+        path.commands
+            .iter()
+            .any(|cmd| matches!(cmd, Command::Close))
+    }
+
+    #[test]
+    fn line_samples() {
+        let measure = line(0.0, 0.0, 30.0, 40.0).measure();
+        assert_near(measure.length(), 50.0);
+
+        let sample = measure.sample(25.0).unwrap();
+        assert_point(sample.position, 15.0, 20.0);
+        assert_point(sample.tangent, 0.6, 0.8);
+
+        // distances are clamped to the path
+        assert_point(measure.sample(-10.0).unwrap().position, 0.0, 0.0);
+        assert_point(measure.sample(80.0).unwrap().position, 30.0, 40.0);
+    }
+
+    #[test]
+    fn closed_rect() {
+        let mut path = PathCommands::default();
+        path.rect((0.0, 0.0, 10.0, 20.0));
+        let measure = path.measure();
+        assert_near(measure.length(), 60.0);
+
+        // the closing segment is part of the length
+        let sample = measure.sample(55.0).unwrap();
+        assert_point(sample.position, 5.0, 0.0);
+        assert_point(sample.tangent, -1.0, 0.0);
+
+        let whole = measure.trim(0.0, 60.0);
+        assert!(closed(&whole));
+        assert_near(whole.length(), 60.0);
+
+        let part = measure.trim(10.0, 35.0);
+        assert!(!closed(&part));
+        assert_eq!(sub_paths(&part), 1);
+        assert_near(part.length(), 25.0);
+        assert_point(part.measure().sample(0.0).unwrap().position, 0.0, 10.0);
+        assert_point(part.measure().sample(25.0).unwrap().position, 10.0, 15.0);
+    }
+
+    #[test]
+    fn sub_paths_in_order() {
+        let mut path = line(0.0, 0.0, 10.0, 0.0);
+        path.move_to((0.0, 10.0));
+        path.line_to((20.0, 10.0));
+        let measure = path.measure();
+        assert_near(measure.length(), 30.0);
+        assert_point(measure.sample(15.0).unwrap().position, 5.0, 10.0);
+
+        let trimmed = measure.trim(5.0, 20.0);
+        assert_eq!(sub_paths(&trimmed), 2);
+        assert_near(trimmed.length(), 15.0);
+
+        let second = measure.trim(12.0, 30.0);
+        assert_eq!(sub_paths(&second), 1);
+        assert_near(second.length(), 18.0);
+    }
+
+    #[test]
+    fn fractions() {
+        let path = line(0.0, 0.0, 100.0, 0.0);
+        assert_near(path.trim(0.25, 0.5).length(), 25.0);
+        assert_near(path.trim_length(10.0, 30.0).length(), 20.0);
+        assert_eq!(sub_paths(&path.trim(0.5, 0.5)), 0);
+    }
+
+    #[test]
+    fn curve_length() {
+        let mut path = PathCommands::default();
+        path.circle((0.0, 0.0), 10.0);
+        let length = path.length();
+        // flattening keeps the polyline just inside the circle
+        assert!(length <= 2.0 * PI * 10.0 + 1e-3);
+        assert!(length > 2.0 * PI * 10.0 * 0.99);
+    }
+
+    #[test]
+    fn empty() {
+        let measure = PathCommands::default().measure();
+        assert_eq!(measure.length(), 0.0);
+        assert!(measure.sample(0.0).is_none());
+        assert_eq!(sub_paths(&measure.trim(0.0, 1.0)), 0);
+    }
+}
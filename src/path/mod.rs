@@ -1,18 +1,30 @@
-use crate::{RendererDevice, Transform};
-use cache::PathSlice;
+use crate::{Bounds, PooledBuffer, RendererDevice, Transform};
+use cache::{PathSlice, Vertex};
 use core::f32;
 use std::{
-    cell::RefCell,
     ops::{Deref, DerefMut},
+    sync::Mutex,
 };
 
+mod boolean;
 pub(crate) mod cache;
 mod commands;
+mod flatten;
 mod transform;
 mod instance;
+mod measure;
+mod stroke;
 
+pub use boolean::*;
 pub use commands::*;
+pub use flatten::*;
 pub use instance::*;
+pub use measure::*;
+
+/// Flattening tolerances used when path geometry is computed outside a context,
+/// the same values a context uses at a device pixel ratio of 1
+pub(crate) const TESS_TOL: f32 = 0.25;
+pub(crate) const DIST_TOL: f32 = 0.01;
 
 bitflags! {
     pub struct DrawPathStyle: u32 {
@@ -29,6 +41,8 @@ pub(crate) struct DrawSlice {
     pub bounds_offset: Option<usize>,
     pub stroke: Vec<PathSlice>,
     pub lines: Vec<PathSlice>,
+    /// Bounds of the vertices of all slices
+    pub bounds: Option<Bounds>,
 }
 
 impl DrawSlice {
@@ -44,12 +58,15 @@ impl DrawSlice {
         self.bounds_offset = None;
         self.stroke.clear();
         self.lines.clear();
+        self.bounds = None;
     }
 }
 
 pub(crate) struct PathMutInner<R: RendererDevice> {
     pub draw_slice: DrawSlice,
-    pub vertex_buffer: Option<R::VertexBuffer>,
+    /// Tessellated vertices waiting to be uploaded to `vertex_buffer`
+    pub vertices: Vec<Vertex>,
+    pub vertex_buffer: Option<PooledBuffer<R>>,
     pub style: DrawPathStyle,
 }
 
@@ -57,23 +74,28 @@ impl<R: RendererDevice> Default for PathMutInner<R> {
     fn default() -> Self {
         PathMutInner {
             draw_slice: DrawSlice::default(),
+            vertices: Vec::new(),
             vertex_buffer: None,
             style: DrawPathStyle::empty(),
         }
     }
 }
 
+/// Path drawn with [`crate::Context::draw_path`], keeping its tessellation
+/// between frames. Paths are `Send` and `Sync` when the vertex buffers of the
+/// renderer are `Send`, so they can be tessellated on worker threads with
+/// [`Path::tessellate`].
 #[allow(unused)]
 pub struct Path<R: RendererDevice> {
     pub(crate) path_comands: PathCommands,
-    pub(crate) inner: RefCell<PathMutInner<R>>,
+    pub(crate) inner: Mutex<PathMutInner<R>>,
 }
 
 impl<R: RendererDevice> Clone for Path<R> {
     fn clone(&self) -> Self {
         return Path {
             path_comands: self.path_comands.clone(),
-            inner: RefCell::new(PathMutInner::default()),
+            inner: Mutex::new(PathMutInner::default()),
         };
     }
 }
@@ -82,7 +104,7 @@ impl<R: RendererDevice> From<PathCommands> for Path<R> {
     fn from(path: PathCommands) -> Self {
         return Self {
             path_comands: path,
-            inner: RefCell::new(PathMutInner::default()),
+            inner: Mutex::new(PathMutInner::default()),
         };
     }
 }
@@ -92,15 +114,12 @@ impl<R: RendererDevice> Path<R> {
         return Self::from(PathCommands::default());
     }
 
-    pub(crate) fn inner_cached_style(&self) -> DrawPathStyle {
-        return self.inner.borrow().style;
-    }
-
     pub fn reset(&mut self) -> &mut PathCommands {
         {
-            let mut inner = self.inner.borrow_mut();
+            let inner = self.inner.get_mut().unwrap();
             inner.style = DrawPathStyle::empty();
             inner.draw_slice.clear();
+            inner.vertices.clear();
             self.path_comands.clear();
         }
         &mut self.path_comands
@@ -116,7 +135,7 @@ impl<R: RendererDevice> Deref for Path<R> {
 
 impl<R: RendererDevice> DerefMut for Path<R> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        if self.inner.borrow().draw_slice.is_none() {
+        if self.inner.get_mut().unwrap().draw_slice.is_none() {
             return &mut self.path_comands;
         }
         panic!("Path must be reset before Updating")
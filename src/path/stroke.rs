@@ -0,0 +1,434 @@
+use super::boolean::{boolean_polygons, cross, ring_area, Operand};
+use super::cache::{curve_divs, PathCache};
+use super::{BooleanOp, PathCommands, PathFillType, WindingSolidity, DIST_TOL, TESS_TOL};
+use crate::{Bounds, LineCap, LineJoin, Point, Vector2D};
+use std::f32::consts::PI;
+
+impl PathCommands {
+    /// Outline of the stroke this path would draw with the given style, as a
+    /// fillable path. Joins and caps match rendering, curves are flattened.
+    pub fn stroke_to_path(
+        &self,
+        width: f32,
+        cap: LineCap,
+        join: LineJoin,
+        miter_limit: f32,
+    ) -> PathCommands {
+        let mut cache = PathCache::default();
+        cache.flatten_paths(&self.commands, DIST_TOL, TESS_TOL);
+
+        let mut stroker = Stroker {
+            w: width * 0.5,
+            cap,
+            join,
+            miter_limit,
+            overlaps: false,
+        };
+        let mut outlines: Vec<Outline> = cache
+            .paths
+            .iter()
+            .filter(|info| info.count > 1)
+            .map(|info| {
+                let points: Vec<Point> = cache.path_points(info).collect();
+                stroker.outline(&points, info.closed)
+            })
+            .collect();
+
+        // outlines of different sub-paths may only overlap when their bounds do
+        for i in 0..outlines.len() {
+            for j in i + 1..outlines.len() {
+                if outlines[i].bounds.intersects(&outlines[j].bounds) {
+                    outlines[i].overlaps = true;
+                    outlines[j].overlaps = true;
+                }
+            }
+        }
+
+        let mut result = PathCommands::default();
+        let mut overlapping = Vec::new();
+        for outline in outlines {
+            if outline.overlaps {
+                overlapping.extend(outline.rings);
+                continue;
+            }
+            for ring in outline.rings {
+                let area = ring_area(&ring);
+                result.move_to(ring[0]);
+                for pt in &ring[1..] {
+                    result.line_to(*pt);
+                }
+                result.close_path();
+                result.path_winding(if area > 0.0 {
+                    WindingSolidity::Solid
+                } else {
+                    WindingSolidity::Hole
+                });
+            }
+        }
+        if overlapping.is_empty() {
+            return result;
+        }
+
+        // the overlapping outlines all wind the same way, so their union under
+        // the non-zero rule is the area the stroke covers
+        let operands = [
+            Operand {
+                rings: overlapping,
+                fill_type: PathFillType::Winding,
+            },
+            Operand {
+                rings: Vec::new(),
+                fill_type: PathFillType::Winding,
+            },
+        ];
+        let merged = boolean_polygons(&operands, BooleanOp::Union, DIST_TOL);
+        result.commands.extend(merged.commands);
+        result
+    }
+}
+
+/// Rings bounding the stroke of one sub-path, wound so that their signed
+/// areas add up to the area of the stroke
+struct Outline {
+    rings: Vec<Vec<Point>>,
+    bounds: Bounds,
+    /// The rings cross themselves or each other
+    overlaps: bool,
+}
+
+struct Stroker {
+    /// Half of the stroke width
+    w: f32,
+    cap: LineCap,
+    join: LineJoin,
+    miter_limit: f32,
+    /// Set when an inner join or a crossing loops the outline over itself
+    overlaps: bool,
+}
+
+/// Left normal of a unit direction, like the one rendering offsets by
+#[inline]
+fn normal(d: Point) -> Point {
+    Point::new(d.y, -d.x)
+}
+
+#[inline]
+fn offset(p: Point, n: Point, w: f32) -> Point {
+    Point::new(p.x + n.x * w, p.y + n.y * w)
+}
+
+impl Stroker {
+    fn outline(&mut self, points: &[Point], closed: bool) -> Outline {
+        let count = points.len();
+        let segments: Vec<(Point, f32)> = (0..if closed { count } else { count - 1 })
+            .map(|i| {
+                let mut d = &points[(i + 1) % count] - &points[i];
+                let len = d.normalize();
+                (d, len)
+            })
+            .collect();
+
+        self.overlaps = false;
+        let mut rings = if closed {
+            let mut left = Vec::new();
+            let mut right = Vec::new();
+            for (i, &p) in points.iter().enumerate() {
+                let prev = segments[(i + count - 1) % count];
+                self.join(&mut left, p, prev, segments[i], 1.0);
+                self.join(&mut right, p, prev, segments[i], -1.0);
+            }
+            right.reverse();
+            vec![left, right]
+        } else {
+            // the start cap closes the ring back to the left side
+            let mut ring = Vec::new();
+            for i in 1..count - 1 {
+                self.join(&mut ring, points[i], segments[i - 1], segments[i], 1.0);
+            }
+            self.cap(&mut ring, points[count - 1], segments[count - 2].0);
+            let mut right = Vec::new();
+            for i in 1..count - 1 {
+                self.join(&mut right, points[i], segments[i - 1], segments[i], -1.0);
+            }
+            right.reverse();
+            ring.extend(right);
+            let first = segments[0].0;
+            self.cap(&mut ring, points[0], Point::new(-first.x, -first.y));
+            vec![ring]
+        };
+
+        if rings.iter().map(|ring| ring_area(ring)).sum::<f32>() < 0.0 {
+            for ring in &mut rings {
+                ring.reverse();
+            }
+        }
+        let overlaps = self.overlaps || self_intersects(&rings, DIST_TOL);
+        let mut bounds = Bounds {
+            min: Point::new(f32::MAX, f32::MAX),
+            max: Point::new(f32::MIN, f32::MIN),
+        };
+        for pt in rings.iter().flatten() {
+            bounds.min = Point::new(bounds.min.x.min(pt.x), bounds.min.y.min(pt.y));
+            bounds.max = Point::new(bounds.max.x.max(pt.x), bounds.max.y.max(pt.y));
+        }
+        Outline {
+            rings,
+            bounds,
+            overlaps,
+        }
+    }
+
+    /// Offset points at `p` on the side `side` of the stroke, turning from the
+    /// direction and length of one segment to the next
+    fn join(
+        &mut self,
+        out: &mut Vec<Point>,
+        p: Point,
+        (d0, len0): (Point, f32),
+        (d1, len1): (Point, f32),
+        side: f32,
+    ) {
+        let w = self.w * side;
+        let (n0, n1) = (normal(d0), normal(d1));
+        let turn = cross(d0, d1);
+        let dot = d0.dot(&d1);
+        if turn.abs() < 1e-6 && dot > 0.0 {
+            out.push(offset(p, n1, w));
+            return;
+        }
+        // a reversal is joined around the front on the left side
+        let reversal = turn.abs() < 1e-6;
+        let outer = if reversal {
+            side > 0.0
+        } else {
+            turn * side > 0.0
+        };
+
+        let dm = Point::new((n0.x + n1.x) * 0.5, (n0.y + n1.y) * 0.5);
+        let dmr2 = dm.dot(&dm);
+        let miter = Point::new(dm.x / dmr2, dm.y / dmr2);
+        if !outer {
+            // the offset lines cross near the corner unless segments are too
+            // short for it, then the outline pivots around the corner
+            let along = self.w * turn.abs() / (1.0 + dot);
+            if !reversal && dmr2 > 1e-6 && along <= 0.5 * len0.min(len1) {
+                out.push(offset(p, miter, w));
+            } else {
+                out.extend([offset(p, n0, w), p, offset(p, n1, w)]);
+                self.overlaps = true;
+            }
+            return;
+        }
+
+        match self.join {
+            LineJoin::Miter if !reversal && dmr2 * self.miter_limit * self.miter_limit >= 1.0 => {
+                out.push(offset(p, miter, w));
+            }
+            LineJoin::Round => {
+                let angle = if reversal { PI } else { turn.atan2(dot) };
+                let start = n0.y.atan2(n0.x) + if side < 0.0 { PI } else { 0.0 };
+                let divs = curve_divs(self.w, angle.abs(), TESS_TOL);
+                for i in 0..=divs {
+                    let a = start + angle * i as f32 / divs as f32;
+                    out.push(offset(p, Point::new(a.cos(), a.sin()), self.w));
+                }
+            }
+            _ => out.extend([offset(p, n0, w), offset(p, n1, w)]),
+        }
+    }
+
+    /// Cap at the end `p` of a sub-path heading in direction `d`, from its
+    /// left to its right side
+    fn cap(&self, out: &mut Vec<Point>, p: Point, d: Point) {
+        let w = self.w;
+        let n = normal(d);
+        match self.cap {
+            LineCap::Butt => out.extend([offset(p, n, w), offset(p, n, -w)]),
+            LineCap::Square => {
+                let front = offset(p, d, w);
+                out.extend([offset(front, n, w), offset(front, n, -w)]);
+            }
+            LineCap::Round => {
+                let divs = curve_divs(w, PI, TESS_TOL);
+                for i in 0..=divs {
+                    let a = PI * i as f32 / divs as f32;
+                    let dir =
+                        Point::new(n.x * a.cos() + d.x * a.sin(), n.y * a.cos() + d.y * a.sin());
+                    out.push(offset(p, dir, w));
+                }
+            }
+        }
+    }
+}
+
+/// Whether segments of the rings that don't follow each other cross or touch
+fn self_intersects(rings: &[Vec<Point>], eps: f32) -> bool {
+    let mut segments = Vec::new();
+    for (r, ring) in rings.iter().enumerate() {
+        for i in 0..ring.len() {
+            segments.push((r, i, ring[i], ring[(i + 1) % ring.len()]));
+        }
+    }
+    segments.sort_by(|a, b| a.2.x.min(a.3.x).partial_cmp(&b.2.x.min(b.3.x)).unwrap());
+
+    let adjacent = |a: &(usize, usize, Point, Point), b: &(usize, usize, Point, Point)| {
+        let len = rings[a.0].len();
+        a.0 == b.0 && (a.1 == b.1 || (a.1 + 1) % len == b.1 || (b.1 + 1) % len == a.1)
+    };
+    for (n, s0) in segments.iter().enumerate() {
+        let max_x = s0.2.x.max(s0.3.x) + eps;
+        for s1 in segments[n + 1..]
+            .iter()
+            .take_while(|s1| s1.2.x.min(s1.3.x) <= max_x)
+        {
+            if !adjacent(s0, s1) && segments_meet(s0.2, s0.3, s1.2, s1.3, eps) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn segments_meet(a: Point, b: Point, c: Point, d: Point, eps: f32) -> bool {
+    let o1 = cross(&b - &a, &c - &a);
+    let o2 = cross(&b - &a, &d - &a);
+    let o3 = cross(&d - &c, &a - &c);
+    let o4 = cross(&d - &c, &b - &c);
+    if o1 * o2 < 0.0 && o3 * o4 < 0.0 {
+        return true;
+    }
+    let eps2 = eps * eps;
+    c.dist_pt_seg(a, b) < eps2
+        || d.dist_pt_seg(a, b) < eps2
+        || a.dist_pt_seg(c, d) < eps2
+        || b.dist_pt_seg(c, d) < eps2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::PI;
+
+    fn area(path: &PathCommands) -> f32 {
+        let operand = Operand::new(path, TESS_TOL);
+        operand
+            .rings
+            .iter()
+            .map(|ring| ring_area(ring))
+            .sum::<f32>()
+            .abs()
+    }
+
+    fn assert_area(path: &PathCommands, expected: f32, tolerance: f32) {
+        let actual = area(path);
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "area {} instead of {}",
+            actual,
+            expected
+        );
+    }
+
+    fn line() -> PathCommands {
+        let mut path = PathCommands::default();
+        path.move_to((0.0, 0.0));
+        path.line_to((100.0, 0.0));
+        path
+    }
+
+    #[test]
+    fn caps() {
+        let path = line();
+        let butt = path.stroke_to_path(10.0, LineCap::Butt, LineJoin::Miter, 10.0);
+        assert_area(&butt, 1000.0, 1e-2);
+        let square = path.stroke_to_path(10.0, LineCap::Square, LineJoin::Miter, 10.0);
+        assert_area(&square, 1100.0, 1e-2);
+        let round = path.stroke_to_path(10.0, LineCap::Round, LineJoin::Miter, 10.0);
+        // the half circles are flattened to polygons inside of them
+        let round = area(&round) - 1000.0;
+        assert!(round < PI * 25.0 && round > PI * 25.0 * 0.9, "{}", round);
+    }
+
+    #[test]
+    fn closed_outline() {
+        let mut path = PathCommands::default();
+        path.rect((0.0, 0.0, 20.0, 20.0));
+        let outline = path.stroke_to_path(2.0, LineCap::Butt, LineJoin::Miter, 10.0);
+        // a ring between the grown and the shrunk square
+        assert_area(&outline, 22.0 * 22.0 - 18.0 * 18.0, 1e-2);
+        assert_eq!(Operand::new(&outline, TESS_TOL).rings.len(), 2);
+
+        let bevel = path.stroke_to_path(2.0, LineCap::Butt, LineJoin::Bevel, 10.0);
+        // each outer corner loses half of the miter square
+        assert_area(&bevel, 22.0 * 22.0 - 18.0 * 18.0 - 4.0 * 0.5, 1e-2);
+    }
+
+    #[test]
+    fn self_overlap_merges() {
+        // back and forth over the same segment
+        let mut path = line();
+        path.line_to((0.0, 0.0));
+        let outline = path.stroke_to_path(10.0, LineCap::Butt, LineJoin::Bevel, 10.0);
+        assert_area(&outline, 1000.0, 1.0);
+    }
+
+    #[test]
+    fn degenerate() {
+        let mut path = PathCommands::default();
+        path.move_to((10.0, 10.0));
+        let outline = path.stroke_to_path(4.0, LineCap::Round, LineJoin::Round, 10.0);
+        assert!(outline.commands.is_empty());
+    }
+
+    #[test]
+    fn joins() {
+        let mut path = line();
+        path.line_to((100.0, 100.0));
+        let rings = |outline: &PathCommands| Operand::new(outline, TESS_TOL).rings.len();
+
+        let miter = path.stroke_to_path(10.0, LineCap::Butt, LineJoin::Miter, 10.0);
+        assert_area(&miter, 2000.0, 1e-2);
+        assert_eq!(rings(&miter), 1);
+        let bevel = path.stroke_to_path(10.0, LineCap::Butt, LineJoin::Bevel, 10.0);
+        assert_area(&bevel, 1987.5, 1e-2);
+        // past the miter limit the corner is beveled
+        let limited = path.stroke_to_path(10.0, LineCap::Butt, LineJoin::Miter, 1.2);
+        assert_area(&limited, 1987.5, 1e-2);
+        let round = area(&path.stroke_to_path(10.0, LineCap::Butt, LineJoin::Round, 10.0));
+        assert!(
+            round > 1987.5 && round < 1975.0 + PI * 25.0 / 4.0,
+            "{}",
+            round
+        );
+    }
+
+    #[test]
+    fn curve_follows_path() {
+        let mut path = PathCommands::default();
+        path.move_to((0.0, 0.0));
+        path.bezier_to((30.0, 60.0), (70.0, 60.0), (100.0, 0.0));
+        let outline = path.stroke_to_path(4.0, LineCap::Butt, LineJoin::Miter, 10.0);
+        assert_eq!(Operand::new(&outline, TESS_TOL).rings.len(), 1);
+        assert_area(&outline, path.length() * 4.0, path.length() * 4.0 * 0.01);
+    }
+
+    #[test]
+    fn crossing_sub_paths() {
+        let mut path = line();
+        path.move_to((50.0, -50.0));
+        path.line_to((50.0, 50.0));
+        let outline = path.stroke_to_path(10.0, LineCap::Butt, LineJoin::Miter, 10.0);
+        assert_area(&outline, 1900.0, 1e-2);
+        assert_eq!(Operand::new(&outline, TESS_TOL).rings.len(), 1);
+    }
+
+    #[test]
+    fn wider_than_curvature() {
+        // the inner side of the stroke collapses, covering the whole disc
+        let mut path = PathCommands::default();
+        path.circle((0.0, 0.0), 5.0);
+        let outline = path.stroke_to_path(20.0, LineCap::Butt, LineJoin::Miter, 10.0);
+        assert_eq!(Operand::new(&outline, TESS_TOL).rings.len(), 1);
+        assert_area(&outline, PI * 15.0 * 15.0, PI * 15.0 * 15.0 * 0.02);
+    }
+}
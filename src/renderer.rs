@@ -6,6 +6,7 @@ pub use crate::path::cache::{PathSlice, Vertex, VertexSlice};
 pub use crate::*;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TextureType {
     #[default]
     RGBA,
@@ -20,6 +21,45 @@ pub enum BufferUsage {
     Instance,
 }
 
+/// Diagnostic views of a renderer, selected at runtime
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum DebugView {
+    #[default]
+    None,
+    /// Adds up the fragments drawn to every pixel, going from black over red
+    /// and yellow to white
+    Overdraw,
+    /// Outlines the triangles of fills and strokes over the frame
+    Wireframe,
+    /// Highlights the antialiasing fringes of fills over the frame
+    Fringe,
+}
+
+impl DebugView {
+    /// Premultiplied color of the view, added once per fragment for overdraw
+    pub fn color(self) -> Color {
+        match self {
+            DebugView::None => Color::rgba(0.0, 0.0, 0.0, 0.0),
+            DebugView::Overdraw => Color::rgba(0.25, 0.1, 0.04, 1.0),
+            DebugView::Wireframe => Color::rgba(0.0, 1.0, 0.0, 1.0),
+            DebugView::Fringe => Color::rgba(1.0, 0.0, 1.0, 1.0),
+        }
+    }
+
+    /// Blending of the view's color, additive for overdraw
+    pub fn blend(self) -> CompositeOperationState {
+        match self {
+            DebugView::Overdraw => CompositeOperationState {
+                src_rgb: BlendFactor::One,
+                dst_rgb: BlendFactor::One,
+                src_alpha: BlendFactor::One,
+                dst_alpha: BlendFactor::One,
+            },
+            _ => CompositeOperationState::default(),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Scissor {
     pub xform: Transform,
@@ -30,7 +70,7 @@ pub trait RendererDevice {
     type VertexBuffer: Clone;
     fn edge_antialias(&self) -> bool;
 
-    fn resize(&mut self, _width: u32, _height: u32) -> anyhow::Result<()> {
+    fn resize(&mut self, _width: u32, _height: u32) -> crate::Result<()> {
         Ok(())
     }
 
@@ -38,13 +78,39 @@ pub trait RendererDevice {
         &mut self,
         buffer_size: usize,
         usage: BufferUsage,
-    ) -> anyhow::Result<Self::VertexBuffer>;
+    ) -> crate::Result<Self::VertexBuffer>;
 
     fn update_vertex_buffer(
         &mut self,
         buffer: Option<&Self::VertexBuffer>,
         vertices: &[u8],
-    ) -> anyhow::Result<()>;
+    ) -> crate::Result<()>;
+
+    /// Uploads the bytes `range` of `data`, the new contents of `buffer`,
+    /// keeping the rest of the buffer. Fails with [`Error::BufferRange`] when
+    /// they do not fit into the buffer. Uploads all of `data` by default.
+    fn update_vertex_buffer_range(
+        &mut self,
+        buffer: &Self::VertexBuffer,
+        data: &[u8],
+        _range: Range<usize>,
+    ) -> crate::Result<()> {
+        self.update_vertex_buffer(Some(buffer), data)
+    }
+
+    /// Bytes held by `buffer`, when the renderer knows them. Renderers may
+    /// allocate more than a buffer is created with, or reallocate it when it
+    /// is updated.
+    fn vertex_buffer_size(&self, _buffer: &Self::VertexBuffer) -> Option<usize> {
+        None
+    }
+
+    /// Releases `buffer`, once clones of it held for pending draw calls are
+    /// dropped as well. Dropping it does by default.
+    fn delete_vertex_buffer(&mut self, buffer: Self::VertexBuffer) -> crate::Result<()> {
+        drop(buffer);
+        Ok(())
+    }
 
     fn create_texture(
         &mut self,
@@ -53,9 +119,9 @@ pub trait RendererDevice {
         height: u32,
         flags: ImageFlags,
         data: Option<&[u8]>,
-    ) -> anyhow::Result<ImageId>;
+    ) -> crate::Result<ImageId>;
 
-    fn delete_texture(&mut self, img: ImageId) -> anyhow::Result<()>;
+    fn delete_texture(&mut self, img: ImageId) -> crate::Result<()>;
 
     fn update_texture(
         &mut self,
@@ -65,15 +131,37 @@ pub trait RendererDevice {
         width: u32,
         height: u32,
         data: &[u8],
-    ) -> anyhow::Result<()>;
+    ) -> crate::Result<()>;
+
+    fn texture_size(&self, img: ImageId) -> crate::Result<(u32, u32)>;
 
-    fn texture_size(&self, img: ImageId) -> anyhow::Result<(u32, u32)>;
+    fn viewport(&mut self, extent: Extent, device_pixel_ratio: f32) -> crate::Result<()>;
 
-    fn viewport(&mut self, extent: Extent, device_pixel_ratio: f32) -> anyhow::Result<()>;
+    /// Restricts the frames drawn without a bound framebuffer to `area`, in
+    /// window coordinates aligned to device pixels, keeping the previous frame
+    /// outside of it. Renderers scale it by the device pixel ratio of the
+    /// latest [`RendererDevice::viewport`]. Returns false when the previous
+    /// frame can't be kept, the frame is then drawn in full. `None` ends
+    /// partial drawing.
+    fn damage(&mut self, _area: Option<Bounds>) -> crate::Result<bool> {
+        Ok(false)
+    }
+
+    fn cancel(&mut self) -> crate::Result<()>;
 
-    fn cancel(&mut self) -> anyhow::Result<()>;
+    fn flush(&mut self) -> crate::Result<()>;
 
-    fn flush(&mut self) -> anyhow::Result<()>;
+    /// GPU time of the latest flushed frame whose timing is known, when the
+    /// renderer measures it
+    fn gpu_time(&mut self) -> Option<std::time::Duration> {
+        None
+    }
+
+    /// Draw calls of the latest flushed frame, after merging the calls the
+    /// renderer batches, when the renderer counts them
+    fn flushed_calls(&self) -> Option<usize> {
+        None
+    }
 
     fn fill(
         &mut self,
@@ -86,7 +174,7 @@ pub trait RendererDevice {
         fringe: f32,
         bounds_offset: Option<usize>,
         paths: &[PathSlice],
-    ) -> anyhow::Result<()>;
+    ) -> crate::Result<()>;
 
     fn stroke(
         &mut self,
@@ -98,7 +186,7 @@ pub trait RendererDevice {
         fringe: f32,
         stroke_width: f32,
         paths: &[PathSlice],
-    ) -> anyhow::Result<()>;
+    ) -> crate::Result<()>;
 
     fn triangles(
         &mut self,
@@ -108,7 +196,29 @@ pub trait RendererDevice {
         composite_operation: CompositeOperationState,
         scissor: &Scissor,
         slice: VertexSlice,
-    ) -> anyhow::Result<()>;
+    ) -> crate::Result<()>;
+
+    /// Draws LCD text. `paint.image` holds glyph coverage at three texels per
+    /// pixel horizontally, which is blended per color channel with
+    /// `paint.inner_color`. Renderers without per-channel blending draw it
+    /// as grayscale triangles.
+    fn lcd_triangles(
+        &mut self,
+        vertex_buffer: Option<Self::VertexBuffer>,
+        instances: Option<(Self::VertexBuffer, Range<u32>)>,
+        paint: &PaintPattern,
+        scissor: &Scissor,
+        slice: VertexSlice,
+    ) -> crate::Result<()> {
+        self.triangles(
+            vertex_buffer,
+            instances,
+            paint,
+            CompositeOperation::Basic(BasicCompositeOperation::SrcOver).into(),
+            scissor,
+            slice,
+        )
+    }
 
     #[cfg(feature = "wirelines")]
     fn wirelines(
@@ -119,9 +229,9 @@ pub trait RendererDevice {
         composite_operation: CompositeOperationState,
         scissor: &Scissor,
         paths: &[PathSlice],
-    ) -> anyhow::Result<()>;
+    ) -> crate::Result<()>;
 
-    fn clear(&mut self, color: Color) -> anyhow::Result<()>;
+    fn clear(&mut self, color: Color) -> crate::Result<()>;
 }
 
 pub trait FrameBufferDevice {
@@ -132,9 +242,221 @@ pub trait FrameBufferDevice {
 pub trait RenderFrameBufferDevice: RendererDevice {
     type FB: FrameBufferDevice;
     fn fb_format(&self) -> TextureType;
-    fn create_fb(&mut self, width: u32, height: u32, image: ImageId) -> anyhow::Result<Self::FB>;
-    fn delete_fb(&mut self, fb: Self::FB) -> anyhow::Result<()>;
-    fn bind(&mut self, fb: &Self::FB) -> anyhow::Result<()>;
-    fn unbind(&mut self) -> anyhow::Result<()>;
+    fn create_fb(&mut self, width: u32, height: u32, image: ImageId) -> crate::Result<Self::FB>;
+    fn delete_fb(&mut self, fb: Self::FB) -> crate::Result<()>;
+    fn bind(&mut self, fb: &Self::FB) -> crate::Result<()>;
+    fn unbind(&mut self) -> crate::Result<()>;
 }
 
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A call made to a [`NullRenderer`], with the paint it was drawn with
+    #[derive(Debug, Clone)]
+    pub(crate) enum Call {
+        Clear(Color),
+        Fill(PaintPattern),
+        Stroke(PaintPattern),
+        Triangles(PaintPattern),
+    }
+
+    /// Renderer without a device, which keeps the sizes of the buffers and
+    /// textures and the calls made to it
+    #[derive(Default)]
+    pub(crate) struct NullRenderer {
+        /// Sizes of the vertex buffers by index, `None` once deleted
+        pub(crate) buffers: Vec<Option<usize>>,
+        /// Multiple the sizes of vertex buffers are rounded up to, if any
+        pub(crate) buffer_alignment: usize,
+        /// Byte ranges written to the vertex buffers
+        pub(crate) uploads: Vec<(usize, Range<usize>)>,
+        /// Texture sizes by id less one, `None` once deleted
+        textures: Vec<Option<(u32, u32)>>,
+        pub(crate) calls: Vec<Call>,
+    }
+
+    impl NullRenderer {
+        fn upload(&mut self, buffer: usize, range: Range<usize>) -> crate::Result<()> {
+            let size = self.buffers[buffer].unwrap_or(0);
+            if range.end > size {
+                return Err(Error::BufferRange {
+                    offset: range.start,
+                    len: range.len(),
+                    size,
+                });
+            }
+            self.uploads.push((buffer, range));
+            Ok(())
+        }
+    }
+
+    impl RendererDevice for NullRenderer {
+        type VertexBuffer = usize;
+
+        fn edge_antialias(&self) -> bool {
+            true
+        }
+
+        fn create_vertex_buffer(&mut self, size: usize, _: BufferUsage) -> crate::Result<usize> {
+            let alignment = self.buffer_alignment.max(1);
+            self.buffers
+                .push(Some(size.div_ceil(alignment) * alignment));
+            Ok(self.buffers.len() - 1)
+        }
+
+        fn update_vertex_buffer(
+            &mut self,
+            buffer: Option<&usize>,
+            data: &[u8],
+        ) -> crate::Result<()> {
+            match buffer {
+                Some(buffer) => self.upload(*buffer, 0..data.len()),
+                None => Ok(()),
+            }
+        }
+
+        fn update_vertex_buffer_range(
+            &mut self,
+            buffer: &usize,
+            _: &[u8],
+            range: Range<usize>,
+        ) -> crate::Result<()> {
+            self.upload(*buffer, range)
+        }
+
+        fn vertex_buffer_size(&self, buffer: &usize) -> Option<usize> {
+            self.buffers[*buffer]
+        }
+
+        fn delete_vertex_buffer(&mut self, buffer: usize) -> crate::Result<()> {
+            assert!(self.buffers[buffer].take().is_some(), "deleted twice");
+            Ok(())
+        }
+
+        /// Reuses the lowest id deleted before, like GL does
+        fn create_texture(
+            &mut self,
+            _: TextureType,
+            width: u32,
+            height: u32,
+            _: ImageFlags,
+            _: Option<&[u8]>,
+        ) -> crate::Result<ImageId> {
+            let size = Some((width, height));
+            match self.textures.iter().position(Option::is_none) {
+                Some(index) => {
+                    self.textures[index] = size;
+                    Ok(index + 1)
+                }
+                None => {
+                    self.textures.push(size);
+                    Ok(self.textures.len())
+                }
+            }
+        }
+
+        fn delete_texture(&mut self, img: ImageId) -> crate::Result<()> {
+            match self.textures.get_mut(img.wrapping_sub(1)) {
+                Some(size @ Some(_)) => {
+                    *size = None;
+                    Ok(())
+                }
+                _ => Err(Error::InvalidImage(img)),
+            }
+        }
+
+        fn update_texture(
+            &mut self,
+            _: ImageId,
+            _: u32,
+            _: u32,
+            _: u32,
+            _: u32,
+            _: &[u8],
+        ) -> crate::Result<()> {
+            Ok(())
+        }
+
+        fn texture_size(&self, img: ImageId) -> crate::Result<(u32, u32)> {
+            match self.textures.get(img.wrapping_sub(1)) {
+                Some(Some(size)) => Ok(*size),
+                _ => Err(Error::InvalidImage(img)),
+            }
+        }
+
+        fn viewport(&mut self, _: Extent, _: f32) -> crate::Result<()> {
+            Ok(())
+        }
+
+        fn cancel(&mut self) -> crate::Result<()> {
+            Ok(())
+        }
+
+        fn flush(&mut self) -> crate::Result<()> {
+            Ok(())
+        }
+
+        fn fill(
+            &mut self,
+            _: Option<usize>,
+            _: Option<(usize, Range<u32>)>,
+            paint: &PaintPattern,
+            _: CompositeOperationState,
+            _: PathFillType,
+            _: &Scissor,
+            _: f32,
+            _: Option<usize>,
+            _: &[PathSlice],
+        ) -> crate::Result<()> {
+            self.calls.push(Call::Fill(*paint));
+            Ok(())
+        }
+
+        fn stroke(
+            &mut self,
+            _: Option<usize>,
+            _: Option<(usize, Range<u32>)>,
+            paint: &PaintPattern,
+            _: CompositeOperationState,
+            _: &Scissor,
+            _: f32,
+            _: f32,
+            _: &[PathSlice],
+        ) -> crate::Result<()> {
+            self.calls.push(Call::Stroke(*paint));
+            Ok(())
+        }
+
+        fn triangles(
+            &mut self,
+            _: Option<usize>,
+            _: Option<(usize, Range<u32>)>,
+            paint: &PaintPattern,
+            _: CompositeOperationState,
+            _: &Scissor,
+            _: VertexSlice,
+        ) -> crate::Result<()> {
+            self.calls.push(Call::Triangles(*paint));
+            Ok(())
+        }
+
+        #[cfg(feature = "wirelines")]
+        fn wirelines(
+            &mut self,
+            _: Option<usize>,
+            _: Option<(usize, Range<u32>)>,
+            _: &PaintPattern,
+            _: CompositeOperationState,
+            _: &Scissor,
+            _: &[PathSlice],
+        ) -> crate::Result<()> {
+            Ok(())
+        }
+
+        fn clear(&mut self, color: Color) -> crate::Result<()> {
+            self.calls.push(Call::Clear(color));
+            Ok(())
+        }
+    }
+}